@@ -0,0 +1,68 @@
+// Force evaluation cost at increasing body counts. Only the direct O(n^2)
+// kernel exists so far; other backends should be added to `force_backends` as
+// they land so they are measured on the same inputs.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use smolnbody::resources::Gravity;
+use smolnbody::scenarios;
+use smolnbody::systems::ApplyGravity;
+use smolnbody::{Simulation, SimulationBuilder};
+use SmolECS::system::{System, SystemData};
+use SmolECS::world::World;
+
+fn builder(n: u32) -> SimulationBuilder {
+    let mut rng = ChaCha8Rng::seed_from_u64(42);
+    SimulationBuilder::new(scenarios::random(n, 1, &mut rng))
+        .gravity(Gravity::default())
+        .timestep(Some(1.0e-3))
+}
+
+fn simulation(n: u32) -> Simulation {
+    builder(n).build()
+}
+
+// The same bodies after one pass of Z-order sorting.
+fn sorted_simulation(n: u32) -> Simulation {
+    let mut sim = builder(n).sort_interval(1).build();
+    sim.step();
+    sim
+}
+
+// Runs one force pass outside the scheduler so nothing else is timed.
+fn direct(world: &World) {
+    let system = ApplyGravity { compensated: false };
+    system.run(SystemData::get_data(world));
+}
+
+fn force_backends(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forces");
+    group.sample_size(10);
+    for &n in &[1_000u32, 10_000] {
+        let sim = simulation(n);
+        // each distinct pair once, as in the pairs/s column of `bench`
+        group.throughput(Throughput::Elements(n as u64 * (n as u64 - 1) / 2));
+        group.bench_with_input(BenchmarkId::new("direct", n), &sim, |b, sim| {
+            b.iter(|| direct(sim.world()))
+        });
+        let sorted = sorted_simulation(n);
+        group.bench_with_input(BenchmarkId::new("direct-sorted", n), &sorted, |b, sim| {
+            b.iter(|| direct(sim.world()))
+        });
+    }
+    group.finish();
+}
+
+// A whole tick, including integration and the always-on statistics pass.
+fn full_tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tick");
+    group.sample_size(10);
+    for &n in &[1_000u32, 10_000] {
+        let mut sim = simulation(n);
+        group.bench_function(BenchmarkId::new("direct", n), |b| b.iter(|| sim.step()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, force_backends, full_tick);
+criterion_main!(benches);
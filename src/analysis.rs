@@ -0,0 +1,760 @@
+use crate::cells::{cell_size_for, CellList, NeighborGrid};
+use crate::components::*;
+use crate::events::{Event, EventLog};
+use crate::orbits;
+use crate::resources::*;
+use crate::stats::StatsOutput;
+use crate::systems::group_root;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write as IoWrite};
+use std::ops::Deref;
+use std::sync::Mutex;
+use SmolECS::{component::*, entity::*, system::*, world::*};
+
+pub struct RotationCurveOutput {
+    writer: BufWriter<File>,
+    interval: u64,
+    bins: usize,
+}
+
+impl RotationCurveOutput {
+    pub fn create(path: &str, interval: u64, bins: usize) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "tick,bin,r_inner,r_outer,count,mean_circular_velocity"
+        )?;
+        Ok(RotationCurveOutput {
+            writer,
+            interval,
+            bins,
+        })
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Every `interval` ticks, bins bodies by distance from the centre of mass and
+// writes the mean tangential velocity (relative to the centre of mass) per bin.
+pub struct RotationCurve;
+impl<'d, 'w: 'd> System<'d, 'w, World> for RotationCurve {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        Read<'d, Time>,
+        Write<'d, RotationCurveOutput>,
+    );
+
+    fn run(&self, (masses, positions, vels, time, mut output): Self::SystemData) {
+        if time.tick % output.interval != 0 {
+            return;
+        }
+
+        let bodies: Vec<(f32, Position, Velocity)> = (&masses, &positions, &vels)
+            .join()
+            .map(|(mass, pos, vel)| (mass.mass, *pos, *vel))
+            .collect();
+        let total_mass: f32 = bodies.iter().map(|b| b.0).sum();
+        if bodies.is_empty() || total_mass <= 0.0 {
+            return;
+        }
+
+        let mut com = (0.0, 0.0, 0.0, 0.0);
+        for (mass, pos, vel) in &bodies {
+            com.0 += mass * pos.x;
+            com.1 += mass * pos.y;
+            com.2 += mass * vel.x;
+            com.3 += mass * vel.y;
+        }
+        let (cx, cy) = (com.0 / total_mass, com.1 / total_mass);
+        let (cvx, cvy) = (com.2 / total_mass, com.3 / total_mass);
+
+        // (radius, tangential velocity) about the centre of mass
+        let samples: Vec<(f32, f32)> = bodies
+            .iter()
+            .map(|(_, pos, vel)| {
+                let (rx, ry) = (pos.x - cx, pos.y - cy);
+                let (vx, vy) = (vel.x - cvx, vel.y - cvy);
+                let r = (rx * rx + ry * ry).sqrt();
+                let v_t = if r > 0.0 {
+                    (rx * vy - ry * vx) / r
+                } else {
+                    0.0
+                };
+                (r, v_t)
+            })
+            .collect();
+        let max_r = samples.iter().map(|s| s.0).fold(0.0, f32::max);
+        if max_r <= 0.0 {
+            return;
+        }
+
+        let width = max_r / output.bins as f32;
+        let mut sums = vec![(0u32, 0.0f32); output.bins];
+        for (r, v_t) in samples {
+            let bin = ((r / width) as usize).min(output.bins - 1);
+            sums[bin].0 += 1;
+            sums[bin].1 += v_t;
+        }
+
+        for (bin, (count, sum)) in sums.into_iter().enumerate() {
+            let mean = if count > 0 { sum / count as f32 } else { 0.0 };
+            writeln!(
+                output.writer,
+                "{},{},{},{},{},{}",
+                time.tick,
+                bin,
+                bin as f32 * width,
+                (bin + 1) as f32 * width,
+                count,
+                mean
+            )
+            .expect("failed to write rotation curve output");
+        }
+    }
+}
+
+pub struct OrbitalElementsOutput {
+    writer: BufWriter<File>,
+    interval: u64,
+}
+
+impl OrbitalElementsOutput {
+    pub fn create(path: &str, interval: u64) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "tick,time,body,semi_major_axis,eccentricity,inclination,argument_of_periapsis"
+        )?;
+        Ok(OrbitalElementsOutput { writer, interval })
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Every `interval` ticks, writes the osculating elements of each `Orbiter`
+// relative to the `Primary`, treating the pair as an isolated two-body orbit.
+// Angles are in degrees; unbound orbits are written as NaN.
+pub struct OrbitalElements;
+impl<'d, 'w: 'd> System<'d, 'w, World> for OrbitalElements {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        ReadComp<'d, Primary>,
+        ReadComp<'d, Orbiter>,
+        Read<'d, Gravity>,
+        Read<'d, Time>,
+        Write<'d, OrbitalElementsOutput>,
+    );
+
+    fn run(
+        &self,
+        (masses, positions, vels, primaries, orbiters, gravity, time, mut output): Self::SystemData,
+    ) {
+        if time.tick % output.interval != 0 {
+            return;
+        }
+        let primary = match (&primaries, &masses, &positions, &vels).join().next() {
+            Some((_, mass, pos, vel)) => (mass.mass, *pos, *vel),
+            None => return,
+        };
+
+        for (orbiter, mass, pos, vel) in (&orbiters, &masses, &positions, &vels).join() {
+            let mu = gravity.g as f64 * (primary.0 + mass.mass) as f64;
+            let elements = orbits::from_state(
+                [(pos.x - primary.1.x) as f64, (pos.y - primary.1.y) as f64],
+                [(vel.x - primary.2.x) as f64, (vel.y - primary.2.y) as f64],
+                mu,
+            );
+            let (a, e, i, omega) = match elements {
+                Some(el) => (
+                    el.semi_major_axis,
+                    el.eccentricity,
+                    if el.retrograde { 180.0 } else { 0.0 },
+                    el.argument_of_periapsis.to_degrees(),
+                ),
+                None => (f64::NAN, f64::NAN, f64::NAN, f64::NAN),
+            };
+            writeln!(
+                output.writer,
+                "{},{},{},{},{},{},{}",
+                time.tick, time.total, orbiter.index, a, e, i, omega
+            )
+            .expect("failed to write orbital elements output");
+        }
+    }
+}
+
+// Every `interval` ticks, computes the radial pair correlation function g(r)
+// out to `r_max`, normalised against a uniform distribution over the bodies'
+// bounding box, and writes it to the `correlation` statistics table. There is
+// no edge correction: shells that reach past the box hold fewer pairs than a
+// uniform distribution would, so g falls off towards r_max when the box is
+// not much wider than it.
+pub struct PairCorrelation {
+    pub interval: u64,
+    pub r_max: f32,
+    pub bins: usize,
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for PairCorrelation {
+    type SystemData = (
+        ReadComp<'d, Position>,
+        Read<'d, EntityStorage>,
+        Read<'d, Time>,
+        Write<'d, StatsOutput>,
+    );
+
+    fn run(&self, (positions, ents, time, mut output): Self::SystemData) {
+        if time.tick % self.interval != 0 {
+            return;
+        }
+
+        let points: Vec<(f32, f32)> = (&positions, ents.deref())
+            .join()
+            .map(|(p, _)| (p.x, p.y))
+            .collect();
+        let n = points.len();
+        if n < 2 {
+            return;
+        }
+
+        let cells = CellList::build(&points, self.r_max);
+        let width = self.r_max / self.bins as f32;
+        let mut pairs = vec![0u64; self.bins];
+        for (i, &(x, y)) in points.iter().enumerate() {
+            for j in cells.nearby((x, y)).filter(|&j| j > i) {
+                let (dx, dy) = (points[j].0 - x, points[j].1 - y);
+                let r = (dx * dx + dy * dy).sqrt();
+                if r < self.r_max {
+                    // rounding can put r just under r_max in the bin past the last
+                    pairs[((r / width) as usize).min(self.bins - 1)] += 1;
+                }
+            }
+        }
+
+        let (min_x, max_x) = points
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), p| {
+                (lo.min(p.0), hi.max(p.0))
+            });
+        let (min_y, max_y) = points
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), p| {
+                (lo.min(p.1), hi.max(p.1))
+            });
+        let area = (max_x - min_x) * (max_y - min_y);
+        if area <= 0.0 {
+            // bodies along one axis-aligned line leave no area to spread over
+            return;
+        }
+        let density = n as f32 / area;
+        let table = output
+            .table("correlation", "tick,r_inner,r_outer,pairs,g")
+            .expect("failed to create correlation output");
+        if let Some(writer) = table {
+            for (bin, count) in pairs.into_iter().enumerate() {
+                let (r_inner, r_outer) = (bin as f32 * width, (bin + 1) as f32 * width);
+                let shell = std::f32::consts::PI * (r_outer * r_outer - r_inner * r_inner);
+                let expected = 0.5 * n as f32 * density * shell;
+                writeln!(
+                    writer,
+                    "{},{},{},{},{}",
+                    time.tick,
+                    r_inner,
+                    r_outer,
+                    count,
+                    count as f32 / expected
+                )
+                .expect("failed to write correlation output");
+            }
+        }
+    }
+}
+
+// Percentiles written to the `percentiles` statistics table.
+const PERCENTILES: [f32; 5] = [10.0, 25.0, 50.0, 75.0, 90.0];
+
+// Counts of `values` in `bins` equal bins from zero to the largest value, with
+// the bin width.
+fn histogram(values: &[f32], bins: usize) -> (Vec<u64>, f32) {
+    let max = values.iter().copied().fold(0.0, f32::max);
+    let width = if max > 0.0 { max / bins as f32 } else { 1.0 };
+    let mut counts = vec![0u64; bins];
+    for &value in values {
+        counts[((value / width) as usize).min(bins - 1)] += 1;
+    }
+    (counts, width)
+}
+
+// The nearest-rank percentiles in `PERCENTILES` of `values`, sorted.
+fn percentiles(sorted: &[f32]) -> Vec<f32> {
+    PERCENTILES
+        .iter()
+        .map(|p| sorted[(p / 100.0 * (sorted.len() - 1) as f32).round() as usize])
+        .collect()
+}
+
+// Every `interval` ticks, writes histograms in `bins` bins of the speeds of the
+// bodies relative to the centre of mass, to the `speeds` statistics table next
+// to the counts a Maxwellian of the same mean square speed would give, and of
+// the distance from each body to its nearest neighbour, to the `separations`
+// table. Some percentiles of both go to the `percentiles` table.
+pub struct Distributions {
+    pub interval: u64,
+    pub bins: usize,
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for Distributions {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        Read<'d, EntityStorage>,
+        Read<'d, Time>,
+        Write<'d, StatsOutput>,
+    );
+
+    fn run(&self, (masses, positions, vels, ents, time, mut output): Self::SystemData) {
+        if time.tick % self.interval != 0 {
+            return;
+        }
+
+        let bodies: Vec<(f32, Position, Velocity)> = (&masses, &positions, &vels, ents.deref())
+            .join()
+            .map(|(m, p, v, _)| (m.mass, *p, *v))
+            .collect();
+        let n = bodies.len();
+        if n < 2 {
+            return;
+        }
+        let bins = self.bins.max(1);
+
+        let total: f32 = bodies.iter().map(|b| b.0).sum();
+        let (mut vx, mut vy) = (0.0, 0.0);
+        if total > 0.0 {
+            for (m, _, v) in &bodies {
+                vx += m * v.x;
+                vy += m * v.y;
+            }
+            vx /= total;
+            vy /= total;
+        }
+        let mut speeds: Vec<f32> = bodies
+            .iter()
+            .map(|(_, _, v)| ((v.x - vx).powi(2) + (v.y - vy).powi(2)).sqrt())
+            .collect();
+
+        let points: Vec<(f32, f32)> = bodies.iter().map(|(_, p, _)| (p.x, p.y)).collect();
+        let mut grid = NeighborGrid::new(cell_size_for(&points, 4));
+        grid.rebuild(points.clone());
+        let mut separations: Vec<f32> = (0..n)
+            .map(|i| {
+                let j = grid.nearest(i, 1)[0];
+                let (dx, dy) = (points[j].0 - points[i].0, points[j].1 - points[i].1);
+                (dx * dx + dy * dy).sqrt()
+            })
+            .collect();
+
+        // in two dimensions the Maxwellian speed distribution is a Rayleigh
+        // distribution with sigma^2 half the mean square speed
+        let two_sigma2 = speeds.iter().map(|v| v * v).sum::<f32>() / n as f32;
+        let below = |v: f32| {
+            if two_sigma2 > 0.0 {
+                1.0 - (-v * v / two_sigma2).exp()
+            } else {
+                1.0
+            }
+        };
+        let (counts, width) = histogram(&speeds, bins);
+        let table = output
+            .table("speeds", "tick,v_inner,v_outer,count,maxwellian")
+            .expect("failed to create speed distribution output");
+        if let Some(writer) = table {
+            for (bin, count) in counts.into_iter().enumerate() {
+                let (v_inner, v_outer) = (bin as f32 * width, (bin + 1) as f32 * width);
+                writeln!(
+                    writer,
+                    "{},{},{},{},{}",
+                    time.tick,
+                    v_inner,
+                    v_outer,
+                    count,
+                    n as f32 * (below(v_outer) - below(v_inner))
+                )
+                .expect("failed to write speed distribution output");
+            }
+        }
+
+        let (counts, width) = histogram(&separations, bins);
+        let table = output
+            .table("separations", "tick,r_inner,r_outer,count")
+            .expect("failed to create separation distribution output");
+        if let Some(writer) = table {
+            for (bin, count) in counts.into_iter().enumerate() {
+                writeln!(
+                    writer,
+                    "{},{},{},{}",
+                    time.tick,
+                    bin as f32 * width,
+                    (bin + 1) as f32 * width,
+                    count
+                )
+                .expect("failed to write separation distribution output");
+            }
+        }
+
+        speeds.sort_by(f32::total_cmp);
+        separations.sort_by(f32::total_cmp);
+        let header = PERCENTILES
+            .iter()
+            .map(|p| format!(",p{}", p))
+            .collect::<String>();
+        let table = output
+            .table("percentiles", &format!("tick,quantity{}", header))
+            .expect("failed to create percentile output");
+        if let Some(writer) = table {
+            for (quantity, sorted) in [("speed", &speeds), ("separation", &separations)] {
+                let values: Vec<String> =
+                    percentiles(sorted).iter().map(|v| v.to_string()).collect();
+                writeln!(writer, "{},{},{}", time.tick, quantity, values.join(","))
+                    .expect("failed to write percentile output");
+            }
+        }
+    }
+}
+
+// Every `interval` ticks, finds the binaries: mutual nearest neighbours within
+// `radius` of each other whose two-body energy is negative. Each one is written
+// to the `binaries` statistics table by the `BodyId`s of its members, with its
+// orbit and the tick it was first seen, and formations and disruptions go to
+// the event log.
+pub struct BinaryCatalog {
+    pub interval: u64,
+    pub radius: f32,
+    // pair (by `BodyId`, smaller first) -> tick first seen
+    binaries: Mutex<HashMap<(u64, u64), u64>>,
+}
+
+impl BinaryCatalog {
+    pub fn new(interval: u64, radius: f32) -> Self {
+        BinaryCatalog {
+            interval,
+            radius,
+            binaries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for BinaryCatalog {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        ReadComp<'d, BodyId>,
+        Read<'d, Gravity>,
+        Read<'d, Time>,
+        Write<'d, StatsOutput>,
+        Write<'d, EventLog>,
+    );
+
+    fn run(
+        &self,
+        (masses, positions, vels, ids, gravity, time, mut output, mut log): Self::SystemData,
+    ) {
+        if time.tick % self.interval != 0 {
+            return;
+        }
+
+        let bodies: Vec<(f32, Position, Velocity, u64)> = (&masses, &positions, &vels, &ids)
+            .join()
+            .map(|(mass, pos, vel, id)| (mass.mass, *pos, *vel, id.0))
+            .collect();
+        let points: Vec<(f32, f32)> = bodies.iter().map(|b| (b.1.x, b.1.y)).collect();
+        let cells = CellList::build(&points, self.radius);
+
+        let nearest: Vec<Option<usize>> = points
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y))| {
+                let mut best: Option<(usize, f32)> = None;
+                for j in cells.nearby((x, y)).filter(|&j| j != i) {
+                    let (dx, dy) = (points[j].0 - x, points[j].1 - y);
+                    let r2 = dx * dx + dy * dy;
+                    if r2 < self.radius * self.radius && best.is_none_or(|(_, b)| r2 < b) {
+                        best = Some((j, r2));
+                    }
+                }
+                best.map(|(j, _)| j)
+            })
+            .collect();
+
+        let mut found = HashMap::new();
+        for (i, j) in nearest.iter().enumerate() {
+            let j = match j {
+                Some(j) if *j > i && nearest[*j] == Some(i) => *j,
+                _ => continue,
+            };
+            let (one, two) = (&bodies[i], &bodies[j]);
+            let mu = gravity.g as f64 * (one.0 + two.0) as f64;
+            let elements = orbits::from_state(
+                [(two.1.x - one.1.x) as f64, (two.1.y - one.1.y) as f64],
+                [(two.2.x - one.2.x) as f64, (two.2.y - one.2.y) as f64],
+                mu,
+            );
+            if let Some(elements) = elements {
+                let ((a, mass_a), (b, mass_b)) = if one.3 < two.3 {
+                    ((one.3, one.0), (two.3, two.0))
+                } else {
+                    ((two.3, two.0), (one.3, one.0))
+                };
+                found.insert((a, b), (elements, mass_a, mass_b));
+            }
+        }
+
+        let mut binaries = self.binaries.lock().unwrap();
+        binaries.retain(|&(a, b), _| {
+            let bound = found.contains_key(&(a, b));
+            if !bound {
+                log.record(&time, Event::BinaryDisrupted { a, b });
+            }
+            bound
+        });
+
+        let mut pairs: Vec<_> = found.into_iter().collect();
+        pairs.sort_by_key(|(pair, _)| *pair);
+        let mut table = output
+            .table(
+                "binaries",
+                "tick,time,a,b,mass_a,mass_b,semi_major_axis,eccentricity,formed",
+            )
+            .expect("failed to create binary catalog");
+        for ((a, b), (elements, mass_a, mass_b)) in pairs {
+            let formed = *binaries.entry((a, b)).or_insert_with(|| {
+                log.record(
+                    &time,
+                    Event::BinaryFormed {
+                        a,
+                        b,
+                        semi_major_axis: elements.semi_major_axis,
+                        eccentricity: elements.eccentricity,
+                    },
+                );
+                time.tick
+            });
+            if let Some(writer) = table.as_mut() {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{},{}",
+                    time.tick,
+                    time.total,
+                    a,
+                    b,
+                    mass_a,
+                    mass_b,
+                    elements.semi_major_axis,
+                    elements.eccentricity,
+                    formed
+                )
+                .expect("failed to write binary catalog");
+            }
+        }
+    }
+}
+
+// Width in dex of the bins of `ClumpCatalog::mass_function`.
+pub const MASS_FUNCTION_BIN: f32 = 0.25;
+
+#[derive(Clone)]
+pub struct Clump {
+    pub members: usize,
+    pub mass: f32,
+    // centre of mass
+    pub center: (f32, f32),
+    // mass-weighted root mean square distance of the members from the centre
+    pub radius: f32,
+}
+
+// The clumps of the last `FriendsOfFriends` search, heaviest first.
+#[derive(Clone, Default)]
+pub struct ClumpCatalog {
+    pub tick: u64,
+    pub clumps: Vec<Clump>,
+}
+
+impl ClumpCatalog {
+    // The number of clumps per bin of log10 mass, as (lower edge, count) for
+    // every bin from the lightest clump's to the heaviest's.
+    pub fn mass_function(&self) -> Vec<(f32, usize)> {
+        let bin = |mass: f32| (mass.log10() / MASS_FUNCTION_BIN).floor() as i32;
+        let (lightest, heaviest) = match (self.clumps.last(), self.clumps.first()) {
+            (Some(lightest), Some(heaviest)) => (bin(lightest.mass), bin(heaviest.mass)),
+            _ => return Vec::new(),
+        };
+        let mut counts = vec![0; (heaviest - lightest + 1) as usize];
+        for clump in &self.clumps {
+            counts[(bin(clump.mass) - lightest) as usize] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| ((lightest + i as i32) as f32 * MASS_FUNCTION_BIN, count))
+            .collect()
+    }
+}
+
+impl fmt::Display for ClumpCatalog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "clumps at tick {}: {}", self.tick, self.clumps.len())?;
+        if !self.clumps.is_empty() {
+            writeln!(f, "{:>10} {:>8}", "log mass", "clumps")?;
+        }
+        for (log_mass, count) in self.mass_function() {
+            writeln!(f, "{:>10.2} {:>8}", log_mass, count)?;
+        }
+        Ok(())
+    }
+}
+
+// Every `interval` ticks, links every pair of massive bodies closer than
+// `linking_length` and takes the connected sets of at least `min_members` as
+// clumps. Their members get a `ClumpId`, the catalog replaces the
+// `ClumpCatalog` resource and is written to the `clumps` statistics table, and
+// its mass function to the `mass_function` table. Tracers are never linked.
+pub struct FriendsOfFriends {
+    pub interval: u64,
+    pub linking_length: f32,
+    pub min_members: usize,
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for FriendsOfFriends {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        Read<'d, EntityStorage>,
+        WriteComp<'d, ClumpId>,
+        Read<'d, Time>,
+        Write<'d, StatsOutput>,
+        Write<'d, ClumpCatalog>,
+    );
+
+    fn run(
+        &self,
+        (masses, positions, ents, mut ids, time, mut output, mut catalog): Self::SystemData,
+    ) {
+        if time.tick % self.interval != 0 {
+            return;
+        }
+
+        let bodies: Vec<(f32, Position, Entity)> = (&masses, &positions, ents.deref())
+            .join()
+            .map(|(mass, pos, ent)| (mass.mass, *pos, *ent))
+            .collect();
+        let points: Vec<(f32, f32)> = bodies.iter().map(|b| (b.1.x, b.1.y)).collect();
+        let cells = CellList::build(&points, self.linking_length);
+        let mut parents: Vec<usize> = (0..bodies.len()).collect();
+        let reach = self.linking_length * self.linking_length;
+        for (i, &(x, y)) in points.iter().enumerate() {
+            if bodies[i].0 == 0.0 {
+                continue;
+            }
+            for j in cells
+                .nearby((x, y))
+                .filter(|&j| j > i && bodies[j].0 != 0.0)
+            {
+                let (dx, dy) = (points[j].0 - x, points[j].1 - y);
+                if dx * dx + dy * dy < reach {
+                    let (a, b) = (group_root(&mut parents, i), group_root(&mut parents, j));
+                    parents[a.max(b)] = a.min(b);
+                }
+            }
+        }
+
+        let mut sets: HashMap<usize, Vec<usize>> = HashMap::new();
+        for i in (0..bodies.len()).filter(|&i| bodies[i].0 != 0.0) {
+            sets.entry(group_root(&mut parents, i)).or_default().push(i);
+        }
+        let sets: Vec<Vec<usize>> = sets
+            .into_values()
+            .filter(|members| members.len() >= self.min_members.max(2))
+            .collect();
+        let clump = |members: &[usize]| {
+            let mass: f32 = members.iter().map(|&i| bodies[i].0).sum();
+            let (mut x, mut y) = (0.0, 0.0);
+            for &i in members {
+                x += bodies[i].0 * bodies[i].1.x / mass;
+                y += bodies[i].0 * bodies[i].1.y / mass;
+            }
+            let spread: f32 = members
+                .iter()
+                .map(|&i| {
+                    let (dx, dy) = (bodies[i].1.x - x, bodies[i].1.y - y);
+                    bodies[i].0 * (dx * dx + dy * dy)
+                })
+                .sum();
+            Clump {
+                members: members.len(),
+                mass,
+                center: (x, y),
+                radius: (spread / mass).sqrt(),
+            }
+        };
+        let mut clumps: Vec<(Clump, Vec<usize>)> = sets
+            .into_iter()
+            .map(|members| (clump(&members), members))
+            .collect();
+        // heaviest first, ties by lowest member so the order is reproducible
+        clumps.sort_by(|a, b| b.0.mass.total_cmp(&a.0.mass).then(a.1[0].cmp(&b.1[0])));
+
+        for body in &bodies {
+            ids.remove(&body.2);
+        }
+        for (id, (_, members)) in clumps.iter().enumerate() {
+            for &i in members {
+                ids.set(&bodies[i].2, ClumpId(id as u32));
+            }
+        }
+        *catalog = ClumpCatalog {
+            tick: time.tick,
+            clumps: clumps.into_iter().map(|(clump, _)| clump).collect(),
+        };
+
+        if let Some(table) = output
+            .table("clumps", "tick,time,clump,members,mass,x,y,radius")
+            .expect("failed to create clump catalog")
+        {
+            for (id, clump) in catalog.clumps.iter().enumerate() {
+                writeln!(
+                    table,
+                    "{},{},{},{},{},{},{},{}",
+                    time.tick,
+                    time.total,
+                    id,
+                    clump.members,
+                    clump.mass,
+                    clump.center.0,
+                    clump.center.1,
+                    clump.radius
+                )
+                .expect("failed to write clump catalog");
+            }
+        }
+        if let Some(table) = output
+            .table("mass_function", "tick,time,log_mass,clumps")
+            .expect("failed to create clump mass function")
+        {
+            for (log_mass, count) in catalog.mass_function() {
+                writeln!(table, "{},{},{},{}", time.tick, time.total, log_mass, count)
+                    .expect("failed to write clump mass function");
+            }
+        }
+    }
+}
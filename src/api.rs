@@ -0,0 +1,282 @@
+// HTTP query API (`--api <addr>`) for inspecting and editing a running
+// simulation from scripts and notebooks:
+//
+//   GET    /state?offset=0&limit=1000   a page of bodies
+//   GET    /body/{id}                   one body
+//   GET    /stats                       the latest statistics sample
+//   POST   /bodies                      spawn {"mass":1,"x":0,"y":0,"vx":0,"vy":0}
+//   DELETE /body/{id}                   remove a body
+//
+// Bodies are identified by their index in storage order, so ids after a
+// deleted body shift down by one. Requests are queued by the HTTP thread and
+// answered between ticks by `Api::answer`, so they always see a whole tick.
+use crate::components::{Position, Velocity};
+use crate::scenarios::BodyState;
+use crate::stats::StatsSample;
+use crate::Simulation;
+#[cfg(feature = "api")]
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(feature = "api")]
+use std::thread;
+#[cfg(feature = "api")]
+use std::time::Duration;
+#[cfg(feature = "api")]
+use tiny_http::{Header, Method, Response, Server};
+
+#[cfg(feature = "api")]
+enum Request {
+    State { offset: usize, limit: usize },
+    Body(usize),
+    Stats,
+    Spawn(BodyState),
+    Remove(usize),
+}
+
+// status code and JSON body
+#[cfg(feature = "api")]
+type Reply = (u16, String);
+
+#[cfg(feature = "api")]
+pub struct Api {
+    requests: Receiver<(Request, Sender<Reply>)>,
+}
+
+#[cfg(feature = "api")]
+impl Api {
+    // Binds `addr` and parses requests on a background thread.
+    pub fn serve(addr: &str) -> Result<Self, String> {
+        let server = Server::http(addr).map_err(|e| e.to_string())?;
+        let (queue, requests) = mpsc::channel();
+
+        thread::spawn(move || {
+            let content_type =
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+            for mut request in server.incoming_requests() {
+                let mut body = String::new();
+                let (status, text) = match request.as_reader().read_to_string(&mut body) {
+                    Ok(_) => match route(request.method(), request.url(), &body) {
+                        Ok(parsed) => {
+                            let (reply, replies) = mpsc::channel();
+                            if queue.send((parsed, reply)).is_err() {
+                                return;
+                            }
+                            replies
+                                .recv_timeout(Duration::from_secs(10))
+                                .unwrap_or_else(|_| (503, error("simulation is not responding")))
+                        }
+                        Err(reply) => reply,
+                    },
+                    Err(e) => (400, error(&e.to_string())),
+                };
+                let response = Response::from_string(text)
+                    .with_status_code(status)
+                    .with_header(content_type.clone());
+                if let Err(e) = request.respond(response) {
+                    eprintln!("failed to answer an API request: {}", e);
+                }
+            }
+        });
+
+        Ok(Api { requests })
+    }
+
+    // Answers every request queued since the last call.
+    pub fn answer(&self, simulation: &mut Simulation) {
+        for (request, reply) in self.requests.try_iter() {
+            let _ = reply.send(handle(request, simulation));
+        }
+    }
+}
+
+#[cfg(feature = "api")]
+fn handle(request: Request, simulation: &mut Simulation) -> Reply {
+    match request {
+        Request::State { offset, limit } => {
+            let bodies = simulation.bodies();
+            let page: Vec<String> = bodies
+                .iter()
+                .enumerate()
+                .skip(offset)
+                .take(limit)
+                .map(|(id, body)| body_json(id, body))
+                .collect();
+            (
+                200,
+                format!(
+                    "{{\"tick\":{},\"time\":{},\"count\":{},\"offset\":{},\"bodies\":[{}]}}",
+                    simulation.tick(),
+                    simulation.time(),
+                    bodies.len(),
+                    offset,
+                    page.join(",")
+                ),
+            )
+        }
+        Request::Body(id) => match simulation.bodies().get(id) {
+            Some(body) => (200, body_json(id, body)),
+            None => (404, error(&format!("no body {}", id))),
+        },
+        Request::Stats => match simulation.latest_stats() {
+            Some(sample) => (200, stats_json(&sample)),
+            None => (404, error("no statistics yet")),
+        },
+        Request::Spawn(body) => match simulation.spawn(body) {
+            Ok(()) => {
+                let id = simulation.bodies().len() - 1;
+                (201, body_json(id, &body))
+            }
+            Err(e) => (400, error(&e)),
+        },
+        Request::Remove(id) => {
+            if simulation.remove(id) {
+                (200, format!("{{\"removed\":{}}}", id))
+            } else {
+                (404, error(&format!("no body {}", id)))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "api")]
+fn route(method: &Method, url: &str, body: &str) -> Result<Request, Reply> {
+    let (path, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => (url, ""),
+    };
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let id = |text: &str| {
+        text.parse::<usize>()
+            .map_err(|_| (400, error(&format!("'{}' is not a body id", text))))
+    };
+    match (method, segments.as_slice()) {
+        (Method::Get, ["state"]) => {
+            let (mut offset, mut limit) = (0, 1000);
+            for pair in query.split('&').filter(|p| !p.is_empty()) {
+                let mut parts = pair.splitn(2, '=');
+                let (key, value) = (parts.next().unwrap(), parts.next().unwrap_or(""));
+                let value = value
+                    .parse()
+                    .map_err(|_| (400, error(&format!("'{}' is not a number", value))))?;
+                match key {
+                    "offset" => offset = value,
+                    "limit" => limit = value,
+                    _ => return Err((400, error(&format!("unknown parameter '{}'", key)))),
+                }
+            }
+            Ok(Request::State { offset, limit })
+        }
+        (Method::Get, ["body", text]) => Ok(Request::Body(id(text)?)),
+        (Method::Delete, ["body", text]) => Ok(Request::Remove(id(text)?)),
+        (Method::Get, ["stats"]) => Ok(Request::Stats),
+        (Method::Post, ["bodies"]) => parse_body(body)
+            .map(Request::Spawn)
+            .map_err(|e| (400, error(&e))),
+        _ => Err((404, error(&format!("no endpoint {} {}", method, path)))),
+    }
+}
+
+// A flat JSON object with numeric values: mass and x, y, vx, vy (default 0),
+// and species and group (default 0).
+pub(crate) fn parse_body(text: &str) -> Result<BodyState, String> {
+    let inner = text
+        .trim()
+        .strip_prefix('{')
+        .and_then(|t| t.strip_suffix('}'))
+        .ok_or("expected a JSON object")?;
+    let mut body = BodyState {
+        mass: f32::NAN,
+        species: 0,
+        group: 0,
+        position: Position { x: 0.0, y: 0.0 },
+        velocity: Velocity { x: 0.0, y: 0.0 },
+    };
+    for field in inner.split(',').filter(|f| !f.trim().is_empty()) {
+        let mut parts = field.splitn(2, ':');
+        let key = parts.next().unwrap().trim().trim_matches('"');
+        let value = parts
+            .next()
+            .ok_or(format!("missing value for '{}'", key))?
+            .trim();
+        let number: f32 = value
+            .parse()
+            .map_err(|_| format!("'{}' is not a number", value))?;
+        match key {
+            "mass" => body.mass = number,
+            "species" => body.species = number as usize,
+            "group" => body.group = number as u32,
+            "x" => body.position.x = number,
+            "y" => body.position.y = number,
+            "vx" => body.velocity.x = number,
+            "vy" => body.velocity.y = number,
+            _ => return Err(format!("unknown field '{}'", key)),
+        }
+    }
+    if body.mass.is_nan() || body.mass < 0.0 {
+        return Err("a body needs a non-negative mass".to_string());
+    }
+    Ok(body)
+}
+
+#[cfg(feature = "api")]
+fn body_json(id: usize, body: &BodyState) -> String {
+    format!(
+        "{{\"id\":{},\"mass\":{},\"species\":{},\"group\":{},\"x\":{},\"y\":{},\"vx\":{},\"vy\":{}}}",
+        id,
+        number(body.mass),
+        body.species,
+        body.group,
+        number(body.position.x),
+        number(body.position.y),
+        number(body.velocity.x),
+        number(body.velocity.y)
+    )
+}
+
+// NaN and infinite values (no pairs, no bound mass) aren't valid JSON numbers.
+pub(crate) fn number(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+pub(crate) fn stats_json(sample: &StatsSample) -> String {
+    format!(
+        "{{\"tick\":{},\"time\":{},\"kinetic\":{},\"potential\":{},\"max_speed\":{},\
+         \"min_pair_distance\":{},\"bodies\":{},\"escaped\":{},\"virial_ratio\":{},\
+         \"half_mass_radius\":{},\"core_density\":{}}}",
+        sample.tick,
+        sample.time,
+        number(sample.kinetic),
+        number(sample.potential),
+        number(sample.max_speed),
+        number(sample.min_pair_distance),
+        sample.bodies,
+        sample.escaped,
+        number(sample.virial_ratio),
+        number(sample.half_mass_radius),
+        number(sample.core_density)
+    )
+}
+
+#[cfg(feature = "api")]
+fn error(message: &str) -> String {
+    format!("{{\"error\":\"{}\"}}", message.replace('"', "'"))
+}
+
+// Builds without the "api" feature have no HTTP server to take requests.
+#[cfg(not(feature = "api"))]
+pub struct Api;
+
+#[cfg(not(feature = "api"))]
+impl Api {
+    pub fn serve(addr: &str) -> Result<Self, String> {
+        Err(format!(
+            "serving {} needs a build with the \"api\" feature",
+            addr
+        ))
+    }
+
+    pub fn answer(&self, _simulation: &mut Simulation) {}
+}
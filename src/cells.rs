@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+
+// Uniform grid over the bounding box of a set of points, with cells of side
+// `cell_size`. Any two points within `cell_size` of each other are in the same
+// or adjacent cells, so short-range pair searches only visit 3x3 cells. Only
+// occupied cells are stored, keyed by row then column, so a few points far
+// from the rest do not fill the box with empty cells.
+pub struct CellList {
+    origin: (f32, f32),
+    cell_size: f32,
+    cols: usize,
+    rows: usize,
+    cells: BTreeMap<(usize, usize), Vec<usize>>,
+}
+
+impl CellList {
+    pub fn build(points: &[(f32, f32)], cell_size: f32) -> Self {
+        let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+        let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for (x, y) in points {
+            min_x = min_x.min(*x);
+            min_y = min_y.min(*y);
+            max_x = max_x.max(*x);
+            max_y = max_y.max(*y);
+        }
+        if points.is_empty() {
+            min_x = 0.0;
+            min_y = 0.0;
+            max_x = 0.0;
+            max_y = 0.0;
+        }
+
+        let cols = (((max_x - min_x) / cell_size) as usize).saturating_add(1);
+        let rows = (((max_y - min_y) / cell_size) as usize).saturating_add(1);
+        let mut list = CellList {
+            origin: (min_x, min_y),
+            cell_size,
+            cols,
+            rows,
+            cells: BTreeMap::new(),
+        };
+        for (i, point) in points.iter().enumerate() {
+            let (col, row) = list.cell_of(*point);
+            list.cells.entry((row, col)).or_default().push(i);
+        }
+        list
+    }
+
+    // Width and height of the area covered by the grid.
+    pub fn extent(&self) -> (f32, f32) {
+        (
+            self.cols as f32 * self.cell_size,
+            self.rows as f32 * self.cell_size,
+        )
+    }
+
+    fn cell_of(&self, (x, y): (f32, f32)) -> (usize, usize) {
+        let col = ((x - self.origin.0) / self.cell_size) as usize;
+        let row = ((y - self.origin.1) / self.cell_size) as usize;
+        (col.min(self.cols - 1), row.min(self.rows - 1))
+    }
+
+    // Indices of the points in the 3x3 block of cells around `point`.
+    pub fn nearby(&self, point: (f32, f32)) -> impl Iterator<Item = usize> + '_ {
+        self.around(point, 1)
+    }
+
+    // Indices of the points in cells up to `rings` cells away from `point`'s,
+    // row by row.
+    pub fn around(&self, point: (f32, f32), rings: usize) -> impl Iterator<Item = usize> + '_ {
+        let (col, row) = self.cell_of(point);
+        let reach = |at: usize, len: usize| {
+            at.saturating_sub(rings)..at.saturating_add(rings).saturating_add(1).min(len)
+        };
+        let (cols, rows) = (reach(col, self.cols), reach(row, self.rows));
+        // each row of the block is a range of the map, unless there are more
+        // rows than occupied cells, when scanning the cells is cheaper
+        let cells: Vec<&Vec<usize>> = if rows.len() <= self.cells.len() {
+            rows.flat_map(|r| {
+                self.cells
+                    .range((r, cols.start)..(r, cols.end))
+                    .map(|(_, cell)| cell)
+            })
+            .collect()
+        } else {
+            self.cells
+                .range((rows.start, 0)..(rows.end, 0))
+                .filter(|((_, c), _)| cols.contains(c))
+                .map(|(_, cell)| cell)
+                .collect()
+        };
+        cells.into_iter().flat_map(|cell| cell.iter().cloned())
+    }
+}
+
+// Body positions binned into a `CellList`, rebuilt at the start of every tick
+// when enabled with `SimulationBuilder::neighbor_grid`. Bodies are identified
+// by their index in storage (join) order, and positions are those from before
+// the tick's integration step, so systems that run after it see where bodies
+// were rather than where they are.
+pub struct NeighborGrid {
+    cell_size: f32,
+    points: Vec<(f32, f32)>,
+    cells: CellList,
+}
+
+impl NeighborGrid {
+    pub fn new(cell_size: f32) -> Self {
+        NeighborGrid {
+            cell_size,
+            points: Vec::new(),
+            cells: CellList::build(&[], cell_size),
+        }
+    }
+
+    pub fn rebuild(&mut self, points: Vec<(f32, f32)>) {
+        self.cells = CellList::build(&points, self.cell_size);
+        self.points = points;
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn position(&self, index: usize) -> (f32, f32) {
+        self.points[index]
+    }
+
+    // Indices of the bodies closer than `radius` to `pos`, including any body
+    // at `pos` itself. Radii larger than the cell size are allowed but visit
+    // more cells.
+    pub fn neighbors_within(&self, pos: (f32, f32), radius: f32) -> Vec<usize> {
+        let rings = (radius / self.cell_size).ceil().max(1.0) as usize;
+        self.cells
+            .around(pos, rings)
+            .filter(|&i| {
+                let (dx, dy) = (self.points[i].0 - pos.0, self.points[i].1 - pos.1);
+                dx * dx + dy * dy < radius * radius
+            })
+            .collect()
+    }
+
+    // The `k` bodies nearest to body `index`, not counting itself, closest
+    // first, or every other body if there are fewer.
+    pub fn nearest(&self, index: usize, k: usize) -> Vec<usize> {
+        let pos = self.points[index];
+        let distance2 = |i: usize| {
+            let (dx, dy) = (self.points[i].0 - pos.0, self.points[i].1 - pos.1);
+            dx * dx + dy * dy
+        };
+        let whole = self.cells.cols.max(self.cells.rows);
+        let mut rings = 1;
+        loop {
+            let mut found: Vec<usize> = self
+                .cells
+                .around(pos, rings)
+                .filter(|&i| i != index)
+                .collect();
+            found.sort_by(|&a, &b| distance2(a).total_cmp(&distance2(b)));
+            found.truncate(k);
+            // the cells searched hold every body within `rings` cells
+            let reach = rings as f32 * self.cell_size;
+            if rings >= whole || (found.len() == k && distance2(found[k - 1]) <= reach * reach) {
+                return found;
+            }
+            rings = rings.saturating_mul(2);
+        }
+    }
+
+    // Surface density around body `index`: the mass of its `k` nearest
+    // neighbours over the area of the circle reaching the furthest of them.
+    // `masses` are in grid order. Zero if there are no other bodies.
+    pub fn density(&self, index: usize, masses: &[f32], k: usize) -> f32 {
+        let nearest = self.nearest(index, k.max(1));
+        let Some(&furthest) = nearest.last() else {
+            return 0.0;
+        };
+        let pos = self.points[index];
+        let (dx, dy) = (
+            self.points[furthest].0 - pos.0,
+            self.points[furthest].1 - pos.1,
+        );
+        let mass: f32 = nearest.iter().map(|&i| masses[i]).sum();
+        mass / (std::f32::consts::PI * (dx * dx + dy * dy))
+    }
+}
+
+// A cell size for a `NeighborGrid` over `points` that puts about `k` of them in
+// each cell, were they spread evenly over their bounding box.
+pub fn cell_size_for(points: &[(f32, f32)], k: usize) -> f32 {
+    let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+    let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for (x, y) in points {
+        min_x = min_x.min(*x);
+        min_y = min_y.min(*y);
+        max_x = max_x.max(*x);
+        max_y = max_y.max(*y);
+    }
+    let area = (max_x - min_x) * (max_y - min_y);
+    let size = (area * k.max(1) as f32 / points.len().max(1) as f32).sqrt();
+    if size > 0.0 {
+        size
+    } else {
+        1.0
+    }
+}
+
+// `NeighborGrid::density` of every one of a set of bodies.
+pub fn densities(points: Vec<(f32, f32)>, masses: &[f32], k: usize) -> Vec<f32> {
+    let mut grid = NeighborGrid::new(cell_size_for(&points, k));
+    grid.rebuild(points);
+    (0..grid.len())
+        .map(|i| grid.density(i, masses, k))
+        .collect()
+}
+
+// Interleaves the bits of two 16-bit grid coordinates into a Z-order (Morton)
+// key, so points close in space are mostly close in key order.
+pub fn morton(x: u16, y: u16) -> u32 {
+    fn spread(v: u16) -> u32 {
+        let mut v = v as u32;
+        v = (v | (v << 8)) & 0x00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333;
+        v = (v | (v << 1)) & 0x5555_5555;
+        v
+    }
+    spread(x) | (spread(y) << 1)
+}
@@ -0,0 +1,226 @@
+// Full simulation state in a small little-endian binary format, enough to
+// resume a run where it stopped. Layout (version 4):
+//
+//   magic "SNBC", version u32, tick u64, time f64,
+//   has_step u8, step f64, g f32, epsilon f32,
+//   species u32, species^2 x coefficient f32,
+//   bodies u64, per body: mass f32, species u32, group u32, x, y, vx, vy f32,
+//   random seed 32 x u8, stream u64, word position u128,
+//   spinning u64, per spinning body: index u64, angle f32, rate f32
+//
+// Version 1 files, without the group, version 2 files, without the random
+// state, and version 3 files, without spins, are still read. Runtime tags such
+// as `Escaped` are not included, and a resumed run numbers its bodies'
+// `BodyId`s afresh in storage order.
+use crate::components::{Position, Spin, Velocity};
+use crate::resources::{Gravity, InteractionMatrix, RandomState, Softening};
+use crate::scenarios::BodyState;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"SNBC";
+const VERSION: u32 = 4;
+
+pub struct Checkpoint {
+    pub tick: u64,
+    pub time: f64,
+    pub step: Option<f64>,
+    pub gravity: Gravity,
+    pub matrix: InteractionMatrix,
+    pub bodies: Vec<BodyState>,
+    // the generator of stochastic systems; `None` in files from before version 3
+    pub rng: Option<RandomState>,
+    // the spinning bodies, by index into `bodies`
+    pub spins: Vec<(usize, Spin)>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    Ok(f32::from_bits(read_u32(reader)?))
+}
+
+pub(crate) fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+// Bodies in the layout above: a count, then mass, species, group and state
+// each.
+pub(crate) fn write_bodies<W: Write>(writer: &mut W, bodies: &[BodyState]) -> io::Result<()> {
+    writer.write_all(&(bodies.len() as u64).to_le_bytes())?;
+    for body in bodies {
+        writer.write_all(&body.mass.to_le_bytes())?;
+        writer.write_all(&(body.species as u32).to_le_bytes())?;
+        writer.write_all(&body.group.to_le_bytes())?;
+        writer.write_all(&body.position.x.to_le_bytes())?;
+        writer.write_all(&body.position.y.to_le_bytes())?;
+        writer.write_all(&body.velocity.x.to_le_bytes())?;
+        writer.write_all(&body.velocity.y.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// `groups` is false for the version 1 layout, which has none.
+pub(crate) fn read_bodies<R: Read>(reader: &mut R, groups: bool) -> io::Result<Vec<BodyState>> {
+    let count = read_u64(reader)?;
+    // the count is only trusted as far as the bodies behind it can be read
+    let mut bodies = Vec::with_capacity(count.min(1 << 16) as usize);
+    for read in 0..count {
+        let body = read_body(reader, groups).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid(format!(
+                "the file ends after {} of its {} bodies",
+                read, count
+            )),
+            _ => e,
+        })?;
+        bodies.push(body);
+    }
+    Ok(bodies)
+}
+
+fn read_body<R: Read>(reader: &mut R, groups: bool) -> io::Result<BodyState> {
+    Ok(BodyState {
+        mass: read_f32(reader)?,
+        species: read_u32(reader)? as usize,
+        group: if groups { read_u32(reader)? } else { 0 },
+        position: Position {
+            x: read_f32(reader)?,
+            y: read_f32(reader)?,
+        },
+        velocity: Velocity {
+            x: read_f32(reader)?,
+            y: read_f32(reader)?,
+        },
+    })
+}
+
+impl Checkpoint {
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.tick.to_le_bytes())?;
+        writer.write_all(&self.time.to_le_bytes())?;
+        writer.write_all(&[self.step.is_some() as u8])?;
+        writer.write_all(&self.step.unwrap_or(0.0).to_le_bytes())?;
+        writer.write_all(&self.gravity.g.to_le_bytes())?;
+        writer.write_all(&self.gravity.epsilon.to_le_bytes())?;
+
+        writer.write_all(&(self.matrix.species() as u32).to_le_bytes())?;
+        for coefficient in self.matrix.coefficients() {
+            writer.write_all(&coefficient.to_le_bytes())?;
+        }
+
+        write_bodies(&mut writer, &self.bodies)?;
+
+        let rng = self
+            .rng
+            .clone()
+            .unwrap_or_else(|| RandomState::new([0; 32], 0));
+        writer.write_all(&rng.seed())?;
+        writer.write_all(&rng.stream().to_le_bytes())?;
+        writer.write_all(&rng.word_pos().to_le_bytes())?;
+
+        writer.write_all(&(self.spins.len() as u64).to_le_bytes())?;
+        for (index, spin) in &self.spins {
+            writer.write_all(&(*index as u64).to_le_bytes())?;
+            writer.write_all(&spin.angle.to_le_bytes())?;
+            writer.write_all(&spin.rate.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a checkpoint file".to_string()));
+        }
+        let version = read_u32(&mut reader)?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid(format!(
+                "unsupported checkpoint version {}",
+                version
+            )));
+        }
+
+        let tick = read_u64(&mut reader)?;
+        let time = read_f64(&mut reader)?;
+        let mut has_step = [0; 1];
+        reader.read_exact(&mut has_step)?;
+        let step = read_f64(&mut reader)?;
+        let gravity = Gravity {
+            g: read_f32(&mut reader)?,
+            epsilon: read_f32(&mut reader)?,
+            // not stored; a resumed run takes them from its options
+            softening: Softening::None,
+            exponent: 2.0,
+        };
+
+        let species = read_u32(&mut reader)? as usize;
+        let coefficients = (0..species * species)
+            .map(|_| read_f32(&mut reader))
+            .collect::<io::Result<Vec<f32>>>()?;
+        let matrix =
+            InteractionMatrix::from_coefficients(species, coefficients).map_err(invalid)?;
+
+        let bodies = read_bodies(&mut reader, version >= 2)?;
+
+        let rng = if version >= 3 {
+            let mut seed = [0; 32];
+            reader.read_exact(&mut seed)?;
+            let stream = read_u64(&mut reader)?;
+            let mut word_pos = [0; 16];
+            reader.read_exact(&mut word_pos)?;
+            Some(RandomState::resume(
+                seed,
+                stream,
+                u128::from_le_bytes(word_pos),
+            ))
+        } else {
+            None
+        };
+
+        let mut spins = Vec::new();
+        if version >= 4 {
+            for _ in 0..read_u64(&mut reader)? {
+                let index = read_u64(&mut reader)? as usize;
+                if index >= bodies.len() {
+                    return Err(invalid(format!("spin of body {} out of range", index)));
+                }
+                let spin = Spin {
+                    angle: read_f32(&mut reader)?,
+                    rate: read_f32(&mut reader)?,
+                };
+                spins.push((index, spin));
+            }
+        }
+
+        Ok(Checkpoint {
+            tick,
+            time,
+            step: if has_step[0] != 0 { Some(step) } else { None },
+            gravity,
+            matrix,
+            bodies,
+            rng,
+            spins,
+        })
+    }
+}
@@ -0,0 +1,235 @@
+// Apache Parquet output for snapshots and the per-tick statistics, chosen by a
+// `.parquet` extension, for runs too large for CSV, and snapshots read back.
+// Only built with the `columnar` feature.
+use crate::components::{Position, Velocity};
+use crate::scenarios::BodyState;
+use crate::snapshot::Snapshot;
+use crate::stats::StatsSample;
+use arrow::array::{ArrayRef, Float32Array, Float64Array, UInt32Array, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::{ArrowReader, ArrowWriter, ParquetFileArrowReader};
+use parquet::file::reader::SerializedFileReader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// Rows of statistics per row group.
+const ROW_GROUP: usize = 4096;
+
+fn other<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+// One row per body with the tick and time in the schema metadata, matching the
+// columns of `snapshot::write_csv`.
+pub fn write_snapshot<P: AsRef<Path>>(
+    path: P,
+    tick: u64,
+    time: f64,
+    bodies: &[BodyState],
+) -> io::Result<()> {
+    let mut metadata = HashMap::new();
+    metadata.insert("tick".to_string(), tick.to_string());
+    metadata.insert("time".to_string(), time.to_string());
+    let schema = Arc::new(Schema::new_with_metadata(
+        vec![
+            Field::new("index", DataType::UInt64, false),
+            Field::new("mass", DataType::Float32, false),
+            Field::new("species", DataType::UInt64, false),
+            Field::new("x", DataType::Float32, false),
+            Field::new("y", DataType::Float32, false),
+            Field::new("vx", DataType::Float32, false),
+            Field::new("vy", DataType::Float32, false),
+            Field::new("group", DataType::UInt32, false),
+        ],
+        metadata,
+    ));
+    let column = |f: fn(&BodyState) -> f32| -> ArrayRef {
+        Arc::new(Float32Array::from(bodies.iter().map(f).collect::<Vec<_>>()))
+    };
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(UInt64Array::from(
+                (0..bodies.len() as u64).collect::<Vec<_>>(),
+            )),
+            column(|b| b.mass),
+            Arc::new(UInt64Array::from(
+                bodies.iter().map(|b| b.species as u64).collect::<Vec<_>>(),
+            )),
+            column(|b| b.position.x),
+            column(|b| b.position.y),
+            column(|b| b.velocity.x),
+            column(|b| b.velocity.y),
+            Arc::new(UInt32Array::from(
+                bodies.iter().map(|b| b.group).collect::<Vec<_>>(),
+            )),
+        ],
+    )
+    .map_err(other)?;
+
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None).map_err(other)?;
+    writer.write(&batch).map_err(other)?;
+    writer.close().map_err(other)?;
+    Ok(())
+}
+
+// Reads back a snapshot written by `write_snapshot`.
+pub fn read_snapshot<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+    let file = SerializedFileReader::new(File::open(path)?).map_err(other)?;
+    let mut reader = ParquetFileArrowReader::new(Arc::new(file));
+    let schema = reader.get_schema().map_err(other)?;
+    let metadata = |key: &str| {
+        schema
+            .metadata()
+            .get(key)
+            .cloned()
+            .ok_or_else(|| other(format!("no {} in the schema metadata", key)))
+    };
+    let mut snapshot = Snapshot {
+        tick: metadata("tick")?.parse().map_err(other)?,
+        time: metadata("time")?.parse().map_err(other)?,
+        bodies: Vec::new(),
+    };
+
+    for batch in reader.get_record_reader(ROW_GROUP).map_err(other)? {
+        let batch = batch.map_err(other)?;
+        let column = |name: &str| -> io::Result<&Float32Array> {
+            let index = batch.schema().index_of(name).map_err(other)?;
+            batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .ok_or_else(|| other(format!("{} is not a float32 column", name)))
+        };
+        let (mass, x, y) = (column("mass")?, column("x")?, column("y")?);
+        let (vx, vy) = (column("vx")?, column("vy")?);
+        let species = batch
+            .column(batch.schema().index_of("species").map_err(other)?)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .ok_or_else(|| other("species is not a uint64 column"))?;
+        // absent from snapshots written before groups
+        let groups = match batch.schema().index_of("group") {
+            Ok(index) => Some(
+                batch
+                    .column(index)
+                    .as_any()
+                    .downcast_ref::<UInt32Array>()
+                    .ok_or_else(|| other("group is not a uint32 column"))?,
+            ),
+            Err(_) => None,
+        };
+        for i in 0..batch.num_rows() {
+            snapshot.bodies.push(BodyState {
+                mass: mass.value(i),
+                species: species.value(i) as usize,
+                group: groups.map_or(0, |g| g.value(i)),
+                position: Position {
+                    x: x.value(i),
+                    y: y.value(i),
+                },
+                velocity: Velocity {
+                    x: vx.value(i),
+                    y: vy.value(i),
+                },
+            });
+        }
+    }
+    Ok(snapshot)
+}
+
+// The statistics table, buffered into row groups of `ROW_GROUP` samples.
+pub struct StatsWriter {
+    schema: Arc<Schema>,
+    writer: Option<ArrowWriter<File>>,
+    pending: Vec<StatsSample>,
+}
+
+impl StatsWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let float = |name: &str| Field::new(name, DataType::Float32, false);
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("tick", DataType::UInt64, false),
+            Field::new("time", DataType::Float64, false),
+            float("kinetic"),
+            float("potential"),
+            float("total"),
+            float("max_speed"),
+            float("min_pair_distance"),
+            Field::new("bodies", DataType::UInt32, false),
+            Field::new("escaped", DataType::UInt32, false),
+            float("virial_ratio"),
+            float("half_mass_radius"),
+            float("core_density"),
+        ]));
+        let writer =
+            ArrowWriter::try_new(File::create(path)?, schema.clone(), None).map_err(other)?;
+        Ok(StatsWriter {
+            schema,
+            writer: Some(writer),
+            pending: Vec::with_capacity(ROW_GROUP),
+        })
+    }
+
+    pub fn append(&mut self, sample: &StatsSample) -> io::Result<()> {
+        self.pending.push(*sample);
+        if self.pending.len() >= ROW_GROUP {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Writes the buffered samples as a row group.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let writer = match self.writer.as_mut() {
+            Some(writer) if !self.pending.is_empty() => writer,
+            _ => return Ok(()),
+        };
+        let rows = &self.pending;
+        let float = |f: fn(&StatsSample) -> f32| -> ArrayRef {
+            Arc::new(Float32Array::from(rows.iter().map(f).collect::<Vec<_>>()))
+        };
+        let count = |f: fn(&StatsSample) -> u32| -> ArrayRef {
+            Arc::new(UInt32Array::from(rows.iter().map(f).collect::<Vec<_>>()))
+        };
+        let batch = RecordBatch::try_new(
+            self.schema.clone(),
+            vec![
+                Arc::new(UInt64Array::from(
+                    rows.iter().map(|s| s.tick).collect::<Vec<_>>(),
+                )),
+                Arc::new(Float64Array::from(
+                    rows.iter().map(|s| s.time).collect::<Vec<_>>(),
+                )),
+                float(|s| s.kinetic),
+                float(|s| s.potential),
+                float(|s| s.kinetic + s.potential),
+                float(|s| s.max_speed),
+                float(|s| s.min_pair_distance),
+                count(|s| s.bodies),
+                count(|s| s.escaped),
+                float(|s| s.virial_ratio),
+                float(|s| s.half_mass_radius),
+                float(|s| s.core_density),
+            ],
+        )
+        .map_err(other)?;
+        writer.write(&batch).map_err(other)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    // Writes the remaining samples and the file footer; nothing more can be
+    // appended after this.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if let Some(mut writer) = self.writer.take() {
+            writer.close().map_err(other)?;
+        }
+        Ok(())
+    }
+}
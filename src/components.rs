@@ -0,0 +1,95 @@
+// Components every body carries, plus tags added at runtime.
+
+#[derive(Copy, Clone)]
+pub struct Body;
+
+#[derive(Copy, Clone)]
+pub struct Mass {
+    pub mass: f32,
+}
+
+#[derive(Copy, Clone)]
+pub struct Species {
+    pub id: usize,
+}
+
+// Names a body for as long as it exists: its index in the initial conditions,
+// or the next unused number for bodies added during the run. It moves with the
+// rest of the body's state when storage is sorted, so events and catalogs
+// identify bodies by it rather than by their position in storage.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BodyId(pub u64);
+
+// Which part of the initial conditions a body belongs to, e.g. which galaxy of
+// a merger, for per-group statistics and colours.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Group(pub u32);
+
+#[derive(Copy, Clone)]
+pub struct Acceleration {
+    pub x: f32,
+    pub y: f32,
+}
+
+// Time derivative of acceleration, used by the Hermite integrator.
+#[derive(Copy, Clone)]
+pub struct Jerk {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Copy, Clone)]
+pub struct Velocity {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Copy, Clone)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+}
+
+// Position at the start of the current tick, for rendering in between ticks.
+#[derive(Copy, Clone)]
+pub struct PreviousPosition {
+    pub x: f32,
+    pub y: f32,
+}
+
+// The body `--elements-bodies` orbits are measured relative to.
+#[derive(Copy, Clone)]
+pub struct Primary;
+
+// A body whose orbital elements are logged; `index` is its position in the
+// initial conditions.
+#[derive(Copy, Clone)]
+pub struct Orbiter {
+    pub index: usize,
+}
+
+// Local surface density around a body, estimated from its nearest neighbours
+// every tick when enabled with `SimulationBuilder::density_estimate`.
+#[derive(Copy, Clone)]
+pub struct Density {
+    pub rho: f32,
+}
+
+// The clump a body was last found in by `FriendsOfFriends`, as an index into
+// the `ClumpCatalog`; bodies in no clump have none.
+#[derive(Copy, Clone, PartialEq)]
+pub struct ClumpId(pub u32);
+
+// Orientation of an extended body, counterclockwise from +x in radians, and
+// how fast it turns in radians per unit time. Only bodies given one with
+// `SimulationBuilder::spin` have it; nothing exerts a torque on them, so each
+// turns at a constant rate.
+#[derive(Copy, Clone)]
+pub struct Spin {
+    pub angle: f32,
+    pub rate: f32,
+}
+
+// Tag for bodies moving faster than the escape velocity of the remaining mass.
+#[derive(Copy, Clone)]
+pub struct Escaped;
@@ -0,0 +1,144 @@
+// Runtime adjustment of a running simulation. Commands are lines of text read
+// from stdin and/or a control file, queued by background threads and applied
+// at the start of each tick by `CommandSystem`:
+//
+//   set dt <seconds>|wall    fixed timestep, or back to wall-clock time
+//   set g <value>            gravitational constant
+//   set epsilon <value>      overlap distance
+//   snapshot now             write the current bodies to <prefix>-<tick>.csv
+//
+// The control file is re-read in full whenever its modification time changes,
+// so appending a line and saving runs the whole file again; tools that replace
+// the file with just the new commands avoid repeats.
+use crate::components::*;
+use crate::resources::*;
+use crate::scenarios::BodyState;
+use crate::snapshot;
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use SmolECS::{component::*, system::*, world::*};
+
+pub struct CommandChannel {
+    commands: Mutex<Receiver<String>>,
+    snapshot_prefix: String,
+}
+
+impl CommandChannel {
+    pub fn new(stdin: bool, control_file: Option<String>, snapshot_prefix: &str) -> Self {
+        let (sender, commands) = mpsc::channel();
+        if stdin {
+            let sender = sender.clone();
+            thread::spawn(move || read_stdin(sender));
+        }
+        if let Some(path) = control_file {
+            thread::spawn(move || watch_file(path, sender));
+        }
+        CommandChannel {
+            commands: Mutex::new(commands),
+            snapshot_prefix: snapshot_prefix.to_string(),
+        }
+    }
+
+    fn pending(&self) -> Vec<String> {
+        self.commands.lock().unwrap().try_iter().collect()
+    }
+}
+
+fn read_stdin(sender: Sender<String>) {
+    for line in std::io::stdin().lock().lines() {
+        match line {
+            Ok(line) => {
+                if sender.send(line).is_err() {
+                    return;
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+fn watch_file(path: String, sender: Sender<String>) {
+    let mut seen: Option<SystemTime> = None;
+    loop {
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified != seen {
+            seen = modified;
+            if let Ok(text) = std::fs::read_to_string(&path) {
+                for line in text.lines() {
+                    if sender.send(line.to_string()).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+pub struct CommandSystem;
+impl<'d, 'w: 'd> System<'d, 'w, World> for CommandSystem {
+    type SystemData = (
+        Read<'d, CommandChannel>,
+        Write<'d, Time>,
+        Write<'d, Gravity>,
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Species>,
+        ReadComp<'d, Group>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+    );
+
+    fn run(
+        &self,
+        (channel, mut time, mut gravity, masses, species, groups, positions, vels): Self::SystemData,
+    ) {
+        for line in channel.pending() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let value = |word: &str| {
+                word.parse::<f32>()
+                    .map_err(|_| format!("'{}' is not a number", word))
+            };
+            let result = match words.as_slice() {
+                [] => continue,
+                ["set", "dt", "wall"] => {
+                    time.step = None;
+                    Ok(())
+                }
+                ["set", "dt", dt] => match dt.parse::<f64>() {
+                    Ok(dt) if dt > 0.0 => {
+                        time.step = Some(dt);
+                        Ok(())
+                    }
+                    _ => Err(format!("'{}' is not a positive timestep", dt)),
+                },
+                ["set", "g", g] => value(g).map(|g| gravity.g = g),
+                ["set", "epsilon", epsilon] => value(epsilon).map(|e| gravity.epsilon = e),
+                ["set", name, _] => Err(format!("unknown parameter '{}'", name)),
+                ["snapshot", "now"] => {
+                    let bodies: Vec<BodyState> = (&masses, &species, &groups, &positions, &vels)
+                        .join()
+                        .map(|(mass, species, group, position, velocity)| BodyState {
+                            mass: mass.mass,
+                            species: species.id,
+                            group: group.0,
+                            position: *position,
+                            velocity: *velocity,
+                        })
+                        .collect();
+                    let path = format!("{}-{}.csv", channel.snapshot_prefix, time.tick);
+                    snapshot::write(&path, time.tick, time.total, &bodies)
+                        .map(|_| eprintln!("wrote {}", path))
+                        .map_err(|e| format!("failed to write {}: {}", path, e))
+                }
+                _ => Err("unknown command".to_string()),
+            };
+            match result {
+                Ok(()) => eprintln!("tick {}: {}", time.tick, line.trim()),
+                Err(e) => eprintln!("tick {}: {}: {}", time.tick, line.trim(), e),
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// Body-by-body comparison of two states of a run, for `diff`: two runs that
+// should agree (the same seed twice, or two force backends) are checkpointed or
+// snapshotted at the same tick and the largest and RMS differences of each body
+// quantity show whether, and where, they went apart. Bodies are matched by
+// their order in the files.
+use crate::scenarios::BodyState;
+use std::fmt;
+
+// How a quantity differs over the bodies.
+#[derive(Copy, Clone, Default)]
+pub struct Spread {
+    pub max: f64,
+    pub rms: f64,
+    // the body the largest difference is at
+    pub worst: usize,
+}
+
+impl fmt::Display for Spread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "max {:.3e} (body {}), rms {:.3e}",
+            self.max, self.worst, self.rms
+        )
+    }
+}
+
+fn spread(differences: impl Iterator<Item = f64>) -> Spread {
+    let mut spread = Spread::default();
+    let (mut squares, mut n) = (0.0, 0);
+    for (i, d) in differences.enumerate() {
+        if d > spread.max || d.is_nan() {
+            spread.max = d;
+            spread.worst = i;
+        }
+        squares += d * d;
+        n += 1;
+    }
+    if n > 0 {
+        spread.rms = (squares / n as f64).sqrt();
+    }
+    spread
+}
+
+pub struct StateDiff {
+    pub bodies: (usize, usize),
+    // distances between the positions and velocities of each body
+    pub position: Spread,
+    pub velocity: Spread,
+    pub mass: Spread,
+    // bodies whose species or group differ
+    pub relabelled: usize,
+}
+
+impl StateDiff {
+    // Compares the bodies both states have, in order.
+    pub fn between(a: &[BodyState], b: &[BodyState]) -> Self {
+        let pairs = || a.iter().zip(b);
+        let distance =
+            |a: (f32, f32), b: (f32, f32)| (a.0 as f64 - b.0 as f64).hypot(a.1 as f64 - b.1 as f64);
+        StateDiff {
+            bodies: (a.len(), b.len()),
+            position: spread(pairs().map(|(a, b)| {
+                distance((a.position.x, a.position.y), (b.position.x, b.position.y))
+            })),
+            velocity: spread(pairs().map(|(a, b)| {
+                distance((a.velocity.x, a.velocity.y), (b.velocity.x, b.velocity.y))
+            })),
+            mass: spread(pairs().map(|(a, b)| (a.mass as f64 - b.mass as f64).abs())),
+            relabelled: pairs()
+                .filter(|(a, b)| a.species != b.species || a.group != b.group)
+                .count(),
+        }
+    }
+
+    // Whether the states have the same bodies, with no difference larger than
+    // the matching tolerance.
+    pub fn within(&self, position: f64, velocity: f64, mass: f64) -> bool {
+        self.bodies.0 == self.bodies.1
+            && self.relabelled == 0
+            && self.position.max <= position
+            && self.velocity.max <= velocity
+            && self.mass.max <= mass
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.bodies.0 == self.bodies.1 {
+            writeln!(f, "bodies:   {}", self.bodies.0)?;
+        } else {
+            writeln!(
+                f,
+                "bodies:   {} against {}, compared up to {}",
+                self.bodies.0,
+                self.bodies.1,
+                self.bodies.0.min(self.bodies.1)
+            )?;
+        }
+        writeln!(f, "position: {}", self.position)?;
+        writeln!(f, "velocity: {}", self.velocity)?;
+        writeln!(f, "mass:     {}", self.mass)?;
+        write!(f, "species or group differ for {} bodies", self.relabelled)
+    }
+}
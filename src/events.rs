@@ -0,0 +1,223 @@
+// Discrete events recorded by systems as they happen, one JSON object per line:
+//
+//   {"tick":1200,"time":1.2,"event":"escape","body":17}
+//
+// Bodies are identified by their `BodyId`, which stays the same when bodies
+// are removed, split or sorted. Wind loss by `MassEvolution` is not logged,
+// only the bodies it merges.
+use crate::components::*;
+use crate::resources::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write as IoWrite};
+use std::sync::Mutex;
+use SmolECS::{component::*, system::*, world::*};
+
+pub enum Event {
+    Escape {
+        body: u64,
+    },
+    CloseEncounter {
+        a: u64,
+        b: u64,
+        distance: f32,
+    },
+    Periapsis {
+        body: u64,
+        distance: f32,
+    },
+    BinaryFormed {
+        a: u64,
+        b: u64,
+        semi_major_axis: f64,
+        eccentricity: f64,
+    },
+    BinaryDisrupted {
+        a: u64,
+        b: u64,
+    },
+    Merge {
+        host: u64,
+        absorbed: u64,
+    },
+}
+
+pub struct EventLog {
+    writer: Option<BufWriter<File>>,
+}
+
+impl EventLog {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        Ok(EventLog {
+            writer: Some(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    pub fn none() -> Self {
+        EventLog { writer: None }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    pub fn record(&mut self, time: &Time, event: Event) {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        let fields = match event {
+            Event::Escape { body } => format!("\"event\":\"escape\",\"body\":{}", body),
+            Event::CloseEncounter { a, b, distance } => format!(
+                "\"event\":\"close_encounter\",\"a\":{},\"b\":{},\"distance\":{}",
+                a, b, distance
+            ),
+            Event::Periapsis { body, distance } => format!(
+                "\"event\":\"periapsis\",\"body\":{},\"distance\":{}",
+                body, distance
+            ),
+            Event::BinaryFormed {
+                a,
+                b,
+                semi_major_axis,
+                eccentricity,
+            } => format!(
+                "\"event\":\"binary_formed\",\"a\":{},\"b\":{},\"semi_major_axis\":{},\"eccentricity\":{}",
+                a, b, semi_major_axis, eccentricity
+            ),
+            Event::BinaryDisrupted { a, b } => {
+                format!("\"event\":\"binary_disrupted\",\"a\":{},\"b\":{}", a, b)
+            }
+            Event::Merge { host, absorbed } => format!(
+                "\"event\":\"merge\",\"host\":{},\"absorbed\":{}",
+                host, absorbed
+            ),
+        };
+        writeln!(
+            writer,
+            "{{\"tick\":{},\"time\":{},{}}}",
+            time.tick, time.total, fields
+        )
+        .expect("failed to write event log");
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Records a close encounter whenever a pair comes within `radius` of each
+// other, once per approach.
+pub struct CloseEncounters {
+    pub radius: f32,
+    // pairs closer than `radius` at the last check, smaller id first
+    close: Mutex<HashSet<(u64, u64)>>,
+}
+
+impl CloseEncounters {
+    pub fn new(radius: f32) -> Self {
+        CloseEncounters {
+            radius,
+            close: Mutex::new(HashSet::new()),
+        }
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for CloseEncounters {
+    type SystemData = (
+        ReadComp<'d, Position>,
+        ReadComp<'d, BodyId>,
+        Read<'d, Time>,
+        Write<'d, EventLog>,
+    );
+
+    fn run(&self, (positions, ids, time, mut log): Self::SystemData) {
+        let (points, ids): (Vec<(f32, f32)>, Vec<u64>) = (&positions, &ids)
+            .join()
+            .map(|(p, id)| ((p.x, p.y), id.0))
+            .unzip();
+        let cells = crate::cells::CellList::build(&points, self.radius);
+
+        let mut close = self.close.lock().unwrap();
+        let mut now = HashSet::new();
+        for (i, &(x, y)) in points.iter().enumerate() {
+            for j in cells.nearby((x, y)).filter(|&j| j > i) {
+                let (dx, dy) = (points[j].0 - x, points[j].1 - y);
+                let distance = (dx * dx + dy * dy).sqrt();
+                if distance < self.radius {
+                    let pair = (ids[i].min(ids[j]), ids[i].max(ids[j]));
+                    if !close.contains(&pair) {
+                        log.record(
+                            &time,
+                            Event::CloseEncounter {
+                                a: pair.0,
+                                b: pair.1,
+                                distance,
+                            },
+                        );
+                    }
+                    now.insert(pair);
+                }
+            }
+        }
+        *close = now;
+    }
+}
+
+// Records each periapsis passage of an `Orbiter` about the `Primary`, when the
+// radial velocity turns from approaching to receding.
+pub struct PeriapsisPassages {
+    approaching: Mutex<HashMap<u64, bool>>,
+}
+
+impl PeriapsisPassages {
+    pub fn new() -> Self {
+        PeriapsisPassages {
+            approaching: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for PeriapsisPassages {
+    fn default() -> Self {
+        PeriapsisPassages::new()
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for PeriapsisPassages {
+    type SystemData = (
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        ReadComp<'d, Primary>,
+        ReadComp<'d, Orbiter>,
+        ReadComp<'d, BodyId>,
+        Read<'d, Time>,
+        Write<'d, EventLog>,
+    );
+
+    fn run(&self, (positions, vels, primaries, orbiters, ids, time, mut log): Self::SystemData) {
+        let (primary_pos, primary_vel) = match (&primaries, &positions, &vels).join().next() {
+            Some((_, pos, vel)) => (*pos, *vel),
+            None => return,
+        };
+
+        let mut approaching = self.approaching.lock().unwrap();
+        for (_, id, pos, vel) in (&orbiters, &ids, &positions, &vels).join() {
+            let (rx, ry) = (pos.x - primary_pos.x, pos.y - primary_pos.y);
+            let (vx, vy) = (vel.x - primary_vel.x, vel.y - primary_vel.y);
+            let inward = rx * vx + ry * vy < 0.0;
+            if approaching.insert(id.0, inward) == Some(true) && !inward {
+                log.record(
+                    &time,
+                    Event::Periapsis {
+                        body: id.0,
+                        distance: (rx * rx + ry * ry).sqrt(),
+                    },
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// HDF5 trajectories: a group per frame named `frame_<n>` (zero-padded so the
+// names sort in order) with `tick` and `time` attributes and one dataset per
+// column of `snapshot::write_csv`; files without a `group` dataset read as all
+// group 0. Only built with the `hdf5` feature.
+use crate::components::{Position, Velocity};
+use crate::scenarios::BodyState;
+use crate::snapshot::Snapshot;
+use std::io;
+use std::path::Path;
+
+fn other<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+pub fn write<P: AsRef<Path>>(path: P, frames: &[Snapshot]) -> io::Result<()> {
+    let file = hdf5::File::create(path).map_err(other)?;
+    for (i, frame) in frames.iter().enumerate() {
+        let group = file
+            .create_group(&format!("frame_{:06}", i))
+            .map_err(other)?;
+        group
+            .new_attr::<u64>()
+            .create("tick", ())
+            .and_then(|a| a.write_scalar(&frame.tick))
+            .map_err(other)?;
+        group
+            .new_attr::<f64>()
+            .create("time", ())
+            .and_then(|a| a.write_scalar(&frame.time))
+            .map_err(other)?;
+
+        let n = frame.bodies.len();
+        let column = |name: &str, f: fn(&BodyState) -> f32| {
+            let values: Vec<f32> = frame.bodies.iter().map(f).collect();
+            group
+                .new_dataset::<f32>()
+                .create(name, n)
+                .and_then(|d| d.write_raw(&values))
+                .map_err(other)
+        };
+        column("mass", |b| b.mass)?;
+        column("x", |b| b.position.x)?;
+        column("y", |b| b.position.y)?;
+        column("vx", |b| b.velocity.x)?;
+        column("vy", |b| b.velocity.y)?;
+        let species: Vec<u64> = frame.bodies.iter().map(|b| b.species as u64).collect();
+        group
+            .new_dataset::<u64>()
+            .create("species", n)
+            .and_then(|d| d.write_raw(&species))
+            .map_err(other)?;
+        let groups: Vec<u32> = frame.bodies.iter().map(|b| b.group).collect();
+        group
+            .new_dataset::<u32>()
+            .create("group", n)
+            .and_then(|d| d.write_raw(&groups))
+            .map_err(other)?;
+    }
+    Ok(())
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<Snapshot>> {
+    let file = hdf5::File::open(path).map_err(other)?;
+    let mut names = file.member_names().map_err(other)?;
+    names.retain(|name| name.starts_with("frame_"));
+    names.sort();
+
+    let mut frames = Vec::with_capacity(names.len());
+    for name in names {
+        let group = file.group(&name).map_err(other)?;
+        let column = |name: &str| {
+            group
+                .dataset(name)
+                .and_then(|d| d.read_raw::<f32>())
+                .map_err(other)
+        };
+        let (mass, x, y) = (column("mass")?, column("x")?, column("y")?);
+        let (vx, vy) = (column("vx")?, column("vy")?);
+        let species = group
+            .dataset("species")
+            .and_then(|d| d.read_raw::<u64>())
+            .map_err(other)?;
+        let n = mass.len();
+        let groups = if group
+            .member_names()
+            .map_err(other)?
+            .iter()
+            .any(|m| m == "group")
+        {
+            group
+                .dataset("group")
+                .and_then(|d| d.read_raw::<u32>())
+                .map_err(other)?
+        } else {
+            vec![0; n]
+        };
+        if [
+            x.len(),
+            y.len(),
+            vx.len(),
+            vy.len(),
+            species.len(),
+            groups.len(),
+        ] != [n; 6]
+        {
+            return Err(other(format!("{}: columns of different lengths", name)));
+        }
+        frames.push(Snapshot {
+            tick: group
+                .attr("tick")
+                .and_then(|a| a.read_scalar())
+                .map_err(other)?,
+            time: group
+                .attr("time")
+                .and_then(|a| a.read_scalar())
+                .map_err(other)?,
+            bodies: (0..n)
+                .map(|i| BodyState {
+                    mass: mass[i],
+                    species: species[i] as usize,
+                    group: groups[i],
+                    position: Position { x: x[i], y: y[i] },
+                    velocity: Velocity { x: vx[i], y: vy[i] },
+                })
+                .collect(),
+        });
+    }
+    Ok(frames)
+}
@@ -0,0 +1,115 @@
+// Recent states of every body kept in memory for `Simulation::body_history`,
+// so that applications embedding the library can draw trails or inspect
+// recent trajectories without recording them themselves.
+use crate::components::*;
+use crate::resources::Time;
+use crate::scenarios::BodyState;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Deref;
+use SmolECS::{component::*, entity::*, system::*, world::*};
+
+// A body's state at the end of a tick.
+#[derive(Copy, Clone)]
+pub struct HistorySample {
+    pub tick: u64,
+    pub time: f64,
+    pub state: BodyState,
+}
+
+// A ring buffer of the last `depth` samples of each body. Samples are kept
+// per entity, so a body keeps its history when bodies before it in storage
+// order are removed; a depth of 0 records nothing.
+pub struct BodyHistory {
+    depth: usize,
+    samples: HashMap<Entity, VecDeque<HistorySample>>,
+}
+
+impl BodyHistory {
+    pub fn new(depth: usize) -> Self {
+        BodyHistory {
+            depth,
+            samples: HashMap::new(),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // The samples of `ent`, oldest first.
+    pub fn of(&self, ent: &Entity) -> Vec<HistorySample> {
+        self.samples
+            .get(ent)
+            .map_or_else(Vec::new, |samples| samples.iter().copied().collect())
+    }
+
+    pub fn forget(&mut self, ent: &Entity) {
+        self.samples.remove(ent);
+    }
+
+    // Gives `slots[i]` the samples of `slots[order[i]]`, for `SortBodies`.
+    pub fn permute(&mut self, slots: &[Entity], order: &[usize]) {
+        let moved: Vec<Option<VecDeque<HistorySample>>> = order
+            .iter()
+            .map(|&from| self.samples.remove(&slots[from]))
+            .collect();
+        for (ent, samples) in slots.iter().zip(moved) {
+            if let Some(samples) = samples {
+                self.samples.insert(*ent, samples);
+            }
+        }
+    }
+}
+
+// Appends the current state of every body to the `BodyHistory`, dropping the
+// oldest sample of those already at its depth and the samples of bodies that
+// no longer exist.
+pub struct RecordHistory;
+impl<'d, 'w: 'd> System<'d, 'w, World> for RecordHistory {
+    type SystemData = (
+        Read<'d, EntityStorage>,
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Species>,
+        ReadComp<'d, Group>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        Read<'d, Time>,
+        Write<'d, BodyHistory>,
+    );
+
+    fn run(
+        &self,
+        (ents, masses, species, groups, positions, vels, time, mut history): Self::SystemData,
+    ) {
+        let depth = history.depth;
+        if depth == 0 {
+            return;
+        }
+
+        let mut alive = HashSet::new();
+        for (mass, species, group, position, velocity, ent) in
+            (&masses, &species, &groups, &positions, &vels, ents.deref()).join()
+        {
+            let samples = history
+                .samples
+                .entry(*ent)
+                .or_insert_with(|| VecDeque::with_capacity(depth));
+            if samples.len() == depth {
+                samples.pop_front();
+            }
+            samples.push_back(HistorySample {
+                tick: time.tick,
+                time: time.total,
+                state: BodyState {
+                    mass: mass.mass,
+                    species: species.id,
+                    group: group.0,
+                    position: *position,
+                    velocity: *velocity,
+                },
+            });
+            alive.insert(*ent);
+        }
+        history.samples.retain(|ent, _| alive.contains(ent));
+    }
+}
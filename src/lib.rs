@@ -0,0 +1,37 @@
+pub mod analysis;
+pub mod api;
+pub mod cells;
+pub mod checkpoint;
+#[cfg(feature = "columnar")]
+pub mod columnar;
+pub mod components;
+pub mod control;
+pub mod diff;
+pub mod events;
+#[cfg(feature = "hdf5")]
+pub mod h5;
+pub mod history;
+pub mod manifest;
+pub mod metrics;
+pub mod orbits;
+pub mod output;
+pub mod pacing;
+pub mod render;
+pub mod resources;
+pub mod restricted;
+pub mod scenario_file;
+pub mod scenarios;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod server;
+pub mod simulation;
+pub mod snapshot;
+pub mod stats;
+pub mod sweep;
+pub mod systems;
+pub mod trajectory;
+
+pub use simulation::{
+    Configuration, DivergenceReport, Integrator, ReversalReport, RunSummary, Simulation,
+    SimulationBuilder, Stage,
+};
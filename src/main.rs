@@ -1,243 +1,1643 @@
-use rand::prelude::*;
-use std::num;
-use std::sync::{Arc, Mutex};
-use SmolECS::{component::*, entity::*, rayon::*, system::*, world::*};
-use clap::{Arg, App};
-
-#[derive(Copy, Clone)]
-pub struct Body;
-
-#[derive(Copy, Clone)]
-pub struct Mass {
-    mass: f32,
-}
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use smolnbody::analysis::{
+    BinaryCatalog, Distributions, FriendsOfFriends, OrbitalElementsOutput, PairCorrelation,
+    RotationCurveOutput,
+};
+use smolnbody::api::Api;
+use smolnbody::cells;
+use smolnbody::checkpoint::Checkpoint;
+use smolnbody::control::CommandChannel;
+use smolnbody::diff::StateDiff;
+use smolnbody::events::EventLog;
+use smolnbody::manifest::Manifest;
+use smolnbody::render::{self, View};
+use smolnbody::resources::{Exclusions, Gravity, InteractionMatrix, Softening};
+use smolnbody::restricted;
+use smolnbody::scenario_file;
+use smolnbody::metrics::Metrics;
+use smolnbody::output::OutputQueue;
+use smolnbody::pacing::Pacer;
+use smolnbody::scenarios::{self, BodyState};
+use smolnbody::server::{self, Command, LevelOfDetail, LodEncoder, Server};
+use smolnbody::snapshot::{self, Compression, Retention, SnapshotSeries};
+use smolnbody::stats::{StatsOutput, StatsReport};
+use smolnbody::sweep::{self, Sweep};
+use smolnbody::systems::{DynamicalFriction, MassEvolution, TidalDisruption};
+use smolnbody::trajectory;
+use smolnbody::{Integrator, SimulationBuilder};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-#[derive(Copy, Clone)]
-pub struct Acceleration {
-    x: f32,
-    y: f32,
-}
+fn main() {
+	let app = App::new("nBody")
+		.version("1.0")
+		.about("n-body simulations and tools for their output")
+		.author("SmolECS")
+		.setting(AppSettings::SubcommandRequiredElseHelp)
+		.subcommand(SubCommand::with_name("run")
+			.about("runs an n-body simulation")
+			.arg(Arg::with_name("count")
+				.short("n")
+				.long("count")
+				.help("the amount of bodies to be simulated")
+				.takes_value(true)
+				.required_unless_one(&["scenario", "scenario-file", "resume"]))
+			.arg(Arg::with_name("scenario")
+				.long("scenario")
+				.help("start from a named preset instead of random bodies")
+				.takes_value(true)
+				.possible_values(scenarios::NAMES))
+			.arg(Arg::with_name("scenario-file")
+				.long("scenario-file")
+				.help("load bodies and nested orbits from a TOML scenario description")
+				.takes_value(true)
+				.conflicts_with("scenario"))
+			.arg(Arg::with_name("potential-output")
+				.long("potential-output")
+				.help("write the co-rotating effective potential and Lagrange points of --scenario restricted-three-body to this CSV file")
+				.takes_value(true)
+				.requires("scenario"))
+			.arg(Arg::with_name("resume")
+				.long("resume")
+				.help("continue from a checkpoint written by --checkpoint")
+				.takes_value(true)
+				.conflicts_with_all(&["scenario", "scenario-file"]))
+			.arg(Arg::with_name("velocities")
+				.long("velocities")
+				.help("initial velocities: as generated, or circular orbits about the enclosed mass")
+				.takes_value(true)
+				.possible_values(&["random", "circular"]))
+			.arg(Arg::with_name("seed")
+				.long("seed")
+				.help("seed for the random bodies, tracers, shadow-run offsets and stochastic systems such as --tidal-threshold; resumed runs continue the checkpoint's generator (default: drawn at random, and recorded in --manifest)")
+				.takes_value(true))
+			.arg(Arg::with_name("spin")
+				.long("spin")
+				.help("add solid-body rotation to the initial bodies to give them this spin parameter lambda")
+				.takes_value(true)
+				.conflicts_with("resume"))
+			.arg(Arg::with_name("tracers")
+				.long("tracers")
+				.help("add this many massless test particles over the initial bodies, which feel gravity but exert none")
+				.takes_value(true)
+				.conflicts_with("resume"))
+			.arg(Arg::with_name("dt")
+				.long("dt")
+				.help("use a fixed timestep instead of wall-clock time (scenarios default to their own)")
+				.takes_value(true))
+			.arg(Arg::with_name("ticks")
+				.long("ticks")
+				.help("the number of ticks to simulate (default 100000, or until interrupted with --serve)")
+				.takes_value(true))
+			.arg(Arg::with_name("species")
+				.long("species")
+				.help("the number of species bodies are randomly assigned to")
+				.takes_value(true))
+			.arg(Arg::with_name("interaction-matrix")
+				.long("interaction-matrix")
+				.help("force coefficients between species, rows separated by ';' (e.g. \"1,-0.5;0.3,1\")")
+				.takes_value(true))
+			.arg(Arg::with_name("exclude")
+				.long("exclude")
+				.help("skip the mutual force within a group or between two, pairs separated by ';' (e.g. \"1;2,3\")")
+				.takes_value(true))
+			.arg(Arg::with_name("integrator")
+				.long("integrator")
+				.help("the integration scheme (default euler, or leapfrog with --reverse-test)")
+				.takes_value(true)
+				.possible_values(&["euler", "hermite4", "leapfrog"]))
+			.arg(Arg::with_name("relativistic")
+				.long("relativistic")
+				.help("add the first post-Newtonian correction to gravity"))
+			.arg(Arg::with_name("speed-of-light")
+				.long("speed-of-light")
+				.help("the speed of light in simulation units for --relativistic (physical scenarios set their own)")
+				.takes_value(true))
+			.arg(Arg::with_name("friction-mass")
+				.long("friction-mass")
+				.help("apply Chandrasekhar dynamical friction to bodies at least this massive")
+				.takes_value(true))
+			.arg(Arg::with_name("friction-radius")
+				.long("friction-radius")
+				.help("radius the background density and dispersion are measured over (default 1.0)")
+				.takes_value(true))
+			.arg(Arg::with_name("coulomb-log")
+				.long("coulomb-log")
+				.help("the Coulomb logarithm ln(Lambda) for dynamical friction (default 3.0)")
+				.takes_value(true))
+			.arg(Arg::with_name("wind-rate")
+				.long("wind-rate")
+				.help("fraction of their mass massive bodies lose to a wind per unit time (negative to grow)")
+				.takes_value(true))
+			.arg(Arg::with_name("accretion-radius")
+				.long("accretion-radius")
+				.help("massive bodies absorb lighter bodies and tracers that come this close")
+				.takes_value(true))
+			.arg(Arg::with_name("evolution-mass")
+				.long("evolution-mass")
+				.help("the least mass of a body that loses mass to --wind-rate and accretes (default 0, every body but tracers)")
+				.takes_value(true))
+			.arg(Arg::with_name("max-substeps")
+				.long("max-substeps")
+				.help("integrate close pairs in up to this many substeps of the tick")
+				.takes_value(true))
+			.arg(Arg::with_name("substep-radius")
+				.long("substep-radius")
+				.help("separation below which pairs are substepped with --max-substeps (default 0.1)")
+				.takes_value(true))
+			.arg(Arg::with_name("g-constant")
+				.long("g-constant")
+				.help("the gravitational constant, e.g. 1 for dimensionless units (default: the scenario's, or SI for random bodies)")
+				.takes_value(true)
+				.conflicts_with("resume"))
+			.arg(Arg::with_name("force-exponent")
+				.long("force-exponent")
+				.help("the exponent p of the 1/r^p force law (default 2)")
+				.takes_value(true))
+			.arg(Arg::with_name("softening")
+				.long("softening")
+				.help("soften gravity below about this separation, as a Plummer-equivalent length")
+				.takes_value(true))
+			.arg(Arg::with_name("softening-kernel")
+				.long("softening-kernel")
+				.help("the softening kernel for --softening (default plummer)")
+				.takes_value(true)
+				.possible_values(&["plummer", "spline"])
+				.requires("softening"))
+			.arg(Arg::with_name("compensated-sum")
+				.long("compensated-sum")
+				.help("add up the gravity on each body with Kahan summation, for precision at large N (Euler only)"))
+			.arg(Arg::with_name("far-field-interval")
+				.long("far-field-interval")
+				.help("recompute forces from bodies beyond --near-radius only every K ticks")
+				.takes_value(true))
+			.arg(Arg::with_name("near-radius")
+				.long("near-radius")
+				.help("separation below which pairs are summed every tick with --far-field-interval (default 1.0)")
+				.takes_value(true))
+			.arg(Arg::with_name("tidal-threshold")
+				.long("tidal-threshold")
+				.help("split bodies whose tidal stress exceeds this multiple of their self-gravity")
+				.takes_value(true))
+			.arg(Arg::with_name("tidal-fragments")
+				.long("tidal-fragments")
+				.help("the number of fragments a disrupted body splits into (default 2)")
+				.takes_value(true))
+			.arg(Arg::with_name("tidal-dispersion")
+				.long("tidal-dispersion")
+				.help("the maximum random velocity kick given to each fragment (default 0.1)")
+				.takes_value(true))
+			.arg(Arg::with_name("rotation-curve")
+				.long("rotation-curve")
+				.help("write the binned circular velocity about the centre of mass to this CSV file")
+				.takes_value(true))
+			.arg(Arg::with_name("rotation-curve-interval")
+				.long("rotation-curve-interval")
+				.help("ticks between rotation curve outputs (default 100)")
+				.takes_value(true))
+			.arg(Arg::with_name("rotation-curve-bins")
+				.long("rotation-curve-bins")
+				.help("the number of radial bins in the rotation curve (default 20)")
+				.takes_value(true))
+			.arg(Arg::with_name("elements")
+				.long("elements")
+				.help("write osculating orbital elements of --elements-bodies about --elements-primary to this CSV file")
+				.takes_value(true)
+				.requires("elements-bodies"))
+			.arg(Arg::with_name("elements-primary")
+				.long("elements-primary")
+				.help("index of the body the orbits are measured about (default 0)")
+				.takes_value(true))
+			.arg(Arg::with_name("elements-bodies")
+				.long("elements-bodies")
+				.help("comma-separated indices of the bodies to log orbital elements for")
+				.takes_value(true))
+			.arg(Arg::with_name("elements-interval")
+				.long("elements-interval")
+				.help("ticks between orbital element outputs (default 100)")
+				.takes_value(true))
+			.arg(Arg::with_name("correlation-interval")
+				.long("correlation-interval")
+				.help("ticks between pair correlation g(r) outputs in the statistics output")
+				.takes_value(true)
+				.requires("stats-output"))
+			.arg(Arg::with_name("correlation-rmax")
+				.long("correlation-rmax")
+				.help("the largest separation g(r) is computed for (default 1.0)")
+				.takes_value(true))
+			.arg(Arg::with_name("correlation-bins")
+				.long("correlation-bins")
+				.help("the number of radial bins in g(r) (default 50)")
+				.takes_value(true))
+			.arg(Arg::with_name("histogram-interval")
+				.long("histogram-interval")
+				.help("ticks between speed and nearest-neighbour distance histograms in the statistics output")
+				.takes_value(true)
+				.requires("stats-output"))
+			.arg(Arg::with_name("histogram-bins")
+				.long("histogram-bins")
+				.help("the number of bins in each histogram (default 50)")
+				.takes_value(true))
+			.arg(Arg::with_name("binaries-interval")
+				.long("binaries-interval")
+				.help("ticks between binary catalog outputs in the statistics output")
+				.takes_value(true)
+				.requires("stats-output"))
+			.arg(Arg::with_name("binaries-radius")
+				.long("binaries-radius")
+				.help("the widest separation a binary is searched for at (default 0.1)")
+				.takes_value(true))
+			.arg(Arg::with_name("clumps-interval")
+				.long("clumps-interval")
+				.help("ticks between friends-of-friends clump searches, written to the statistics output, with the last clump mass function printed at the end")
+				.takes_value(true))
+			.arg(Arg::with_name("linking-length")
+				.long("linking-length")
+				.help("the separation below which bodies are linked into the same clump (default 0.1)")
+				.takes_value(true)
+				.requires("clumps-interval"))
+			.arg(Arg::with_name("clump-members")
+				.long("clump-members")
+				.help("the fewest bodies a clump is made of (default 10)")
+				.takes_value(true)
+				.requires("clumps-interval"))
+			.arg(Arg::with_name("detect-escapes")
+				.long("detect-escapes")
+				.help("tag bodies faster than the local escape velocity and count them in the statistics"))
+			.arg(Arg::with_name("remove-escaped")
+				.long("remove-escaped")
+				.help("delete bodies once they have escaped")
+				.requires("detect-escapes"))
+			.arg(Arg::with_name("sort-interval")
+				.long("sort-interval")
+				.help("ticks between re-sorting body storage along a Z-order curve for cache locality")
+				.takes_value(true)
+				.conflicts_with("far-field-interval"))
+			.arg(Arg::with_name("stats-output")
+				.long("stats-output")
+				.help("append per-tick statistics to this CSV file")
+				.takes_value(true))
+			.arg(Arg::with_name("stats-interval")
+				.long("stats-interval")
+				.help("ticks between statistics samples (default 1)")
+				.takes_value(true))
+			.arg(Arg::with_name("serve")
+				.long("serve")
+				.help("run headless, streaming positions over WebSocket to viewers connecting to this address")
+				.takes_value(true))
+			.arg(Arg::with_name("serve-interval")
+				.long("serve-interval")
+				.help("ticks between frames sent to viewers (default 10)")
+				.takes_value(true))
+			.arg(Arg::with_name("serve-fps")
+				.long("serve-fps")
+				.help("send frames at this rate instead, with the physics keeping pace with wall-clock time and positions interpolated between ticks")
+				.takes_value(true)
+				.requires("serve")
+				.conflicts_with("serve-interval"))
+			.arg(Arg::with_name("lod-keyframes")
+				.long("lod-keyframes")
+				.help("stream quantized frames, with every body only in every n-th frame and the rest carrying just the bodies picked by --lod-speed and --lod-radius")
+				.takes_value(true)
+				.requires("serve"))
+			.arg(Arg::with_name("lod-speed")
+				.long("lod-speed")
+				.help("with --lod-keyframes, send bodies faster than this in every frame")
+				.takes_value(true)
+				.requires("lod-keyframes"))
+			.arg(Arg::with_name("lod-radius")
+				.long("lod-radius")
+				.help("with --lod-keyframes, send bodies within this distance of --lod-focus in every frame")
+				.takes_value(true)
+				.requires("lod-keyframes"))
+			.arg(Arg::with_name("lod-focus")
+				.long("lod-focus")
+				.help("the point --lod-radius is measured from, as x,y (default 0,0)")
+				.takes_value(true)
+				.requires("lod-radius"))
+			.arg(Arg::with_name("realtime")
+				.long("realtime")
+				.help("advance simulated time at this multiple of wall-clock time, sleeping when ahead and reporting when the run can't keep up (needs a fixed --dt)")
+				.takes_value(true))
+			.arg(Arg::with_name("api")
+				.long("api")
+				.help("serve an HTTP API at http://<addr> to query, spawn and delete bodies while running")
+				.takes_value(true))
+			.arg(Arg::with_name("metrics")
+				.long("metrics")
+				.help("serve Prometheus metrics at http://<addr>/metrics")
+				.takes_value(true))
+			.arg(Arg::with_name("commands-stdin")
+				.long("commands-stdin")
+				.help("read commands such as \"set dt 0.001\" or \"snapshot now\" from stdin while running"))
+			.arg(Arg::with_name("control-file")
+				.long("control-file")
+				.help("re-read commands from this file whenever it changes")
+				.takes_value(true))
+			.arg(Arg::with_name("snapshot-prefix")
+				.long("snapshot-prefix")
+				.help("path prefix for snapshots written by \"snapshot now\" and --snapshot-interval (default \"snapshot\")")
+				.takes_value(true))
+			.arg(Arg::with_name("snapshot-interval")
+				.long("snapshot-interval")
+				.help("ticks between snapshots written to <prefix>_<tick>.csv")
+				.takes_value(true))
+			.arg(Arg::with_name("snapshot-compression")
+				.long("snapshot-compression")
+				.help("compress periodic snapshots")
+				.takes_value(true)
+				.possible_values(&["none", "gzip", "zstd"])
+				.requires("snapshot-interval"))
+			.arg(Arg::with_name("snapshot-max-bytes")
+				.long("snapshot-max-bytes")
+				.help("delete the oldest periodic snapshots once together they exceed this many bytes")
+				.takes_value(true)
+				.requires("snapshot-interval"))
+			.arg(Arg::with_name("snapshot-max-age")
+				.long("snapshot-max-age")
+				.help("delete periodic snapshots older than this many seconds")
+				.takes_value(true)
+				.requires("snapshot-interval"))
+			.arg(Arg::with_name("output-queue")
+				.long("output-queue")
+				.help("periodic snapshots and statistics rows that may wait to be written on a background thread; 0 writes them between ticks")
+				.takes_value(true)
+				.default_value("16"))
+			.arg(Arg::with_name("event-log")
+				.long("event-log")
+				.help("write escapes, close encounters and periapsis passages to this JSONL file")
+				.takes_value(true))
+			.arg(Arg::with_name("encounter-radius")
+				.long("encounter-radius")
+				.help("log a close encounter when two bodies come within this distance")
+				.takes_value(true)
+				.requires("event-log"))
+			.arg(Arg::with_name("script")
+				.long("script")
+				.help("add the per-body force defined by fn force(x, y, vx, vy, mass, time) in this Rhai script (needs the scripting feature)")
+				.takes_value(true))
+			.arg(Arg::with_name("checkpoint")
+				.long("checkpoint")
+				.help("write the final state to this file so the run can be resumed")
+				.takes_value(true))
+			.arg(Arg::with_name("snapshot")
+				.long("snapshot")
+				.help("write the final body states to this CSV file")
+				.takes_value(true))
+			.arg(Arg::with_name("dry-run")
+				.long("dry-run")
+				.help("set everything up, print the resolved configuration and estimated memory, then exit without simulating"))
+			.arg(Arg::with_name("reverse-test")
+				.long("reverse-test")
+				.help("run --ticks forward, negate the velocities, run as many back and report how far each body is from its start"))
+			.arg(Arg::with_name("reverse-errors")
+				.long("reverse-errors")
+				.help("write the per-body position errors of --reverse-test to this CSV file")
+				.takes_value(true)
+				.requires("reverse-test"))
+			.arg(Arg::with_name("shadow-run")
+				.long("shadow-run")
+				.help("run --ticks alongside a copy with every body moved --shadow-delta in a random direction and report how fast the two diverge")
+				.conflicts_with("reverse-test"))
+			.arg(Arg::with_name("shadow-delta")
+				.long("shadow-delta")
+				.help("how far each body of the --shadow-run copy is moved (default 1e-4)")
+				.takes_value(true)
+				.requires("shadow-run"))
+			.arg(Arg::with_name("divergence-output")
+				.long("divergence-output")
+				.help("write the divergence of --shadow-run after every tick to this CSV file")
+				.takes_value(true)
+				.requires("shadow-run"))
+			.arg(Arg::with_name("manifest")
+				.long("manifest")
+				.help("write the arguments, seed, configuration, version and host of the run to this JSON file at the start, and its summary at the end")
+				.takes_value(true)
+				.conflicts_with_all(&["reverse-test", "shadow-run"]))
+			.arg(Arg::with_name("max-memory")
+				.long("max-memory")
+				.help("memory budget in bytes (or with a K, M or G suffix): random bodies are capped to fit it, other runs refuse to start if their estimate is over")
+				.takes_value(true)))
+		.subcommand(SubCommand::with_name("sweep")
+			.about("runs the simulation once per row of a CSV table of option values and collects the run summaries")
+			.arg(Arg::with_name("runs")
+				.help("CSV file with a header of option names and a row of values per run")
+				.required(true)
+				.index(1))
+			.arg(Arg::with_name("jobs")
+				.short("j")
+				.long("jobs")
+				.help("how many runs to have going at once")
+				.takes_value(true))
+			.arg(Arg::with_name("output")
+				.short("o")
+				.long("output")
+				.help("where to write the results table")
+				.takes_value(true))
+			.arg(Arg::with_name("common")
+				.help("options passed to every run, after --")
+				.multiple(true)
+				.last(true)))
+		.subcommand(SubCommand::with_name("bench")
+			.about("times whole ticks of random bodies at increasing counts")
+			.arg(Arg::with_name("count")
+				.short("n")
+				.long("count")
+				.help("comma-separated body counts (default 1000,10000)")
+				.takes_value(true))
+			.arg(Arg::with_name("ticks")
+				.long("ticks")
+				.help("ticks timed per body count (default 100)")
+				.takes_value(true))
+			.arg(Arg::with_name("integrator")
+				.long("integrator")
+				.takes_value(true)
+				.possible_values(&["euler", "hermite4", "leapfrog"]))
+			.arg(Arg::with_name("compare-summation")
+				.long("compare-summation")
+				.help("run each count with naive and compensated force summation and compare their energy drift")
+				.conflicts_with("integrator")))
+		.subcommand(SubCommand::with_name("render")
+			.about("draws snapshots as PGM images, or PPM coloured by group, density, mass, speed or acceleration")
+			.arg(Arg::with_name("trajectory")
+				.help("snapshot or trajectory files, in order")
+				.required(true)
+				.multiple(true))
+			.arg(Arg::with_name("output")
+				.short("o")
+				.long("output")
+				.help("prefix of the images, written to <prefix>_<tick>.pgm or .ppm (default frame)")
+				.takes_value(true))
+			.arg(Arg::with_name("size")
+				.long("size")
+				.help("width and height in pixels (default 512)")
+				.takes_value(true))
+			.arg(Arg::with_name("extent")
+				.long("extent")
+				.help("half the width of the view (default: fit the first snapshot)")
+				.takes_value(true))
+			.arg(Arg::with_name("center")
+				.long("center")
+				.help("x,y the view is centred on (default: the origin with --extent, else the centre of the first snapshot)")
+				.takes_value(true))
+			.arg(Arg::with_name("follow")
+				.long("follow")
+				.help("keep the body at this index in each frame in the centre, which is its BodyId while no body has been removed, split or sorted")
+				.takes_value(true)
+				.conflicts_with("center"))
+			.arg(Arg::with_name("colour")
+				.long("colour")
+				.alias("color-by")
+				.help("colour bodies by group, local density, mass, speed or the magnitude of their acceleration, with a colour bar for the last four (default: group if the first snapshot has more than one, else greyscale)")
+				.takes_value(true)
+				.possible_values(&["group", "density", "mass", "speed", "acceleration"]))
+			.arg(Arg::with_name("size-by")
+				.long("size-by")
+				.help("draw bodies as discs sized by local density, mass, speed or the magnitude of their acceleration")
+				.takes_value(true)
+				.possible_values(&["density", "mass", "speed", "acceleration"]))
+			.arg(Arg::with_name("neighbors")
+				.long("neighbors")
+				.help("number of nearest neighbours densities are estimated from (default 16)")
+				.takes_value(true)))
+		.subcommand(SubCommand::with_name("convert")
+			.about("rewrites snapshots and trajectories in another format, optionally downsampled")
+			.arg(Arg::with_name("input")
+				.help("snapshot or trajectory files, whose frames are joined in order")
+				.required(true)
+				.multiple(true))
+			.arg(Arg::with_name("to")
+				.long("to")
+				.help("the format to write")
+				.takes_value(true)
+				.required(true)
+				.possible_values(&["csv", "gzip", "zstd", "parquet", "json", "binary", "hdf5"]))
+			.arg(Arg::with_name("output")
+				.short("o")
+				.long("output")
+				.help("where to write it (default: the first input with the new extension)")
+				.takes_value(true))
+			.arg(Arg::with_name("every-frame")
+				.long("every-frame")
+				.help("keep only every k-th frame")
+				.takes_value(true))
+			.arg(Arg::with_name("every-body")
+				.long("every-body")
+				.help("keep only every k-th body of each frame")
+				.takes_value(true)))
+		.subcommand(SubCommand::with_name("diff")
+			.about("compares the bodies of two checkpoints or snapshots, exiting with 1 if they differ by more than the tolerances and 2 if one can't be read")
+			.arg(Arg::with_name("first")
+				.help("checkpoint, snapshot or trajectory, whose last frame is compared")
+				.required(true)
+				.index(1))
+			.arg(Arg::with_name("second")
+				.required(true)
+				.index(2))
+			.arg(Arg::with_name("position-tolerance")
+				.long("position-tolerance")
+				.help("the largest distance between the positions of a body allowed (default 0)")
+				.takes_value(true))
+			.arg(Arg::with_name("velocity-tolerance")
+				.long("velocity-tolerance")
+				.help("the largest difference between the velocities of a body allowed (default 0)")
+				.takes_value(true))
+			.arg(Arg::with_name("mass-tolerance")
+				.long("mass-tolerance")
+				.help("the largest difference between the masses of a body allowed (default 0)")
+				.takes_value(true)))
+		.subcommand(SubCommand::with_name("analyze")
+			.about("summarizes a statistics table written by run --stats-output")
+			.arg(Arg::with_name("stats")
+				.required(true)
+				.index(1)))
+		.get_matches();
 
-#[derive(Copy, Clone)]
-pub struct Velocity {
-    x: f32,
-    y: f32,
+	match app.subcommand() {
+		("run", Some(matches)) => run(matches),
+		("sweep", Some(matches)) => sweep(matches),
+		("bench", Some(matches)) => bench(matches),
+		("render", Some(matches)) => render(matches),
+		("convert", Some(matches)) => convert(matches),
+		("diff", Some(matches)) => diff(matches),
+		("analyze", Some(matches)) => analyze(matches),
+		_ => unreachable!(),
+	}
 }
 
-#[derive(Copy, Clone)]
-pub struct Position {
-    x: f32,
-    y: f32,
-}
+fn run(app: &ArgMatches) {
+	let count = app.value_of("count").unwrap_or("100");
+	let n: u32 = count.parse().unwrap();
+    let ticks: u64 = match app.value_of("ticks") {
+        Some(ticks) => ticks.parse().unwrap(),
+        None if app.is_present("serve") => u64::MAX,
+        None => 100000,
+    };
 
-#[derive(Copy, Clone)]
-pub struct WorldBounds {
-    x: f32,
-    y: f32,
-}
+    let scenario = match (app.value_of("scenario"), app.value_of("scenario-file")) {
+        (Some(name), _) => Some(
+            scenarios::by_name(name, app.value_of("count").map(|_| n)).expect("unknown scenario"),
+        ),
+        (None, Some(path)) => Some(scenario_file::load(path).unwrap_or_else(|e| panic!("{}", e))),
+        (None, None) => None,
+    };
 
-#[derive(Copy, Clone)]
-pub struct Time {
-    beginning: std::time::Instant,
-    last: std::time::Instant,
-    total: f64,
-    delta: f64,
-}
+    if let Some(path) = app.value_of("potential-output") {
+        assert!(
+            app.value_of("scenario") == Some("restricted-three-body"),
+            "--potential-output needs --scenario restricted-three-body"
+        );
+        restricted::write_potential(path, scenarios::RESTRICTED_MASS_RATIO, 1.5, 301, -3.0)
+            .expect("failed to write potential");
+    }
 
-// SYSTEMS
-pub struct UpdateTime;
-impl<'d, 'w: 'd> System<'d, 'w, World> for UpdateTime {
-    type SystemData = (Write<'d, Time>);
+    // every random draw of the set-up comes from this seed
+    let seed = match app.value_of("seed") {
+        Some(seed) => seed.parse().expect("invalid --seed"),
+        None => rand::random(),
+    };
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
 
-    fn run(&self, (mut time): Self::SystemData) {
-        let current = std::time::Instant::now();
-        time.delta = current.duration_since(time.last).as_secs_f64();
-        time.total = current.duration_since(time.beginning).as_secs_f64();
-        time.last = current;
+    let matrix = match app.value_of("interaction-matrix") {
+        Some(text) => InteractionMatrix::parse(text).expect("invalid --interaction-matrix"),
+        None => {
+            let species: usize = app.value_of("species").unwrap_or("1").parse().unwrap();
+            assert!(species >= 1, "--species must be at least 1");
+            InteractionMatrix::uniform(species)
+        }
+    };
+    if let Some(species) = app.value_of("species") {
+        let species: usize = species.parse().unwrap();
+        assert!(
+            species == matrix.species(),
+            "--species {} does not match the {}x{} interaction matrix",
+            species,
+            matrix.species(),
+            matrix.species()
+        );
     }
-}
 
-fn overlapping(pos_one: &Position, pos_two: &Position) -> bool {
-    let epsilon: f32 = 0.05;
+    let step = match app.value_of("dt") {
+        Some(dt) => Some(dt.parse().unwrap()),
+        None => scenario.as_ref().map(|s| s.dt),
+    };
+    let mut gravity = match &scenario {
+        Some(s) => Gravity {
+            g: s.gravity,
+            epsilon: 0.0,
+            softening: Softening::None,
+            exponent: 2.0,
+        },
+        None => Gravity::default(),
+    };
+    if let Some(g) = app.value_of("g-constant") {
+        gravity.g = g.parse().expect("invalid --g-constant");
+    }
+    let scenario_halo = scenario.as_ref().and_then(|s| s.halo);
+    let speed_of_light = match app.value_of("speed-of-light") {
+        Some(c) => Some(c.parse().unwrap()),
+        None => scenario.as_ref().and_then(|s| s.speed_of_light),
+    };
+    let mut builder = match app.value_of("resume") {
+        Some(path) => {
+            let checkpoint = Checkpoint::read(path).expect("failed to read checkpoint");
+            let step = app
+                .value_of("dt")
+                .map(|dt| dt.parse().unwrap())
+                .or(checkpoint.step);
+            // checkpoints from before version 3 have no generator to continue
+            let fresh = checkpoint.rng.is_none();
+            let builder = SimulationBuilder::from_checkpoint(checkpoint).timestep(step);
+            if fresh {
+                builder.seed(seed)
+            } else {
+                builder
+            }
+        }
+        None => {
+            let mut initial = match scenario {
+                Some(scenario) => scenario.bodies,
+                None => scenarios::random(n, matrix.species(), &mut rng),
+            };
+            if let Some(count) = app.value_of("tracers") {
+                let count = count.parse().expect("invalid --tracers");
+                let tracers = scenarios::tracers(count, &initial, &mut rng);
+                initial.extend(tracers);
+            }
+            if app.value_of("velocities") == Some("circular") {
+                scenarios::circular_velocities(&mut initial, gravity.g, scenario_halo.as_ref());
+            }
+            if let Some(lambda) = app.value_of("spin") {
+                let lambda = lambda.parse().expect("invalid --spin");
+                scenarios::spin(&mut initial, lambda, gravity.g)
+                    .unwrap_or_else(|e| panic!("--spin: {}", e));
+            }
+            SimulationBuilder::new(initial)
+                .gravity(gravity)
+                .timestep(step)
+                .interaction_matrix(matrix)
+                .seed(seed)
+        }
+    };
+    let capacity: usize = app
+        .value_of("output-queue")
+        .unwrap()
+        .parse()
+        .expect("invalid --output-queue");
+    let output = if capacity > 0 {
+        Some(OutputQueue::new(capacity))
+    } else {
+        None
+    };
+    if let Some(path) = app.value_of("stats-output") {
+        let mut stats = StatsOutput::create(path).expect("failed to create statistics output");
+        if let Some(output) = &output {
+            stats = stats.queued(output.handle());
+        }
+        builder = builder.stats_output(stats);
+    }
+    if let Some(interval) = app.value_of("stats-interval") {
+        let interval: u64 = interval.parse().unwrap();
+        assert!(interval >= 1, "--stats-interval must be at least 1");
+        builder = builder.statistics(interval);
+    } else if ["api", "metrics", "manifest"]
+        .iter()
+        .any(|arg| app.is_present(arg))
+    {
+        // these report the latest sample even without a statistics file
+        builder = builder.statistics(1);
+    }
+    if let Some(halo) = scenario_halo {
+        builder = builder.halo(halo);
+    }
+    if let Some(text) = app.value_of("exclude") {
+        let exclusions = Exclusions::parse(text).expect("invalid --exclude");
+        for &(a, b) in exclusions.pairs() {
+            builder = builder.exclude(a, b);
+        }
+    }
+    if let Some(threshold) = app.value_of("friction-mass") {
+        builder = builder.dynamical_friction(DynamicalFriction {
+            mass_threshold: threshold.parse().unwrap(),
+            radius: app
+                .value_of("friction-radius")
+                .unwrap_or("1.0")
+                .parse()
+                .unwrap(),
+            coulomb_log: app
+                .value_of("coulomb-log")
+                .unwrap_or("3.0")
+                .parse()
+                .unwrap(),
+        });
+    }
+    if app.is_present("wind-rate") || app.is_present("accretion-radius") {
+        builder = builder.mass_evolution(MassEvolution {
+            mass_threshold: app
+                .value_of("evolution-mass")
+                .unwrap_or("0")
+                .parse()
+                .expect("invalid --evolution-mass"),
+            wind_rate: app
+                .value_of("wind-rate")
+                .unwrap_or("0")
+                .parse()
+                .expect("invalid --wind-rate"),
+            accretion_radius: app
+                .value_of("accretion-radius")
+                .unwrap_or("0")
+                .parse()
+                .expect("invalid --accretion-radius"),
+        });
+    }
+    if app.is_present("relativistic") {
+        let c = speed_of_light.expect("--relativistic needs --speed-of-light for this scenario");
+        builder = builder.relativistic(c);
+    }
+    builder = builder.integrator(match app.value_of("integrator") {
+        Some("hermite4") => Integrator::Hermite4,
+        Some("leapfrog") => Integrator::Leapfrog,
+        Some(_) => Integrator::Euler,
+        None if app.is_present("reverse-test") => Integrator::Leapfrog,
+        None => Integrator::Euler,
+    });
+    if let Some(path) = app.value_of("script") {
+        #[cfg(feature = "scripting")]
+        {
+            let force =
+                smolnbody::scripting::ScriptForce::load(path).unwrap_or_else(|e| panic!("{}", e));
+            builder = builder.add_system_at(smolnbody::Stage::Force, force, "script_force", vec![]);
+        }
+        #[cfg(not(feature = "scripting"))]
+        panic!(
+            "--script {} needs a build with the \"scripting\" feature",
+            path
+        );
+    }
+    if let Some(path) = app.value_of("event-log") {
+        builder = builder.event_log(
+            EventLog::create(path).expect("failed to create event log"),
+            app.value_of("encounter-radius").map(|r| r.parse().unwrap()),
+        );
+    }
+    if app.is_present("commands-stdin") || app.is_present("control-file") {
+        builder = builder.commands(CommandChannel::new(
+            app.is_present("commands-stdin"),
+            app.value_of("control-file").map(String::from),
+            app.value_of("snapshot-prefix").unwrap_or("snapshot"),
+        ));
+    }
+    if let Some(interval) = app.value_of("snapshot-interval") {
+        let interval: u64 = interval.parse().unwrap();
+        assert!(interval >= 1, "--snapshot-interval must be at least 1");
+        let mut series = SnapshotSeries::new(
+            app.value_of("snapshot-prefix").unwrap_or("snapshot"),
+            interval,
+            Compression::parse(app.value_of("snapshot-compression").unwrap_or("none")).unwrap(),
+            Retention {
+                max_bytes: app
+                    .value_of("snapshot-max-bytes")
+                    .map(|b| b.parse().unwrap()),
+                max_age: app
+                    .value_of("snapshot-max-age")
+                    .map(|s| Duration::from_secs_f64(s.parse().unwrap())),
+            },
+        );
+        if let Some(output) = &output {
+            series = series.queued(output.handle());
+        }
+        builder = builder.add_system(series, "snapshots", vec![]);
+    }
+    if let Some(max) = app.value_of("max-substeps") {
+        let radius = app
+            .value_of("substep-radius")
+            .unwrap_or("0.1")
+            .parse()
+            .unwrap();
+        builder = builder.substeps(radius, max.parse().unwrap());
+    }
+    if let Some(exponent) = app.value_of("force-exponent") {
+        builder = builder.force_exponent(exponent.parse().expect("invalid --force-exponent"));
+    }
+    if let Some(length) = app.value_of("softening") {
+        let length: f32 = length.parse().expect("invalid --softening");
+        builder = builder.softening(match app.value_of("softening-kernel") {
+            Some("spline") => Softening::Spline(length),
+            _ => Softening::Plummer(length),
+        });
+    }
+    if app.is_present("compensated-sum") {
+        builder = builder.compensated_sum();
+    }
+    if let Some(interval) = app.value_of("far-field-interval") {
+        let interval: u64 = interval.parse().unwrap();
+        assert!(interval >= 1, "--far-field-interval must be at least 1");
+        let radius = app
+            .value_of("near-radius")
+            .unwrap_or("1.0")
+            .parse()
+            .unwrap();
+        builder = builder.far_field(interval, radius);
+    }
+    if let Some(threshold) = app.value_of("tidal-threshold") {
+        let tidal = TidalDisruption {
+            threshold: threshold.parse().unwrap(),
+            fragments: app
+                .value_of("tidal-fragments")
+                .unwrap_or("2")
+                .parse()
+                .unwrap(),
+            dispersion: app
+                .value_of("tidal-dispersion")
+                .unwrap_or("0.1")
+                .parse()
+                .unwrap(),
+            radius_scale: 0.05,
+            min_mass: 0.1,
+        };
+        assert!(tidal.fragments >= 2, "--tidal-fragments must be at least 2");
+        assert!(
+            tidal.dispersion >= 0.0,
+            "--tidal-dispersion must not be negative"
+        );
+        builder = builder.tidal_disruption(tidal);
+    }
+    if app.is_present("detect-escapes") {
+        builder = builder.detect_escapes(app.is_present("remove-escaped"));
+    }
+    if let Some(path) = app.value_of("rotation-curve") {
+        let interval = app
+            .value_of("rotation-curve-interval")
+            .unwrap_or("100")
+            .parse()
+            .unwrap();
+        assert!(
+            interval >= 1,
+            "--rotation-curve-interval must be at least 1"
+        );
+        let bins = app
+            .value_of("rotation-curve-bins")
+            .unwrap_or("20")
+            .parse()
+            .unwrap();
+        builder = builder.rotation_curve(
+            RotationCurveOutput::create(path, interval, bins)
+                .expect("failed to create rotation curve output"),
+        );
+    }
+    if let Some(path) = app.value_of("elements") {
+        let interval = app
+            .value_of("elements-interval")
+            .unwrap_or("100")
+            .parse()
+            .unwrap();
+        assert!(interval >= 1, "--elements-interval must be at least 1");
+        let primary = app
+            .value_of("elements-primary")
+            .unwrap_or("0")
+            .parse()
+            .unwrap();
+        let orbiters = app
+            .value_of("elements-bodies")
+            .unwrap()
+            .split(',')
+            .map(|i| i.trim().parse().expect("invalid --elements-bodies"))
+            .collect();
+        builder = builder.orbital_elements(
+            OrbitalElementsOutput::create(path, interval)
+                .expect("failed to create orbital elements output"),
+            primary,
+            orbiters,
+        );
+    }
+    if let Some(interval) = app.value_of("sort-interval") {
+        let interval: u64 = interval.parse().unwrap();
+        assert!(interval >= 1, "--sort-interval must be at least 1");
+        builder = builder.sort_interval(interval);
+    }
+    if let Some(interval) = app.value_of("correlation-interval") {
+        let correlation = PairCorrelation {
+            interval: interval.parse().unwrap(),
+            r_max: app
+                .value_of("correlation-rmax")
+                .unwrap_or("1.0")
+                .parse()
+                .unwrap(),
+            bins: app
+                .value_of("correlation-bins")
+                .unwrap_or("50")
+                .parse()
+                .unwrap(),
+        };
+        assert!(
+            correlation.interval >= 1,
+            "--correlation-interval must be at least 1"
+        );
+        assert!(
+            correlation.bins >= 1,
+            "--correlation-bins must be at least 1"
+        );
+        builder = builder.pair_correlation(correlation);
+    }
+    if let Some(interval) = app.value_of("histogram-interval") {
+        let interval: u64 = interval.parse().unwrap();
+        assert!(interval >= 1, "--histogram-interval must be at least 1");
+        builder = builder.distributions(Distributions {
+            interval,
+            bins: app
+                .value_of("histogram-bins")
+                .unwrap_or("50")
+                .parse()
+                .unwrap(),
+        });
+    }
+    if let Some(interval) = app.value_of("binaries-interval") {
+        let interval: u64 = interval.parse().unwrap();
+        assert!(interval >= 1, "--binaries-interval must be at least 1");
+        builder = builder.binary_catalog(BinaryCatalog::new(
+            interval,
+            app.value_of("binaries-radius")
+                .unwrap_or("0.1")
+                .parse()
+                .unwrap(),
+        ));
+    }
+
+    if let Some(interval) = app.value_of("clumps-interval") {
+        let linking_length: f32 = app
+            .value_of("linking-length")
+            .unwrap_or("0.1")
+            .parse()
+            .unwrap();
+        assert!(linking_length > 0.0, "--linking-length must be positive");
+        let interval: u64 = interval.parse().unwrap();
+        assert!(interval >= 1, "--clumps-interval must be at least 1");
+        builder = builder.clump_finder(FriendsOfFriends {
+            interval,
+            linking_length,
+            min_members: app
+                .value_of("clump-members")
+                .unwrap_or("10")
+                .parse()
+                .unwrap(),
+        });
+    }
 
-    if ((pos_one.x - pos_two.x).abs() <= epsilon) && ((pos_one.y - pos_two.y).abs() <= epsilon) {
-        return true;
+    let frame_interval = app.value_of("serve-fps").map(|fps| {
+        let fps: f64 = fps.parse().expect("invalid --serve-fps");
+        assert!(fps > 0.0, "--serve-fps must be positive");
+        Duration::from_secs_f64(1.0 / fps)
+    });
+    if frame_interval.is_some() {
+        builder = builder.interpolation();
+    }
+
+    if let Some(budget) = app.value_of("max-memory") {
+        let budget = parse_bytes(budget).expect("invalid --max-memory");
+        let estimate = builder.estimated_memory();
+        let random = ["scenario", "scenario-file", "resume"]
+            .iter()
+            .all(|arg| !app.is_present(arg));
+        if estimate > budget && random {
+            let count = builder.max_bodies(budget);
+            assert!(count > 0, "--max-memory is too small for a single body");
+            builder = builder.truncate(count);
+            eprintln!(
+                "estimated memory {} over --max-memory {}, capped at {} bodies ({})",
+                mebibytes(estimate),
+                mebibytes(budget),
+                count,
+                mebibytes(builder.estimated_memory())
+            );
+        } else {
+            assert!(
+                estimate <= budget,
+                "estimated memory {} is over --max-memory {}",
+                mebibytes(estimate),
+                mebibytes(budget)
+            );
+            eprintln!(
+                "estimated memory {} of --max-memory {}",
+                mebibytes(estimate),
+                mebibytes(budget)
+            );
+        }
+    }
+
+    let shadow = if app.is_present("shadow-run") {
+        let delta = app
+            .value_of("shadow-delta")
+            .unwrap_or("1e-4")
+            .parse()
+            .unwrap();
+        Some(builder.shadow(delta, &mut rng))
     } else {
-        return false;
+        None
+    };
+    let mut simulation = builder.build();
+    if app.is_present("dry-run") {
+        if ticks < u64::MAX {
+            println!("ticks:        {}", ticks);
+        } else {
+            println!("ticks:        until stopped");
+        }
+        println!("{}", simulation.configuration());
+        return;
+    }
+    if app.is_present("reverse-test") {
+        assert!(
+            simulation.timestep().is_some(),
+            "--reverse-test needs a fixed timestep"
+        );
+        let report = simulation.reverse_test(ticks);
+        simulation.finish().expect("failed to write output");
+        finish_output(output);
+        if let Some(path) = app.value_of("reverse-errors") {
+            report
+                .write_csv(path)
+                .expect("failed to write reversal errors");
+        }
+        println!("{}", report);
+        return;
+    }
+    if let Some(shadow) = shadow {
+        assert!(
+            simulation.timestep().is_some(),
+            "--shadow-run needs a fixed timestep"
+        );
+        assert!(ticks < u64::MAX, "--shadow-run needs --ticks");
+        let report = simulation.shadow_run(&mut shadow.build(), ticks);
+        simulation.finish().expect("failed to write output");
+        finish_output(output);
+        if let Some(path) = app.value_of("divergence-output") {
+            report.write_csv(path).expect("failed to write divergence");
+        }
+        println!("{}", report);
+        return;
     }
-}
 
-fn distance(pos_one: &Position, pos_two: &Position) -> f32 {
-    let delta_x = pos_one.x - pos_two.x;
-    let delta_y = pos_one.y - pos_two.y;
+    let manifest = app.value_of("manifest").map(|path| {
+        let arguments: Vec<String> = std::env::args().collect();
+        Manifest::start(path, &arguments, seed, &simulation.configuration())
+            .expect("failed to write manifest")
+    });
 
-    let d = delta_x.powf(2.0) + delta_y.powf(2.0);
-    return d.sqrt();
-}
+    // The first Ctrl-C lets the current tick finish and the outputs get
+    // written; a second one exits straight away. Builds without the
+    // "interrupt" feature leave Ctrl-C to end the process at once.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    #[cfg(feature = "interrupt")]
+    {
+        let flag = interrupted.clone();
+        ctrlc::set_handler(move || {
+            if flag.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            eprintln!(
+                "interrupted, finishing the current tick (press Ctrl-C again to exit immediately)"
+            );
+        })
+        .expect("failed to install the Ctrl-C handler");
+    }
 
-use std::ops::Deref;
-pub struct ApplyGravity;
-impl<'d, 'w: 'd> System<'d, 'w, World> for ApplyGravity {
-    type SystemData = (
-        ReadComp<'d, Mass>,
-        ReadComp<'d, Position>,
-        WriteComp<'d, Acceleration>,
-        Read<'d, EntityStorage>,
-    );
+    let server = app.value_of("serve").map(|addr| {
+        let server = Server::bind(addr).expect("failed to start the server");
+        eprintln!("serving on ws://{}", addr);
+        server
+    });
+    let serve_interval: u64 = app
+        .value_of("serve-interval")
+        .unwrap_or("10")
+        .parse()
+        .unwrap();
+    assert!(serve_interval >= 1, "--serve-interval must be at least 1");
+    let mut lod = app.value_of("lod-keyframes").map(|keyframes| {
+        let focus = app.value_of("lod-focus").map_or((0.0, 0.0), |focus| {
+            let (x, y) = focus.split_once(',').expect("--lod-focus takes x,y");
+            (
+                x.trim().parse().expect("invalid --lod-focus"),
+                y.trim().parse().expect("invalid --lod-focus"),
+            )
+        });
+        LodEncoder::new(LevelOfDetail {
+            keyframes: keyframes.parse().expect("invalid --lod-keyframes"),
+            speed: app
+                .value_of("lod-speed")
+                .map_or(f32::INFINITY, |v| v.parse().expect("invalid --lod-speed")),
+            focus,
+            radius: app
+                .value_of("lod-radius")
+                .map_or(0.0, |r| r.parse().expect("invalid --lod-radius")),
+        })
+    });
 
-    fn run(&self, (masses, positions, mut accels, ents): Self::SystemData) {
-        const G: f32 = 6.67430e-11_f32;
+    let metrics = app.value_of("metrics").map(|addr| {
+        let metrics = Metrics::serve(addr, simulation.threads())
+            .unwrap_or_else(|e| panic!("failed to start the metrics endpoint: {}", e));
+        eprintln!("metrics on http://{}/metrics", addr);
+        metrics
+    });
+    let api = app.value_of("api").map(|addr| {
+        let api =
+            Api::serve(addr).unwrap_or_else(|e| panic!("failed to start the HTTP API: {}", e));
+        eprintln!("HTTP API on http://{}", addr);
+        api
+    });
+    let realtime: Option<f64> = app
+        .value_of("realtime")
+        .map(|factor| factor.parse().expect("invalid --realtime"));
+    if let Some(factor) = realtime {
+        assert!(factor > 0.0, "--realtime must be positive");
+        assert!(
+            simulation.timestep().is_some(),
+            "--realtime needs a fixed timestep"
+        );
+    }
+    // --serve-fps keeps its own pace
+    let mut pacer = realtime
+        .filter(|_| frame_interval.is_none())
+        .map(|factor| Pacer::new(factor, simulation.time()));
 
-        for (mass_one, pos_one, accel, ent_one) in
-            (&masses, &positions, &mut accels, ents.deref()).join()
-        {
-            accel.x = 0.0;
-            accel.y = 0.0;
-            for (mass_two, pos_two, ent_two) in (&masses, &positions, ents.deref()).join() {
-                if ent_one == ent_two {
-                    continue;
+    let mut paused = false;
+    let mut done = 0;
+    // simulated time not yet run with --serve-fps
+    let mut lag = 0.0;
+    let mut clock = Instant::now();
+    while done < ticks && !interrupted.load(Ordering::SeqCst) {
+        if let Some(api) = &api {
+            api.answer(&mut simulation);
+        }
+        if let Some(server) = &server {
+            for command in server.poll() {
+                match command {
+                    Command::Pause => paused = true,
+                    Command::Resume => {
+                        paused = false;
+                        simulation.reset_clock();
+                        clock = Instant::now();
+                        if let Some(pacer) = &mut pacer {
+                            pacer.reset(simulation.time());
+                        }
+                    }
+                    Command::SetTimestep(step) => simulation.set_timestep(step),
+                    Command::Spawn(body) => {
+                        if let Err(e) = simulation.spawn(body) {
+                            eprintln!("could not spawn a body: {}", e);
+                        }
+                    }
+                    Command::Remove(point) => {
+                        if let Some(index) = simulation.nearest(point) {
+                            simulation.remove(index);
+                        }
+                    }
+                    Command::Kick(point, dv) => {
+                        if let Some(index) = simulation.nearest(point) {
+                            simulation.kick(index, dv);
+                        }
+                    }
                 }
+            }
+            if paused {
+                std::thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+        }
 
-                if overlapping(pos_one, pos_two) {
-                    continue;
+        if let (Some(server), Some(interval)) = (&server, frame_interval) {
+            let frame_start = Instant::now();
+            let speed = realtime.unwrap_or(1.0);
+            lag += speed * frame_start.duration_since(clock).as_secs_f64();
+            clock = frame_start;
+            // in wall-clock mode one tick covers all the time since the last
+            let step = simulation.timestep().unwrap_or(lag);
+            // don't try to catch up on more than a quarter second at once
+            lag = lag.min((0.25 * speed).max(step));
+            while lag >= step && lag > 0.0 && done < ticks {
+                let started = Instant::now();
+                simulation.step();
+                done += 1;
+                lag -= step;
+                if let Some(metrics) = &metrics {
+                    metrics.record(started.elapsed(), &simulation);
                 }
+            }
 
-                let dist_x = (pos_one.x - pos_two.x).abs();
-                let dist_y = (pos_one.y - pos_two.y).abs();
+            // renders one tick behind, so that there is always a later state
+            let alpha = match simulation.timestep() {
+                Some(_) => lag / step,
+                None => 1.0,
+            };
+            stream(
+                server,
+                &mut lod,
+                simulation.tick(),
+                simulation.time() - (1.0 - alpha) * step,
+                &simulation.interpolated(alpha as f32),
+            );
+            std::thread::sleep(interval.saturating_sub(frame_start.elapsed()));
+            continue;
+        }
 
-                // BREAK THIS INTO X AND Y COMPONENTS
-                let force_x = G * (mass_one.mass * mass_two.mass) / dist_x.powf(2.0);
-                let force_y = G * (mass_one.mass * mass_two.mass) / dist_y.powf(2.0);
+        let started = Instant::now();
+        simulation.step();
+        done += 1;
+        if let Some(metrics) = &metrics {
+            metrics.record(started.elapsed(), &simulation);
+        }
+        if let Some(pacer) = &mut pacer {
+            if let Some(speed) = pacer.wait(simulation.time()) {
+                eprintln!(
+                    "can't keep up with --realtime {}: running at {:.3}x wall-clock time",
+                    realtime.unwrap(),
+                    speed
+                );
+            }
+        }
 
-                accel.x += force_x / mass_one.mass;
-                accel.y += force_y / mass_one.mass;
+        if let Some(server) = &server {
+            if simulation.tick() % serve_interval == 0 {
+                stream(
+                    server,
+                    &mut lod,
+                    simulation.tick(),
+                    simulation.time(),
+                    &simulation.bodies(),
+                );
             }
         }
     }
+    simulation.finish().expect("failed to write output");
+    finish_output(output);
+
+    // an interrupted run is always saved, so it can be resumed or inspected
+    let interrupted_path = |extension: &str| {
+        if interrupted.load(Ordering::SeqCst) {
+            Some(format!("interrupted_{}.{}", simulation.tick(), extension))
+        } else {
+            None
+        }
+    };
+    let checkpoint_path = app
+        .value_of("checkpoint")
+        .map(str::to_string)
+        .or_else(|| interrupted_path("chk"));
+    if let Some(path) = checkpoint_path {
+        simulation
+            .checkpoint()
+            .write(&path)
+            .expect("failed to write checkpoint");
+        if !app.is_present("checkpoint") {
+            eprintln!("wrote a checkpoint to {}", path);
+        }
+    }
+    let snapshot_path = app
+        .value_of("snapshot")
+        .map(str::to_string)
+        .or_else(|| interrupted_path("csv"));
+    if let Some(path) = snapshot_path {
+        snapshot::write(
+            &path,
+            simulation.tick(),
+            simulation.time(),
+            &simulation.bodies(),
+        )
+        .expect("failed to write snapshot");
+        if !app.is_present("snapshot") {
+            eprintln!("wrote a snapshot to {}", path);
+        }
+    }
+    println!("{}", simulation.summary());
+    if let Some(manifest) = &manifest {
+        manifest
+            .finish(
+                &simulation.summary(),
+                simulation.latest_stats().as_ref(),
+                interrupted.load(Ordering::SeqCst),
+            )
+            .expect("failed to write manifest");
+    }
+    if app.is_present("clumps-interval") {
+        print!("{}", simulation.clumps());
+    }
+
+    if interrupted.load(Ordering::SeqCst) {
+        std::process::exit(130);
+    }
 }
 
-pub struct ApplyAccelerations;
-impl<'d, 'w: 'd> System<'d, 'w, World> for ApplyAccelerations {
-    type SystemData = (
-        ReadComp<'d, Acceleration>,
-        Read<'d, Time>,
-        WriteComp<'d, Velocity>,
-    );
+// Sends viewers a frame of `bodies`, with level of detail when enabled.
+fn stream(
+    server: &Server,
+    lod: &mut Option<LodEncoder>,
+    tick: u64,
+    time: f64,
+    bodies: &[BodyState],
+) {
+    match lod {
+        Some(lod) => server.broadcast_lod(lod.encode(tick, time, bodies)),
+        None => server.broadcast(server::encode_frame(tick, time, bodies)),
+    }
+}
 
-    fn run(&self, (accels, time, mut vels): Self::SystemData) {
-        for (accel, vel) in (&accels, &mut vels).join() {
-            vel.x += accel.x * time.delta as f32;
-            vel.y += accel.y * time.delta as f32;
+// Waits for the background output thread to write what it was handed, and
+// says if the run had to wait for it.
+fn finish_output(output: Option<OutputQueue>) {
+    if let Some(output) = output {
+        let report = output.finish().expect("failed to write output");
+        if report.stalls > 0 {
+            eprintln!("{}", report);
         }
     }
 }
 
-pub struct ApplyVelocities;
-impl<'d, 'w: 'd> System<'d, 'w, World> for ApplyVelocities {
-    type SystemData = (
-        ReadComp<'d, Velocity>,
-        Read<'d, Time>,
-        WriteComp<'d, Position>,
-    );
+// A size in bytes, optionally with a binary K, M or G suffix.
+fn parse_bytes(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let (digits, scale) = match text.chars().last()?.to_ascii_uppercase() {
+        'K' => (&text[..text.len() - 1], 1 << 10),
+        'M' => (&text[..text.len() - 1], 1 << 20),
+        'G' => (&text[..text.len() - 1], 1 << 30),
+        _ => (text, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(scale)
+}
 
-    fn run(&self, (vels, time, mut positions): Self::SystemData) {
-        for (vel, position) in (&vels, &mut positions).join() {
-            position.x += vel.x * time.delta as f32;
-            position.y += vel.y * time.delta as f32;
-        }
+fn mebibytes(bytes: usize) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn sweep(matches: &ArgMatches) {
+    let runs = Sweep::read(matches.value_of("runs").unwrap()).unwrap_or_else(|e| panic!("{}", e));
+    let mut common = vec!["run".to_string()];
+    if let Some(values) = matches.values_of("common") {
+        common.extend(values.map(String::from));
     }
+    let jobs = matches.value_of("jobs").unwrap_or("1").parse().unwrap();
+    let program = std::env::current_exe().expect("failed to find the simulation binary");
+    let output = matches.value_of("output").unwrap_or("sweep.csv");
+    sweep::run(&program, &runs, &common, jobs, output).expect("failed to write sweep results");
+    eprintln!("wrote {} runs to {}", runs.runs.len(), output);
 }
 
-fn main() {
-	let app = App::new("nBody")
-		.version("1.0")
-		.about("runs an n-body simulation")
-		.author("SmolECS")
-		.arg(Arg::with_name("count")
-			.short("n")
-			.long("count")
-			.help("the amount of bodies to be simulated")
-			.takes_value(true)
-			.required(true))
-		.get_matches();
+fn bench(matches: &ArgMatches) {
+    let counts: Vec<u32> = matches
+        .value_of("count")
+        .unwrap_or("1000,10000")
+        .split(',')
+        .map(|n| n.trim().parse().expect("invalid --count"))
+        .collect();
+    let ticks: u32 = matches.value_of("ticks").unwrap_or("100").parse().unwrap();
+    let integrator = match matches.value_of("integrator") {
+        Some("hermite4") => Integrator::Hermite4,
+        Some("leapfrog") => Integrator::Leapfrog,
+        _ => Integrator::Euler,
+    };
+    let compare = matches.is_present("compare-summation");
+    // the time per tick and the energy drift of a run at each body count
+    let measure = |n: u32, compensated: bool| {
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let mut builder = SimulationBuilder::new(scenarios::random(n, 1, &mut rng))
+            .gravity(Gravity::default())
+            .timestep(Some(1.0e-3))
+            .integrator(integrator);
+        if compensated {
+            builder = builder.compensated_sum();
+        }
+        let mut simulation = builder.build();
+        // one untimed tick to warm the caches and the thread pool
+        simulation.step();
+        let started = Instant::now();
+        simulation.run(ticks as u64);
+        let rate = ticks as f64 / started.elapsed().as_secs_f64();
+        (rate, simulation.summary().energy_drift)
+    };
 
-	let count = app.value_of("count").unwrap_or("100");
-	let n: u32 = count.parse().unwrap();
+    if compare {
+        println!(
+            "{:>10} {:>12} {:>14} {:>12} {:>14}",
+            "bodies", "naive/s", "naive drift", "kahan/s", "kahan drift"
+        );
+    } else {
+        println!("{:>10} {:>12} {:>14}", "bodies", "ticks/s", "pairs/s");
+    }
+    for n in counts {
+        let (rate, drift) = measure(n, false);
+        if compare {
+            let (kahan_rate, kahan_drift) = measure(n, true);
+            println!(
+                "{:>10} {:>12.1} {:>14.3e} {:>12.1} {:>14.3e}",
+                n, rate, drift, kahan_rate, kahan_drift
+            );
+        } else {
+            println!(
+                "{:>10} {:>12.1} {:>14.3e}",
+                n,
+                rate,
+                // each distinct pair once, however the backend sums them
+                rate * (n as f64 * (n as f64 - 1.0) / 2.0)
+            );
+        }
+    }
+}
 
-    let mut world = World::new();
-    world.register_comp::<Body>();
-    world.register_comp::<Mass>();
-    world.register_comp::<Acceleration>();
-    world.register_comp::<Velocity>();
-    world.register_comp::<Position>();
-
-    world.insert(WorldBounds { x: 10.0, y: 10.0 });
-    world.insert(Time {
-        beginning: std::time::Instant::now(),
-        last: std::time::Instant::now(),
-        total: 0.0,
-        delta: 0.0,
+fn render(matches: &ArgMatches) {
+    let prefix = matches.value_of("output").unwrap_or("frame");
+    let size = matches.value_of("size").unwrap_or("512").parse().unwrap();
+    let center = matches.value_of("center").map(|center| {
+        let (x, y) = center.split_once(',').expect("--center takes x,y");
+        (
+            x.trim().parse().expect("invalid --center"),
+            y.trim().parse().expect("invalid --center"),
+        )
     });
-    world.insert(EntityStorage::new());
-
-    let mut ents = Write::<EntityStorage>::get_data(&world);
-    let mut bodies = WriteComp::<Body>::get_data(&world);
-    let mut masses = WriteComp::<Mass>::get_data(&world);
-    let mut accels = WriteComp::<Acceleration>::get_data(&world);
-    let mut vels = WriteComp::<Velocity>::get_data(&world);
-    let mut positions = WriteComp::<Position>::get_data(&world);
-
-    let mut rng = rand::thread_rng();
-    for _ in 0..n {
-        ents.create_entity()
-            .add(&mut bodies, Body {})
-            .add(
-                &mut masses,
-                Mass {
-                    mass: rng.gen_range(1.0, 5.0),
-                },
-            )
-            .add(&mut accels, Acceleration { x: 0.0, y: 0.0 })
-            .add(
-                &mut vels,
-                Velocity {
-                    x: rng.gen_range(-1.0, 1.0),
-                    y: rng.gen_range(-1.0, 1.0),
-                },
-            )
-            .add(
-                &mut positions,
-                Position {
-                    x: rng.gen_range(0.0, 10.0),
-                    y: rng.gen_range(0.0, 10.0),
-                },
-            );
+    let follow: Option<usize> = matches
+        .value_of("follow")
+        .map(|index| index.parse().expect("invalid --follow"));
+    let mut view = matches.value_of("extent").map(|extent| View {
+        center: center.unwrap_or((0.0, 0.0)),
+        extent: extent.parse().unwrap(),
+    });
+    let neighbors = matches
+        .value_of("neighbors")
+        .unwrap_or("16")
+        .parse()
+        .unwrap();
+    // a per-body quantity of a frame
+    let quantity = |name: &str, bodies: &[BodyState]| -> Vec<f32> {
+        match name {
+            "mass" => bodies.iter().map(|b| b.mass).collect(),
+            "speed" => bodies
+                .iter()
+                .map(|b| b.velocity.x.hypot(b.velocity.y))
+                .collect(),
+            // snapshots do not record it, so it is recomputed with the default gravity
+            "acceleration" => render::accelerations(bodies, &Gravity::default()),
+            _ => {
+                let points = bodies
+                    .iter()
+                    .map(|b| (b.position.x, b.position.y))
+                    .collect();
+                let masses: Vec<f32> = bodies.iter().map(|b| b.mass).collect();
+                cells::densities(points, &masses, neighbors)
+            }
+        }
+    };
+    let size_by = matches.value_of("size-by");
+    // colour by group when the first frame has more than one
+    let mut colour = matches.value_of("colour").map(str::to_string);
+    for path in matches.values_of("trajectory").unwrap() {
+        for frame in trajectory::read(path).unwrap_or_else(|e| panic!("{}", e)) {
+            // every frame shares the view of the first, moved with --follow
+            let mut view = *view.get_or_insert_with(|| {
+                let fit = View::fit(&frame.bodies);
+                View {
+                    center: center.unwrap_or(fit.center),
+                    ..fit
+                }
+            });
+            if let Some(index) = follow {
+                let body = frame.bodies.get(index).unwrap_or_else(|| {
+                    panic!("{}: tick {} has no body {}", path, frame.tick, index)
+                });
+                view.center = (body.position.x, body.position.y);
+            }
+            let colour = colour.get_or_insert_with(|| {
+                if frame.bodies.iter().any(|b| b.group != 0) {
+                    "group".to_string()
+                } else {
+                    "none".to_string()
+                }
+            });
+            let sizes = size_by.map(|name| quantity(name, &frame.bodies));
+            let ppm = format!("{}_{:06}.ppm", prefix, frame.tick);
+            match colour.as_str() {
+                "none" if sizes.is_none() => {
+                    let image = format!("{}_{:06}.pgm", prefix, frame.tick);
+                    render::write_pgm(&image, &frame.bodies, view, size)
+                        .expect("failed to write image");
+                    eprintln!("{} -> {}", path, image);
+                }
+                // group 0 is white, so this is greyscale for a single group
+                "group" | "none" => {
+                    render::write_ppm(&ppm, &frame.bodies, sizes.as_deref(), view, size)
+                        .expect("failed to write image");
+                    eprintln!("{} -> {}", path, ppm);
+                }
+                name => {
+                    let values = quantity(name, &frame.bodies);
+                    let scale = render::write_scaled_ppm(
+                        &ppm,
+                        &frame.bodies,
+                        &values,
+                        sizes.as_deref(),
+                        view,
+                        size,
+                    )
+                    .expect("failed to write image");
+                    let (low, high) = scale.range();
+                    eprintln!("{} -> {} ({} {:.3e} to {:.3e})", path, ppm, name, low, high);
+                }
+            }
+        }
     }
+}
 
-    let mut scheduler = SystemScheduler::new(Arc::new(
-        ThreadPoolBuilder::new().num_threads(4).build().unwrap(),
-    ));
-    scheduler.add(UpdateTime {}, "update_time", vec![]);
-    scheduler.add(ApplyGravity {}, "apply_gravity", vec!["update_time"]);
-    scheduler.add(
-        ApplyAccelerations {},
-        "update_vels",
-        vec!["update_time", "apply_gravity"],
+fn convert(matches: &ArgMatches) {
+    let inputs: Vec<&str> = matches.values_of("input").unwrap().collect();
+    let extension = match matches.value_of("to").unwrap() {
+        "csv" => "csv",
+        "parquet" => "parquet",
+        "json" => "jsonl",
+        "binary" => "bin",
+        "hdf5" => "h5",
+        name => Compression::parse(name).unwrap().extension(),
+    };
+    let output = match matches.value_of("output") {
+        Some(path) => path.to_string(),
+        None => {
+            let stem = [
+                ".gz", ".zst", ".csv", ".parquet", ".jsonl", ".json", ".bin", ".h5",
+            ]
+            .iter()
+            .fold(inputs[0], |stem, e| stem.strip_suffix(e).unwrap_or(stem));
+            format!("{}.{}", stem, extension)
+        }
+    };
+    assert!(
+        !inputs.contains(&output.as_str()),
+        "--output is an input file"
     );
-    scheduler.add(
-        ApplyVelocities {},
-        "update_positions",
-        vec!["update_time", "update_vels"],
+
+    let mut frames = Vec::new();
+    for input in &inputs {
+        frames.extend(trajectory::read(input).unwrap_or_else(|e| panic!("{}", e)));
+    }
+    let every = |name| matches.value_of(name).unwrap_or("1").parse().unwrap();
+    let frames = trajectory::downsample(frames, every("every-frame"), every("every-body"));
+    let written = trajectory::write(&output, &frames).expect("failed to write output");
+    eprintln!(
+        "{} frames from {} files -> {}",
+        frames.len(),
+        inputs.len(),
+        match written.as_slice() {
+            [path] => path.display().to_string(),
+            _ => format!("{} files", written.len()),
+        }
     );
+}
 
-    drop(ents);
-    drop(bodies);
-    drop(masses);
-    drop(accels);
-    drop(vels);
-    drop(positions);
+fn diff(matches: &ArgMatches) {
+    let last_frame = |name: &str| {
+        let path = matches.value_of(name).unwrap();
+        match trajectory::read(path).map(|mut frames| frames.pop()) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                eprintln!("{}: no frames", path);
+                std::process::exit(2);
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(2);
+            }
+        }
+    };
+    let tolerance = |name: &str| -> f64 {
+        match matches.value_of(name).map(str::parse) {
+            None => 0.0,
+            Some(Ok(tolerance)) => tolerance,
+            Some(Err(_)) => {
+                eprintln!("invalid --{}", name);
+                std::process::exit(2);
+            }
+        }
+    };
+    let (first, second) = (last_frame("first"), last_frame("second"));
 
-    for _ in 0..100_000 {
-        scheduler.run(&world);
+    let diff = StateDiff::between(&first.bodies, &second.bodies);
+    if first.tick != second.tick || first.time != second.time {
+        println!(
+            "tick:     {} (time {}) against {} (time {})",
+            first.tick, first.time, second.tick, second.time
+        );
+    } else {
+        println!("tick:     {} (time {})", first.tick, first.time);
     }
+    println!("{}", diff);
+    let within = diff.within(
+        tolerance("position-tolerance"),
+        tolerance("velocity-tolerance"),
+        tolerance("mass-tolerance"),
+    );
+    if !within {
+        std::process::exit(1);
+    }
+}
+
+fn analyze(matches: &ArgMatches) {
+    let report =
+        StatsReport::read(matches.value_of("stats").unwrap()).unwrap_or_else(|e| panic!("{}", e));
+    println!("{}", report);
 }
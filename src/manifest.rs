@@ -0,0 +1,148 @@
+// The run manifest: a JSON object written when a run starts, with everything
+// needed to reproduce it (arguments, seed, resolved configuration, crate
+// version and commit) and where and when it ran, then rewritten when it ends
+// with the run summary and the last statistics sample.
+use crate::api::{number, stats_json};
+use crate::simulation::{Configuration, RunSummary};
+use crate::stats::StatsSample;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Manifest {
+    path: PathBuf,
+    // the members written at the start, one per line
+    members: Vec<String>,
+}
+
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64())
+}
+
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn configuration_json(config: &Configuration) -> String {
+    let systems: Vec<String> = config.systems.iter().map(|s| string(s)).collect();
+    format!(
+        "{{\"bodies\":{},\"species\":{},\"integrator\":\"{}\",\"timestep\":{},\"g\":{},\
+         \"force_exponent\":{},\"epsilon\":{},\"softening\":\"{}\",\"start_tick\":{},\
+         \"start_time\":{},\"systems\":[{}],\"estimated_memory\":{}}}",
+        config.bodies,
+        config.species,
+        config.integrator,
+        config
+            .step
+            .map_or("null".to_string(), |step| step.to_string()),
+        number(config.gravity.g),
+        number(config.gravity.exponent),
+        number(config.gravity.epsilon),
+        config.gravity.softening,
+        config.tick,
+        config.time,
+        systems.join(","),
+        config.estimated_memory
+    )
+}
+
+impl Manifest {
+    // Writes the manifest of a run started with `arguments` and random bodies
+    // (if any) drawn with `seed`, marked as running.
+    pub fn start<P: AsRef<Path>>(
+        path: P,
+        arguments: &[String],
+        seed: u64,
+        configuration: &Configuration,
+    ) -> io::Result<Self> {
+        let arguments: Vec<String> = arguments.iter().map(|a| string(a)).collect();
+        let manifest = Manifest {
+            path: path.as_ref().to_path_buf(),
+            members: vec![
+                format!("\"version\":{}", string(env!("CARGO_PKG_VERSION"))),
+                format!(
+                    "\"commit\":{}",
+                    option_env!("SMOLNBODY_COMMIT").map_or("null".to_string(), string)
+                ),
+                format!(
+                    "\"hostname\":{}",
+                    hostname().map_or("null".to_string(), |name| string(&name))
+                ),
+                format!("\"started\":{}", now()),
+                format!("\"arguments\":[{}]", arguments.join(",")),
+                format!("\"seed\":{}", seed),
+                format!("\"threads\":{}", configuration.threads),
+                format!("\"configuration\":{}", configuration_json(configuration)),
+            ],
+        };
+        manifest.write(&["\"status\":\"running\"".to_string()])?;
+        Ok(manifest)
+    }
+
+    // Rewrites the manifest with the outcome of the run.
+    pub fn finish(
+        &self,
+        summary: &RunSummary,
+        stats: Option<&StatsSample>,
+        interrupted: bool,
+    ) -> io::Result<()> {
+        let status = if interrupted {
+            "interrupted"
+        } else {
+            "completed"
+        };
+        self.write(&[
+            format!("\"status\":\"{}\"", status),
+            format!("\"finished\":{}", now()),
+            format!(
+                "\"summary\":{{\"ticks\":{},\"time\":{},\"wall_seconds\":{},\"bodies\":{},\
+                 \"energy_drift\":{}}}",
+                summary.ticks,
+                summary.time,
+                summary.wall_seconds,
+                summary.bodies,
+                number(summary.energy_drift)
+            ),
+            format!(
+                "\"final_stats\":{}",
+                stats.map_or("null".to_string(), stats_json)
+            ),
+        ])
+    }
+
+    fn write(&self, extra: &[String]) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        let members: Vec<&String> = self.members.iter().chain(extra).collect();
+        writeln!(out, "{{")?;
+        for (i, member) in members.iter().enumerate() {
+            let comma = if i + 1 < members.len() { "," } else { "" };
+            writeln!(out, "  {}{}", member, comma)?;
+        }
+        writeln!(out, "}}")?;
+        out.flush()
+    }
+}
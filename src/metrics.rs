@@ -0,0 +1,226 @@
+// Prometheus text-format metrics served over HTTP at `/metrics`
+// (`--metrics <addr>`), for monitoring long runs.
+use crate::Simulation;
+#[cfg(feature = "api")]
+use std::fmt::Write as FmtWrite;
+#[cfg(feature = "api")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "api")]
+use std::thread;
+use std::time::Duration;
+#[cfg(feature = "api")]
+use std::time::Instant;
+#[cfg(feature = "api")]
+use tiny_http::{Header, Response, Server};
+
+// Upper bounds of the step latency histogram buckets, in seconds.
+#[cfg(feature = "api")]
+const LATENCY_BUCKETS: &[f64] = &[
+    1.0e-4, 2.5e-4, 5.0e-4, 1.0e-3, 2.5e-3, 5.0e-3, 1.0e-2, 2.5e-2, 5.0e-2, 0.1, 0.25, 0.5, 1.0,
+];
+
+#[cfg(feature = "api")]
+struct State {
+    started: Instant,
+    threads: usize,
+    ticks: u64,
+    bodies: u32,
+    initial_energy: Option<f32>,
+    energy_drift: f32,
+    // cumulative counts per bucket, plus the implicit +Inf bucket in `count`
+    buckets: Vec<u64>,
+    latency_sum: f64,
+    count: u64,
+    // rolling window for the tick rate gauge
+    window_start: Instant,
+    window_ticks: u64,
+    tick_rate: f64,
+}
+
+#[cfg(feature = "api")]
+pub struct Metrics {
+    state: Arc<Mutex<State>>,
+}
+
+// User plus system CPU time of this process, from /proc on Linux.
+#[cfg(feature = "api")]
+fn process_cpu_seconds() -> Option<f64> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // fields after the parenthesised command name; utime and stime are 14 and 15
+    let fields: Vec<&str> = stat.rsplit(')').next()?.split_whitespace().collect();
+    let utime: f64 = fields.get(11)?.parse().ok()?;
+    let stime: f64 = fields.get(12)?.parse().ok()?;
+    // clock ticks; USER_HZ is 100 on every mainstream Linux configuration
+    Some((utime + stime) / 100.0)
+}
+
+#[cfg(feature = "api")]
+impl Metrics {
+    // Binds `addr` and answers scrapes on a background thread.
+    pub fn serve(addr: &str, threads: usize) -> Result<Self, String> {
+        let server = Server::http(addr).map_err(|e| e.to_string())?;
+        let now = Instant::now();
+        let state = Arc::new(Mutex::new(State {
+            started: now,
+            threads,
+            ticks: 0,
+            bodies: 0,
+            initial_energy: None,
+            energy_drift: 0.0,
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            latency_sum: 0.0,
+            count: 0,
+            window_start: now,
+            window_ticks: 0,
+            tick_rate: 0.0,
+        }));
+
+        let shared = state.clone();
+        thread::spawn(move || {
+            let content_type =
+                Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..])
+                    .unwrap();
+            for request in server.incoming_requests() {
+                let result = if request.url() == "/metrics" {
+                    let body = render(&shared.lock().unwrap());
+                    request.respond(Response::from_string(body).with_header(content_type.clone()))
+                } else {
+                    request.respond(Response::empty(404))
+                };
+                if let Err(e) = result {
+                    eprintln!("failed to answer a metrics request: {}", e);
+                }
+            }
+        });
+
+        Ok(Metrics { state })
+    }
+
+    // Records one tick that took `latency` to step.
+    pub fn record(&self, latency: Duration, simulation: &Simulation) {
+        let mut state = self.state.lock().unwrap();
+        let seconds = latency.as_secs_f64();
+        for (bucket, bound) in state.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        state.latency_sum += seconds;
+        state.count += 1;
+        state.ticks += 1;
+
+        state.window_ticks += 1;
+        let window = state.window_start.elapsed().as_secs_f64();
+        if window >= 1.0 {
+            state.tick_rate = state.window_ticks as f64 / window;
+            state.window_start = Instant::now();
+            state.window_ticks = 0;
+        }
+
+        if let Some(sample) = simulation.latest_stats() {
+            state.bodies = sample.bodies;
+            let energy = sample.kinetic + sample.potential;
+            let initial = *state.initial_energy.get_or_insert(energy);
+            state.energy_drift = if initial != 0.0 {
+                ((energy - initial) / initial).abs()
+            } else {
+                (energy - initial).abs()
+            };
+        }
+    }
+}
+
+#[cfg(feature = "api")]
+fn render(state: &State) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, kind: &str, value: f64| {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+        writeln!(out, "{} {}", name, value).unwrap();
+    };
+    gauge(
+        "smolnbody_ticks_total",
+        "Ticks simulated since the run started.",
+        "counter",
+        state.ticks as f64,
+    );
+    gauge(
+        "smolnbody_tick_rate",
+        "Ticks per second over the last second or so.",
+        "gauge",
+        state.tick_rate,
+    );
+    gauge(
+        "smolnbody_bodies",
+        "Bodies in the simulation.",
+        "gauge",
+        state.bodies as f64,
+    );
+    gauge(
+        "smolnbody_energy_drift",
+        "Relative change in total energy since the first tick.",
+        "gauge",
+        state.energy_drift as f64,
+    );
+    gauge(
+        "smolnbody_threads",
+        "Worker threads in the scheduler's pool.",
+        "gauge",
+        state.threads as f64,
+    );
+    if let Some(cpu) = process_cpu_seconds() {
+        gauge(
+            "process_cpu_seconds_total",
+            "Total user and system CPU time spent in seconds.",
+            "counter",
+            cpu,
+        );
+        let wall = state.started.elapsed().as_secs_f64() * state.threads as f64;
+        gauge(
+            "smolnbody_thread_utilization",
+            "Average fraction of the worker threads kept busy since the run started.",
+            "gauge",
+            if wall > 0.0 { cpu / wall } else { 0.0 },
+        );
+    }
+
+    writeln!(
+        out,
+        "# HELP smolnbody_step_seconds Time taken by each tick."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE smolnbody_step_seconds histogram").unwrap();
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(&state.buckets) {
+        writeln!(
+            out,
+            "smolnbody_step_seconds_bucket{{le=\"{}\"}} {}",
+            bound, count
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "smolnbody_step_seconds_bucket{{le=\"+Inf\"}} {}",
+        state.count
+    )
+    .unwrap();
+    writeln!(out, "smolnbody_step_seconds_sum {}", state.latency_sum).unwrap();
+    writeln!(out, "smolnbody_step_seconds_count {}", state.count).unwrap();
+    out
+}
+
+// Builds without the "api" feature have no HTTP server to answer scrapes.
+#[cfg(not(feature = "api"))]
+pub struct Metrics;
+
+#[cfg(not(feature = "api"))]
+impl Metrics {
+    pub fn serve(addr: &str, _threads: usize) -> Result<Self, String> {
+        Err(format!(
+            "serving {} needs a build with the \"api\" feature",
+            addr
+        ))
+    }
+
+    pub fn record(&self, _latency: Duration, _simulation: &Simulation) {}
+}
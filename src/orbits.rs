@@ -0,0 +1,89 @@
+// Conversions between planar Keplerian orbital elements and relative state
+// vectors. The simulation is 2D, so the only inclinations are 0 (prograde,
+// counter-clockwise) and 180 degrees (retrograde); angles are in radians.
+
+#[derive(Copy, Clone, Debug)]
+pub struct Elements {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub retrograde: bool,
+    pub argument_of_periapsis: f64,
+    pub mean_anomaly: f64,
+}
+
+// Solves Kepler's equation M = E - e sin E for the eccentric anomaly.
+fn eccentric_anomaly(mean_anomaly: f64, e: f64) -> f64 {
+    let mut anomaly = if e < 0.8 {
+        mean_anomaly
+    } else {
+        std::f64::consts::PI
+    };
+    for _ in 0..50 {
+        let step = (anomaly - e * anomaly.sin() - mean_anomaly) / (1.0 - e * anomaly.cos());
+        anomaly -= step;
+        if step.abs() < 1.0e-12 {
+            break;
+        }
+    }
+    anomaly
+}
+
+// Position and velocity relative to the primary, for a bound orbit with
+// gravitational parameter `mu` = G (m1 + m2).
+pub fn to_state(elements: &Elements, mu: f64) -> ([f64; 2], [f64; 2]) {
+    let a = elements.semi_major_axis;
+    let e = elements.eccentricity;
+    let b = a * (1.0 - e * e).sqrt();
+    let big_e = eccentric_anomaly(elements.mean_anomaly, e);
+    let (sin_e, cos_e) = big_e.sin_cos();
+    let n = (mu / (a * a * a)).sqrt();
+    let rate = n / (1.0 - e * cos_e);
+
+    // perifocal frame, periapsis along +x
+    let sense = if elements.retrograde { -1.0 } else { 1.0 };
+    let (px, py) = (a * (cos_e - e), sense * b * sin_e);
+    let (vx, vy) = (-a * sin_e * rate, sense * b * cos_e * rate);
+
+    let (sin_w, cos_w) = elements.argument_of_periapsis.sin_cos();
+    (
+        [px * cos_w - py * sin_w, px * sin_w + py * cos_w],
+        [vx * cos_w - vy * sin_w, vx * sin_w + vy * cos_w],
+    )
+}
+
+// Osculating elements of a relative state. Returns None for unbound or
+// degenerate (radial) motion, which has no ellipse to describe.
+pub fn from_state(position: [f64; 2], velocity: [f64; 2], mu: f64) -> Option<Elements> {
+    let [x, y] = position;
+    let [vx, vy] = velocity;
+    let r = (x * x + y * y).sqrt();
+    let v2 = vx * vx + vy * vy;
+    let h = x * vy - y * vx;
+    let energy = 0.5 * v2 - mu / r;
+    if r == 0.0 || h == 0.0 || energy >= 0.0 {
+        return None;
+    }
+
+    let a = -mu / (2.0 * energy);
+    // eccentricity vector points at periapsis
+    let ex = vy * h / mu - x / r;
+    let ey = -vx * h / mu - y / r;
+    let e = (ex * ex + ey * ey).sqrt();
+    let omega = if e > 1.0e-12 { ey.atan2(ex) } else { 0.0 };
+
+    // true anomaly measured in the direction of motion
+    let sense = h.signum();
+    let true_anomaly = sense * (y.atan2(x) - omega);
+    let cos_e = (e + true_anomaly.cos()) / (1.0 + e * true_anomaly.cos());
+    let sin_e = (1.0 - e * e).sqrt() * true_anomaly.sin() / (1.0 + e * true_anomaly.cos());
+    let big_e = sin_e.atan2(cos_e);
+    let mean_anomaly = (big_e - e * sin_e).rem_euclid(2.0 * std::f64::consts::PI);
+
+    Some(Elements {
+        semi_major_axis: a,
+        eccentricity: e,
+        retrograde: h < 0.0,
+        argument_of_periapsis: omega.rem_euclid(2.0 * std::f64::consts::PI),
+        mean_anomaly,
+    })
+}
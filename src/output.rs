@@ -0,0 +1,140 @@
+// A background thread for disk output, so that writing snapshots and
+// statistics doesn't hold up the ticks. Systems hand it jobs that own copies
+// of what they write, through a bounded queue: when the disk can't keep up
+// and the queue is full, the physics waits for room rather than letting
+// copies pile up in memory, and the time spent waiting is reported.
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() -> io::Result<()> + Send>;
+
+// Wall-clock seconds between reports of waiting on a full queue.
+const REPORT_INTERVAL: f64 = 5.0;
+
+#[derive(Default)]
+struct Backpressure {
+    // jobs that found the queue full, and the time spent waiting for room
+    stalls: u64,
+    waited: Duration,
+    last_report: Option<Instant>,
+    // the first job to fail
+    error: Option<io::Error>,
+}
+
+// A cloneable handle for queueing jobs on an `OutputQueue`.
+#[derive(Clone)]
+pub struct OutputHandle {
+    // `None` stops the thread
+    jobs: SyncSender<Option<Job>>,
+    backpressure: Arc<Mutex<Backpressure>>,
+}
+
+impl OutputHandle {
+    // Queues `job`, waiting for room when the queue is full. Jobs run in the
+    // order they were queued.
+    pub fn submit<F>(&self, job: F)
+    where
+        F: FnOnce() -> io::Result<()> + Send + 'static,
+    {
+        let job = match self.jobs.try_send(Some(Box::new(job))) {
+            Ok(()) => return,
+            Err(TrySendError::Full(job)) => job,
+            // the thread is gone after a panic; finish reports it
+            Err(TrySendError::Disconnected(_)) => return,
+        };
+        let started = Instant::now();
+        if self.jobs.send(job).is_err() {
+            return;
+        }
+        let mut backpressure = self.backpressure.lock().unwrap();
+        backpressure.stalls += 1;
+        backpressure.waited += started.elapsed();
+        let due = backpressure
+            .last_report
+            .is_none_or(|at| at.elapsed().as_secs_f64() >= REPORT_INTERVAL);
+        if due {
+            eprintln!(
+                "output queue full: the physics has waited {:.2} s on disk output ({} times)",
+                backpressure.waited.as_secs_f64(),
+                backpressure.stalls
+            );
+            backpressure.last_report = Some(Instant::now());
+        }
+    }
+}
+
+// How much the physics had to wait on output over a run.
+pub struct OutputReport {
+    pub jobs: u64,
+    pub stalls: u64,
+    pub waited: Duration,
+}
+
+impl fmt::Display for OutputReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "output: {} writes in the background, {} waited for room in the queue ({:.2} s)",
+            self.jobs,
+            self.stalls,
+            self.waited.as_secs_f64()
+        )
+    }
+}
+
+pub struct OutputQueue {
+    handle: OutputHandle,
+    worker: JoinHandle<u64>,
+}
+
+impl OutputQueue {
+    // Starts the thread, with room for `capacity` jobs waiting to run.
+    pub fn new(capacity: usize) -> Self {
+        let (jobs, queued) = mpsc::sync_channel::<Option<Job>>(capacity);
+        let backpressure = Arc::new(Mutex::new(Backpressure::default()));
+        let failures = backpressure.clone();
+        let worker = thread::spawn(move || {
+            let mut done = 0;
+            for job in queued.iter().map_while(|job| job) {
+                if let Err(e) = job() {
+                    eprintln!("background output failed: {}", e);
+                    failures.lock().unwrap().error.get_or_insert(e);
+                }
+                done += 1;
+            }
+            done
+        });
+        OutputQueue {
+            handle: OutputHandle { jobs, backpressure },
+            worker,
+        }
+    }
+
+    pub fn handle(&self) -> OutputHandle {
+        self.handle.clone()
+    }
+
+    // Waits for the jobs queued so far to run and stops the thread; later jobs
+    // are dropped. Returns the first error, or how much the physics waited.
+    pub fn finish(self) -> io::Result<OutputReport> {
+        let OutputQueue { handle, worker } = self;
+        // fails only if the thread is already gone, which join reports
+        let _ = handle.jobs.send(None);
+        let jobs = worker
+            .join()
+            .map_err(|_| io::Error::other("the output thread panicked"))?;
+        let mut backpressure = handle.backpressure.lock().unwrap();
+        if let Some(e) = backpressure.error.take() {
+            return Err(e);
+        }
+        Ok(OutputReport {
+            jobs,
+            stalls: backpressure.stalls,
+            waited: backpressure.waited,
+        })
+    }
+}
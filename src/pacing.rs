@@ -0,0 +1,64 @@
+// Soft real-time pacing for `--realtime <factor>`: after each tick the loop
+// sleeps until simulated time is `factor` times the wall-clock time since the
+// start. A run that falls too far behind gives up on catching up rather than
+// racing through a backlog of ticks, and says how fast it actually went.
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How far behind schedule, in wall-clock seconds, a run may fall before the
+// schedule is moved up to the present.
+const MAX_LAG: f64 = 0.25;
+
+// Wall-clock seconds between reports while behind.
+const REPORT_INTERVAL: f64 = 1.0;
+
+pub struct Pacer {
+    factor: f64,
+    // the wall-clock instant and simulated time the schedule counts from
+    anchor: (Instant, f64),
+    // the start of the stretch behind schedule the speed is measured over
+    window: (Instant, f64),
+}
+
+impl Pacer {
+    pub fn new(factor: f64, time: f64) -> Self {
+        let now = Instant::now();
+        Pacer {
+            factor,
+            anchor: (now, time),
+            window: (now, time),
+        }
+    }
+
+    // Restarts the schedule from `time`, after a pause.
+    pub fn reset(&mut self, time: f64) {
+        let now = Instant::now();
+        self.anchor = (now, time);
+        self.window = (now, time);
+    }
+
+    // Sleeps until simulated `time` is due. When the run is behind, returns
+    // the multiple of wall-clock time it has been managing, at most once per
+    // `REPORT_INTERVAL`.
+    pub fn wait(&mut self, time: f64) -> Option<f64> {
+        let due = ((time - self.anchor.1) / self.factor).max(0.0);
+        let elapsed = self.anchor.0.elapsed().as_secs_f64();
+        if due >= elapsed {
+            thread::sleep(Duration::from_secs_f64(due - elapsed));
+            self.window = (Instant::now(), time);
+            return None;
+        }
+
+        let now = Instant::now();
+        if elapsed - due > MAX_LAG {
+            self.anchor = (now, time);
+        }
+        let measured = now.duration_since(self.window.0).as_secs_f64();
+        if measured < REPORT_INTERVAL {
+            return None;
+        }
+        let speed = (time - self.window.1) / measured;
+        self.window = (now, time);
+        Some(speed)
+    }
+}
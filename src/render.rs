@@ -0,0 +1,280 @@
+// Draws snapshots as greyscale PGM images, or PPM images coloured by group or
+// by a per-body quantity such as local density or speed and optionally sized
+// by another, for a quick look at a run without the web viewer.
+use crate::resources::Gravity;
+use crate::scenarios::BodyState;
+use crate::systems::overlapping;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// The square region of the plane an image covers.
+#[derive(Copy, Clone)]
+pub struct View {
+    pub center: (f32, f32),
+    // half the side of the square
+    pub extent: f32,
+}
+
+impl View {
+    // The smallest square holding every body, with a small margin.
+    pub fn fit(bodies: &[BodyState]) -> View {
+        if bodies.is_empty() {
+            return View {
+                center: (0.0, 0.0),
+                extent: 1.0,
+            };
+        }
+        let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+        let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for b in bodies {
+            min_x = min_x.min(b.position.x);
+            min_y = min_y.min(b.position.y);
+            max_x = max_x.max(b.position.x);
+            max_y = max_y.max(b.position.y);
+        }
+        let extent = 0.5 * (max_x - min_x).max(max_y - min_y) * 1.05;
+        View {
+            center: (0.5 * (min_x + max_x), 0.5 * (min_y + max_y)),
+            extent: if extent > 0.0 { extent } else { 1.0 },
+        }
+    }
+}
+
+// Colours of groups 0, 1, ..., repeating.
+const PALETTE: [(f32, f32, f32); 6] = [
+    (1.0, 1.0, 1.0),
+    (1.0, 0.45, 0.2),
+    (0.3, 0.6, 1.0),
+    (0.4, 0.9, 0.3),
+    (0.9, 0.3, 0.8),
+    (1.0, 0.85, 0.2),
+];
+
+// Radius in pixels of the largest bodies drawn with `sizes`.
+pub const MAX_RADIUS: usize = 4;
+
+// The pixel each body falls in, if any.
+fn pixel(b: &BodyState, view: View, size: usize) -> Option<usize> {
+    let scale = size as f32 / (2.0 * view.extent);
+    let col = (b.position.x - view.center.0 + view.extent) * scale;
+    let row = (view.center.1 + view.extent - b.position.y) * scale;
+    if col >= 0.0 && row >= 0.0 && (col as usize) < size && (row as usize) < size {
+        Some(row as usize * size + col as usize)
+    } else {
+        None
+    }
+}
+
+// The pixels of a disc of `radius` pixels around the one body `b` falls in,
+// clipped to the image; only that pixel for a radius of zero.
+fn disc(b: &BodyState, view: View, size: usize, radius: usize) -> Vec<usize> {
+    let scale = size as f32 / (2.0 * view.extent);
+    let col = ((b.position.x - view.center.0 + view.extent) * scale).floor() as i64;
+    let row = ((view.center.1 + view.extent - b.position.y) * scale).floor() as i64;
+    let r = radius as i64;
+    let mut pixels = Vec::new();
+    for dr in -r..=r {
+        for dc in -r..=r {
+            let (c, rw) = (col + dc, row + dr);
+            let inside = c >= 0 && rw >= 0 && c < size as i64 && rw < size as i64;
+            if inside && dr * dr + dc * dc <= r * r {
+                pixels.push(rw as usize * size + c as usize);
+            }
+        }
+    }
+    pixels
+}
+
+// Maps a per-body quantity onto [0, 1] on a log scale between its smallest and
+// largest positive values. Zero and negative values map to 0.
+#[derive(Copy, Clone)]
+pub struct LogScale {
+    // log10 of the smallest and largest positive values
+    pub min: f32,
+    pub max: f32,
+}
+
+impl LogScale {
+    pub fn fit(values: &[f32]) -> Self {
+        let logs = values.iter().filter(|&&v| v > 0.0).map(|v| v.log10());
+        let (min, max) = logs.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), l| {
+            (lo.min(l), hi.max(l))
+        });
+        if min.is_finite() {
+            LogScale { min, max }
+        } else {
+            LogScale { min: 0.0, max: 0.0 }
+        }
+    }
+
+    pub fn at(&self, value: f32) -> f32 {
+        if value > 0.0 && value.is_finite() {
+            ((value.log10() - self.min) / (self.max - self.min).max(f32::MIN_POSITIVE))
+                .clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    // The smallest and largest positive values.
+    pub fn range(&self) -> (f32, f32) {
+        (10f32.powf(self.min), 10f32.powf(self.max))
+    }
+}
+
+// The magnitude of the gravitational acceleration of each body towards all the
+// others under `gravity`, summed directly, for colouring snapshots that do not
+// record it.
+pub fn accelerations(bodies: &[BodyState], gravity: &Gravity) -> Vec<f32> {
+    bodies
+        .iter()
+        .map(|one| {
+            let (mut ax, mut ay) = (0.0f32, 0.0f32);
+            for two in bodies {
+                if overlapping(&one.position, &two.position, gravity.epsilon) {
+                    continue;
+                }
+                let (dx, dy) = (
+                    two.position.x - one.position.x,
+                    two.position.y - one.position.y,
+                );
+                let f = gravity.g * two.mass * gravity.factor(dx * dx + dy * dy);
+                ax += f * dx;
+                ay += f * dy;
+            }
+            ax.hypot(ay)
+        })
+        .collect()
+}
+
+// Writes a `size`x`size` image of the bodies in `view`, with +y up. Pixels are
+// brighter the more bodies fall in them, on a square-root scale so sparse
+// regions stay visible next to dense ones.
+pub fn write_pgm<P: AsRef<Path>>(
+    path: P,
+    bodies: &[BodyState],
+    view: View,
+    size: usize,
+) -> io::Result<()> {
+    let mut counts = vec![0u32; size * size];
+    for i in bodies.iter().filter_map(|b| pixel(b, view, size)) {
+        counts[i] += 1;
+    }
+
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let pixels: Vec<u8> = counts
+        .iter()
+        .map(|&n| (255.0 * (n as f32 / max).sqrt()).round() as u8)
+        .collect();
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P5\n{} {}\n255\n", size, size)?;
+    out.write_all(&pixels)?;
+    out.flush()
+}
+
+// As `write_pgm`, in colour: each pixel is the mix of the colours of the groups
+// of the bodies in it, as bright as it would be in `write_pgm`. With `sizes`,
+// bodies are drawn as discs from a single pixel for the lowest of them to
+// `MAX_RADIUS` for the highest, on a log scale.
+pub fn write_ppm<P: AsRef<Path>>(
+    path: P,
+    bodies: &[BodyState],
+    sizes: Option<&[f32]>,
+    view: View,
+    size: usize,
+) -> io::Result<()> {
+    write_colours(path, bodies, sizes, false, view, size, |i| {
+        PALETTE[bodies[i].group as usize % PALETTE.len()]
+    })
+}
+
+// As `write_ppm`, coloured by `values` of the bodies, such as their densities
+// or speeds, instead of their groups, from dark red for the lowest to white for
+// the highest on a log scale. A colour bar of the scale is drawn along the
+// bottom left, and the scale returned so its range can be given with the image.
+pub fn write_scaled_ppm<P: AsRef<Path>>(
+    path: P,
+    bodies: &[BodyState],
+    values: &[f32],
+    sizes: Option<&[f32]>,
+    view: View,
+    size: usize,
+) -> io::Result<LogScale> {
+    let scale = LogScale::fit(values);
+    write_colours(path, bodies, sizes, true, view, size, |i| {
+        heat(scale.at(values[i]))
+    })?;
+    Ok(scale)
+}
+
+// Red, then yellow, then white as `t` goes from 0 to 1.
+fn heat(t: f32) -> (f32, f32, f32) {
+    let t = t.clamp(0.0, 1.0) * 3.0;
+    (
+        (0.3 + t).min(1.0),
+        (t - 1.0).clamp(0.0, 1.0),
+        (t - 2.0).clamp(0.0, 1.0),
+    )
+}
+
+// Writes a PPM image in which each pixel is the mean of `colour` of the bodies
+// in it, as bright as it would be in `write_pgm`, with bodies sized by `sizes`
+// as for `write_ppm` and, with `legend`, the `heat` colour bar.
+fn write_colours<P: AsRef<Path>>(
+    path: P,
+    bodies: &[BodyState],
+    sizes: Option<&[f32]>,
+    legend: bool,
+    view: View,
+    size: usize,
+    colour: impl Fn(usize) -> (f32, f32, f32),
+) -> io::Result<()> {
+    let radius = sizes.map(|sizes| (LogScale::fit(sizes), sizes));
+    let mut counts = vec![0u32; size * size];
+    let mut colours = vec![(0.0f32, 0.0f32, 0.0f32); size * size];
+    for (index, body) in bodies.iter().enumerate() {
+        let covered = match radius {
+            Some((scale, sizes)) => {
+                let r = (scale.at(sizes[index]) * MAX_RADIUS as f32).round() as usize;
+                disc(body, view, size, r)
+            }
+            None => pixel(body, view, size).into_iter().collect(),
+        };
+        for i in covered {
+            let (r, g, b) = colour(index);
+            counts[i] += 1;
+            colours[i].0 += r;
+            colours[i].1 += g;
+            colours[i].2 += b;
+        }
+    }
+
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let mut pixels = Vec::with_capacity(3 * size * size);
+    for (&n, &(r, g, b)) in counts.iter().zip(&colours) {
+        // mean colour in the pixel times its brightness
+        let scale = if n > 0 {
+            255.0 * (n as f32 / max).sqrt() / n as f32
+        } else {
+            0.0
+        };
+        pixels.extend([r, g, b].iter().map(|c| (c * scale).round() as u8));
+    }
+    if legend {
+        // lowest on the left, a quarter of the width long
+        let (length, height, margin) = (size / 4, (size / 64).max(3), (size / 64).max(2));
+        for row in size.saturating_sub(margin + height)..size.saturating_sub(margin) {
+            for col in margin..(margin + length).min(size) {
+                let (r, g, b) =
+                    heat((col - margin) as f32 / length.saturating_sub(1).max(1) as f32);
+                let i = 3 * (row * size + col);
+                pixels[i..i + 3].copy_from_slice(&[r, g, b].map(|c| (255.0 * c).round() as u8));
+            }
+        }
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P6\n{} {}\n255\n", size, size)?;
+    out.write_all(&pixels)?;
+    out.flush()
+}
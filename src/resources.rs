@@ -0,0 +1,414 @@
+// Resources shared by the systems.
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::fmt;
+
+#[derive(Copy, Clone)]
+pub struct WorldBounds {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Copy, Clone)]
+pub struct Time {
+    pub beginning: std::time::Instant,
+    pub last: std::time::Instant,
+    pub total: f64,
+    pub delta: f64,
+    pub tick: u64,
+    // fixed timestep; wall-clock time between ticks when unset
+    pub step: Option<f64>,
+}
+
+impl Time {
+    pub fn new(step: Option<f64>) -> Self {
+        let now = std::time::Instant::now();
+        Time {
+            beginning: now,
+            last: now,
+            total: 0.0,
+            delta: 0.0,
+            tick: 0,
+            step,
+        }
+    }
+}
+
+pub const G: f32 = 6.67430e-11_f32;
+
+// The generator every stochastic system draws from. It is saved in
+// checkpoints, so a resumed run makes the same draws as an uninterrupted one;
+// rand_chacha 0.2 cannot report a generator's seed and stream, so they are
+// kept alongside it.
+#[derive(Clone)]
+pub struct RandomState {
+    pub rng: ChaCha8Rng,
+    seed: [u8; 32],
+    stream: u64,
+}
+
+impl RandomState {
+    pub fn new(seed: [u8; 32], stream: u64) -> Self {
+        let mut rng = ChaCha8Rng::from_seed(seed);
+        rng.set_stream(stream);
+        // get_word_pos underflows on a generator that has not filled its
+        // buffer yet; seeking to the start fills it without skipping output.
+        rng.set_word_pos(0);
+        RandomState { rng, seed, stream }
+    }
+
+    // A generator seeded from the operating system.
+    pub fn from_entropy() -> Self {
+        RandomState::new(rand::thread_rng().gen(), 0)
+    }
+
+    // `new(seed, stream)` advanced to `word_pos`, as saved by a checkpoint.
+    pub fn resume(seed: [u8; 32], stream: u64, word_pos: u128) -> Self {
+        let mut state = RandomState::new(seed, stream);
+        state.rng.set_word_pos(word_pos);
+        state
+    }
+
+    pub fn seed(&self) -> [u8; 32] {
+        self.seed
+    }
+
+    pub fn stream(&self) -> u64 {
+        self.stream
+    }
+
+    pub fn word_pos(&self) -> u128 {
+        self.rng.get_word_pos()
+    }
+}
+
+// Gravitational constant, the per-axis separation below which a pair is
+// treated as overlapping and skipped, how gravity is smoothed at short range,
+// and the exponent p of the 1/r^p force law, 2 for Newtonian gravity.
+#[derive(Copy, Clone)]
+pub struct Gravity {
+    pub g: f32,
+    pub epsilon: f32,
+    pub softening: Softening,
+    pub exponent: f32,
+}
+
+impl Default for Gravity {
+    fn default() -> Self {
+        Gravity {
+            g: G,
+            epsilon: 0.05,
+            softening: Softening::None,
+            exponent: 2.0,
+        }
+    }
+}
+
+impl Gravity {
+    // The factor f with a = G m f r for a pair at squared separation `r2`;
+    // 1/r^(p+1) unsoftened. Other force laws than 1/r^2 are Plummer-softened
+    // as (r^2 + epsilon^2)^(-(p+1)/2); the spline kernel is only defined for
+    // 1/r^2.
+    pub fn factor(&self, r2: f32) -> f32 {
+        self.factor_and_slope(r2).0
+    }
+
+    // `factor` along with (df/dr) / r, which the jerk needs.
+    pub fn factor_and_slope(&self, r2: f32) -> (f32, f32) {
+        if self.exponent == 2.0 {
+            return self.softening.factor_and_slope(r2);
+        }
+        let s2 = match self.softening {
+            Softening::Plummer(epsilon) => r2 + epsilon * epsilon,
+            _ => r2,
+        };
+        let f = s2.powf(-0.5 * (self.exponent + 1.0));
+        (f, -(self.exponent + 1.0) * f / s2)
+    }
+
+    // The potential per unit G m1 m2 at separation `r`, so that a pair's
+    // potential energy is -G m1 m2 times it: the softened 1/r for 1/r^2,
+    // r^(1-p) / (p - 1) otherwise and -ln r for p = 1, which only differences
+    // are meaningful for.
+    pub fn potential(&self, r: f32) -> f32 {
+        if self.exponent == 2.0 {
+            return self.softening.inverse_distance(r);
+        }
+        let s = match self.softening {
+            Softening::Plummer(epsilon) => (r * r + epsilon * epsilon).sqrt(),
+            _ => r,
+        };
+        if self.exponent == 1.0 {
+            -s.ln()
+        } else {
+            s.powf(1.0 - self.exponent) / (self.exponent - 1.0)
+        }
+    }
+}
+
+// Replaces 1/r^2 gravity at short range so that close pairs don't produce
+// unbounded accelerations. Both kernels take the Plummer-equivalent length
+// epsilon: the potential at zero separation is -G m / epsilon either way.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Softening {
+    None,
+    // a = G m r / (r^2 + epsilon^2)^(3/2), which is below Newtonian at every
+    // separation and only slowly converges to it
+    Plummer(f32),
+    // the cubic spline of Monaghan & Lattanzio (1985) as in GADGET-2, with
+    // support h = 2.8 epsilon: exactly Newtonian beyond h
+    Spline(f32),
+}
+
+impl Softening {
+    // The factor f with a = G m f r for a pair at squared separation `r2`;
+    // 1/r^3 unsoftened.
+    pub fn factor(self, r2: f32) -> f32 {
+        self.factor_and_slope(r2).0
+    }
+
+    // `factor` along with (df/dr) / r, which the jerk needs.
+    pub fn factor_and_slope(self, r2: f32) -> (f32, f32) {
+        match self {
+            Softening::Plummer(epsilon) => {
+                let s2 = r2 + epsilon * epsilon;
+                let f = 1.0 / (s2 * s2.sqrt());
+                (f, -3.0 * f / s2)
+            }
+            Softening::Spline(epsilon) if r2 < (2.8 * epsilon).powi(2) => {
+                let h = 2.8 * epsilon;
+                let u = r2.sqrt() / h;
+                let (f, slope) = if u < 0.5 {
+                    (10.666667 + u * u * (32.0 * u - 38.4), -76.8 + 96.0 * u)
+                } else {
+                    (
+                        21.333333 - 48.0 * u + 38.4 * u * u
+                            - 10.666667 * u * u * u
+                            - 0.0666667 / (u * u * u),
+                        -48.0 / u + 76.8 - 32.0 * u + 0.2 / u.powi(5),
+                    )
+                };
+                (f / h.powi(3), slope / h.powi(5))
+            }
+            _ => {
+                let f = 1.0 / (r2 * r2.sqrt());
+                (f, -3.0 * f / r2)
+            }
+        }
+    }
+
+    // The softened 1/r, so that a pair's potential energy is -G m1 m2 times it.
+    pub fn inverse_distance(self, r: f32) -> f32 {
+        match self {
+            Softening::Plummer(epsilon) => 1.0 / (r * r + epsilon * epsilon).sqrt(),
+            Softening::Spline(epsilon) if r < 2.8 * epsilon => {
+                let h = 2.8 * epsilon;
+                let u = r / h;
+                let w = if u < 0.5 {
+                    -2.8 + u * u * (5.333333 + u * u * (6.4 * u - 9.6))
+                } else {
+                    -3.2 + 0.0666667 / u
+                        + u * u * (10.666667 + u * (-16.0 + u * (9.6 - 2.133333 * u)))
+                };
+                -w / h
+            }
+            _ => 1.0 / r,
+        }
+    }
+}
+
+impl fmt::Display for Softening {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Softening::None => write!(f, "none"),
+            Softening::Plummer(epsilon) => write!(f, "plummer {}", epsilon),
+            Softening::Spline(epsilon) => write!(f, "spline {}", epsilon),
+        }
+    }
+}
+
+// Enables the first post-Newtonian correction; `c` is the speed of light in
+// simulation units.
+#[derive(Copy, Clone)]
+pub struct Relativity {
+    pub c: f32,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HaloProfile {
+    // rho ~ 1 / (x (1 + x)^2); `mass` is the characteristic 4 pi rho_0 r_s^3
+    Nfw,
+    // rho ~ 1 / (x (1 + x)^3); `mass` is the total mass
+    Hernquist,
+}
+
+// A fixed spherical dark-matter halo centred on `centre`. Bodies feel the pull
+// of the mass enclosed within their radius but do not act back on it.
+#[derive(Copy, Clone, Debug)]
+pub struct Halo {
+    pub profile: HaloProfile,
+    pub mass: f32,
+    pub scale_radius: f32,
+    pub centre: (f32, f32),
+}
+
+impl Halo {
+    pub fn enclosed_mass(&self, r: f32) -> f32 {
+        let x = r / self.scale_radius;
+        match self.profile {
+            HaloProfile::Nfw => self.mass * ((1.0 + x).ln() - x / (1.0 + x)),
+            HaloProfile::Hernquist => self.mass * x * x / ((1.0 + x) * (1.0 + x)),
+        }
+    }
+}
+
+// Scales the pairwise force that species `a` feels from species `b`. Positive
+// values attract, negative values repel; the default single-species matrix of
+// 1.0 is plain gravity.
+#[derive(Clone)]
+pub struct InteractionMatrix {
+    species: usize,
+    coefficients: Vec<f32>,
+}
+
+impl InteractionMatrix {
+    pub fn uniform(species: usize) -> Self {
+        InteractionMatrix {
+            species,
+            coefficients: vec![1.0; species * species],
+        }
+    }
+
+    pub fn from_coefficients(species: usize, coefficients: Vec<f32>) -> Result<Self, String> {
+        if coefficients.len() != species * species {
+            return Err(format!(
+                "expected {} coefficients for {} species, got {}",
+                species * species,
+                species,
+                coefficients.len()
+            ));
+        }
+        Ok(InteractionMatrix {
+            species,
+            coefficients,
+        })
+    }
+
+    // Rows separated by ';', columns by ',', e.g. "1,-0.5;0.3,1".
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let rows: Vec<Vec<f32>> = text
+            .split(';')
+            .map(|row| {
+                row.split(',')
+                    .map(|c| {
+                        c.trim()
+                            .parse::<f32>()
+                            .map_err(|e| format!("bad coefficient '{}': {}", c.trim(), e))
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+
+        let species = rows.len();
+        if rows.iter().any(|row| row.len() != species) {
+            return Err(format!(
+                "interaction matrix must be square, got {} rows",
+                species
+            ));
+        }
+
+        Ok(InteractionMatrix {
+            species,
+            coefficients: rows.into_iter().flatten().collect(),
+        })
+    }
+
+    pub fn species(&self) -> usize {
+        self.species
+    }
+
+    // Row-major, `species * species` long.
+    pub fn coefficients(&self) -> &[f32] {
+        &self.coefficients
+    }
+
+    pub fn get(&self, a: usize, b: usize) -> f32 {
+        self.coefficients[a * self.species + b]
+    }
+}
+
+// The `BodyId`s of bodies added during a run, such as tidal fragments.
+pub struct BodyIds {
+    next: u64,
+}
+
+impl BodyIds {
+    pub fn starting_at(next: u64) -> Self {
+        BodyIds { next }
+    }
+
+    pub fn issue(&mut self) -> crate::components::BodyId {
+        self.next += 1;
+        crate::components::BodyId(self.next - 1)
+    }
+}
+
+// Pairs of groups whose members exert no force on each other, so that a
+// subsystem handled separately (say a planet and its moon, advanced
+// analytically) can sit inside the run without its internal forces being
+// counted twice. A group paired with itself has no forces between its own
+// members; to exclude a single pair of bodies, give them a group of their own.
+// Excluded pairs are also left out of the potential energy.
+#[derive(Clone, Default)]
+pub struct Exclusions {
+    // each pair smallest first
+    pairs: Vec<(u32, u32)>,
+}
+
+impl Exclusions {
+    pub fn none() -> Self {
+        Exclusions::default()
+    }
+
+    pub fn add(&mut self, a: u32, b: u32) {
+        let pair = (a.min(b), a.max(b));
+        if !self.pairs.contains(&pair) {
+            self.pairs.push(pair);
+        }
+    }
+
+    // Pairs separated by ';', each a single group or two separated by ',',
+    // e.g. "1;2,3" for no forces within group 1 or between groups 2 and 3.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut exclusions = Exclusions::none();
+        for pair in text.split(';').filter(|p| !p.trim().is_empty()) {
+            let groups = pair
+                .split(',')
+                .map(|g| {
+                    g.trim()
+                        .parse::<u32>()
+                        .map_err(|e| format!("bad group '{}': {}", g.trim(), e))
+                })
+                .collect::<Result<Vec<u32>, String>>()?;
+            match groups[..] {
+                [a] => exclusions.add(a, a),
+                [a, b] => exclusions.add(a, b),
+                _ => return Err(format!("expected one or two groups, got '{}'", pair)),
+            }
+        }
+        Ok(exclusions)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn pairs(&self) -> &[(u32, u32)] {
+        &self.pairs
+    }
+
+    // Whether bodies in groups `a` and `b` skip their mutual force.
+    pub fn excludes(&self, a: u32, b: u32) -> bool {
+        !self.pairs.is_empty() && self.pairs.contains(&(a.min(b), a.max(b)))
+    }
+}
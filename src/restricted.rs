@@ -0,0 +1,83 @@
+// The circular restricted three-body problem in the frame co-rotating with the
+// primaries: total mass 1, separation 1 and G = 1, so the angular velocity is 1
+// and the period 2*pi. The primary of mass 1 - mu sits at (-mu, 0) and the
+// secondary of mass mu at (1 - mu, 0).
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// The effective (gravitational plus centrifugal) potential per unit mass.
+pub fn effective_potential(mu: f64, x: f64, y: f64) -> f64 {
+    let r1 = ((x + mu).powi(2) + y * y).sqrt();
+    let r2 = ((x - 1.0 + mu).powi(2) + y * y).sqrt();
+    -(1.0 - mu) / r1 - mu / r2 - 0.5 * (x * x + y * y)
+}
+
+// The x component of the effective force along the line of the primaries.
+fn axial_force(mu: f64, x: f64) -> f64 {
+    let (d1, d2) = (x + mu, x - 1.0 + mu);
+    x - (1.0 - mu) * d1 / d1.abs().powi(3) - mu * d2 / d2.abs().powi(3)
+}
+
+// The root of the axial force between `lo` and `hi`, where it changes sign.
+fn bisect(mu: f64, mut lo: f64, mut hi: f64) -> f64 {
+    let lo_sign = axial_force(mu, lo).signum();
+    for _ in 0..100 {
+        let mid = 0.5 * (lo + hi);
+        if axial_force(mu, mid).signum() == lo_sign {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+// L1 to L5 in order. L1 lies between the primaries, L2 beyond the secondary
+// and L3 beyond the primary; L4 leads the secondary by 60 degrees and L5
+// trails it.
+pub fn lagrange_points(mu: f64) -> [(f64, f64); 5] {
+    let margin = 1.0e-9;
+    let triangle = (0.5 - mu, 3.0_f64.sqrt() / 2.0);
+    [
+        (bisect(mu, -mu + margin, 1.0 - mu - margin), 0.0),
+        (bisect(mu, 1.0 - mu + margin, 2.0), 0.0),
+        (bisect(mu, -2.0, -mu - margin), 0.0),
+        triangle,
+        (triangle.0, -triangle.1),
+    ]
+}
+
+// Writes the effective potential on a `resolution` x `resolution` grid over
+// [-extent, extent]^2 for contour plotting, after a comment line per Lagrange
+// point. Values inside the primaries are clipped to `floor` so they don't
+// swamp the colour scale.
+pub fn write_potential<P: AsRef<Path>>(
+    path: P,
+    mu: f64,
+    extent: f64,
+    resolution: usize,
+    floor: f64,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for (i, (x, y)) in lagrange_points(mu).iter().enumerate() {
+        writeln!(
+            writer,
+            "# L{} {} {} {}",
+            i + 1,
+            x,
+            y,
+            effective_potential(mu, *x, *y)
+        )?;
+    }
+    writeln!(writer, "x,y,potential")?;
+    let spacing = 2.0 * extent / (resolution - 1) as f64;
+    for i in 0..resolution {
+        for j in 0..resolution {
+            let (x, y) = (-extent + i as f64 * spacing, -extent + j as f64 * spacing);
+            let potential = effective_potential(mu, x, y).max(floor);
+            writeln!(writer, "{},{},{}", x, y, potential)?;
+        }
+    }
+    writer.flush()
+}
@@ -0,0 +1,317 @@
+// Declarative scenarios loaded with `--scenario-file`: a TOML document of
+// bodies, each of which may carry satellites described by orbital elements
+// relative to it, nested to any depth:
+//
+//   gravity = 39.478        # G, default 1
+//   dt = 1.0e-5             # default 1.0e-3
+//   c = 63239.7             # speed of light, for --relativistic
+//
+//   [halo]                  # optional fixed dark-matter halo
+//   profile = "nfw"         # or "hernquist"
+//   mass = 1.0e12           # NFW: 4 pi rho_0 r_s^3; Hernquist: total mass
+//   scale_radius = 20.0
+//   x = 0.0                 # centre, default the origin
+//   y = 0.0
+//
+//   [[body]]
+//   name = "sun"
+//   mass = 1.0
+//   group = 0               # for per-group statistics and colours, default 0;
+//                           # satellites default to their parent's
+//
+//     [[body.satellite]]
+//     name = "earth"
+//     mass = 3.0e-6
+//     a = 1.0               # semi-major axis
+//     e = 0.0167            # eccentricity, default 0
+//     inclination = 0       # degrees, 0 or 180 in the plane, default 0
+//     periapsis = 102.9     # argument of periapsis in degrees, default 0
+//     anomaly = 0           # mean anomaly in degrees, default 0
+//
+//       [[body.satellite.satellite]]
+//       name = "moon"
+//       mass = 3.7e-8
+//       a = 2.57e-3
+//
+// A satellite's elements describe the orbit of its own subsystem's centre of
+// mass about its parent body. Top-level bodies are placed at `x`, `y` with
+// velocity `vx`, `vy` (all default 0).
+use crate::components::{Position, Velocity};
+use crate::orbits::{self, Elements};
+use crate::resources::{Halo, HaloProfile};
+use crate::scenarios::{BodyState, Scenario};
+use toml::Value;
+
+const TOP_KEYS: &[&str] = &["gravity", "dt", "c", "halo", "body"];
+const HALO_KEYS: &[&str] = &["profile", "mass", "scale_radius", "x", "y"];
+const BODY_KEYS: &[&str] = &[
+    "name",
+    "mass",
+    "species",
+    "group",
+    "x",
+    "y",
+    "vx",
+    "vy",
+    "satellite",
+];
+const SATELLITE_KEYS: &[&str] = &[
+    "name",
+    "mass",
+    "species",
+    "group",
+    "a",
+    "e",
+    "inclination",
+    "periapsis",
+    "anomaly",
+    "satellite",
+];
+
+fn check_keys(value: &Value, allowed: &[&str], context: &str) -> Result<(), String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| format!("{} must be a table", context))?;
+    for key in table.keys() {
+        if !allowed.contains(&key.as_str()) {
+            return Err(format!("unknown key '{}' in {}", key, context));
+        }
+    }
+    Ok(())
+}
+
+fn number(value: &Value, key: &str, context: &str) -> Result<Option<f64>, String> {
+    match value.get(key) {
+        None => Ok(None),
+        Some(v) => v
+            .as_float()
+            .or_else(|| v.as_integer().map(|i| i as f64))
+            .map(Some)
+            .ok_or_else(|| format!("'{}' in {} must be a number", key, context)),
+    }
+}
+
+fn children<'v>(value: &'v Value, key: &str, context: &str) -> Result<&'v [Value], String> {
+    match value.get(key) {
+        None => Ok(&[]),
+        Some(v) => v
+            .as_array()
+            .map(|a| a.as_slice())
+            .ok_or_else(|| format!("'{}' in {} must be an array of tables", key, context)),
+    }
+}
+
+fn describe(value: &Value, fallback: &str) -> String {
+    match value.get("name").and_then(Value::as_str) {
+        Some(name) => format!("body '{}'", name),
+        None => fallback.to_string(),
+    }
+}
+
+fn elements(value: &Value, context: &str) -> Result<Elements, String> {
+    let a = number(value, "a", context)?.ok_or_else(|| format!("{} needs 'a'", context))?;
+    let e = number(value, "e", context)?.unwrap_or(0.0);
+    let inclination = number(value, "inclination", context)?.unwrap_or(0.0);
+    if a <= 0.0 {
+        return Err(format!("{} needs a positive semi-major axis", context));
+    }
+    if !(0.0..1.0).contains(&e) {
+        return Err(format!("{} needs an eccentricity in [0, 1)", context));
+    }
+    let inclination = inclination.rem_euclid(360.0);
+    if inclination != 0.0 && inclination != 180.0 {
+        return Err(format!(
+            "{} has inclination {}, but the simulation is planar (use 0 or 180)",
+            context, inclination
+        ));
+    }
+    let retrograde = inclination == 180.0;
+    Ok(Elements {
+        semi_major_axis: a,
+        eccentricity: e,
+        retrograde,
+        argument_of_periapsis: number(value, "periapsis", context)?
+            .unwrap_or(0.0)
+            .to_radians(),
+        mean_anomaly: number(value, "anomaly", context)?
+            .unwrap_or(0.0)
+            .to_radians(),
+    })
+}
+
+fn halo(value: &Value) -> Result<Halo, String> {
+    let context = "the halo";
+    check_keys(value, HALO_KEYS, context)?;
+    let profile = match value.get("profile").and_then(Value::as_str) {
+        Some("nfw") => HaloProfile::Nfw,
+        Some("hernquist") => HaloProfile::Hernquist,
+        Some(other) => return Err(format!("unknown halo profile '{}'", other)),
+        None => return Err("the halo needs a 'profile' of \"nfw\" or \"hernquist\"".to_string()),
+    };
+    let mass = number(value, "mass", context)?.ok_or("the halo needs 'mass'")?;
+    let scale_radius = number(value, "scale_radius", context)?
+        .filter(|&r| r > 0.0)
+        .ok_or("the halo needs a positive 'scale_radius'")?;
+    Ok(Halo {
+        profile,
+        mass: mass as f32,
+        scale_radius: scale_radius as f32,
+        centre: (
+            number(value, "x", context)?.unwrap_or(0.0) as f32,
+            number(value, "y", context)?.unwrap_or(0.0) as f32,
+        ),
+    })
+}
+
+// One body and everything orbiting it, in f64 until the end.
+struct Node {
+    mass: f64,
+    species: usize,
+    group: u32,
+    position: [f64; 2],
+    velocity: [f64; 2],
+}
+
+fn subsystem_mass(nodes: &[Node]) -> f64 {
+    nodes.iter().map(|n| n.mass).sum()
+}
+
+// Shifts a subsystem into its own centre-of-mass frame.
+fn centre(nodes: &mut [Node]) {
+    let total = subsystem_mass(nodes);
+    if total <= 0.0 {
+        return;
+    }
+    let mut com = [0.0; 4];
+    for n in nodes.iter() {
+        com[0] += n.mass * n.position[0] / total;
+        com[1] += n.mass * n.position[1] / total;
+        com[2] += n.mass * n.velocity[0] / total;
+        com[3] += n.mass * n.velocity[1] / total;
+    }
+    for n in nodes.iter_mut() {
+        n.position[0] -= com[0];
+        n.position[1] -= com[1];
+        n.velocity[0] -= com[2];
+        n.velocity[1] -= com[3];
+    }
+}
+
+// `group` is the parent's, for satellites that don't set their own.
+fn build(
+    value: &Value,
+    g: f64,
+    context: &str,
+    satellite: bool,
+    group: u32,
+) -> Result<Vec<Node>, String> {
+    let keys = if satellite { SATELLITE_KEYS } else { BODY_KEYS };
+    check_keys(value, keys, context)?;
+    let mass =
+        number(value, "mass", context)?.ok_or_else(|| format!("{} needs 'mass'", context))?;
+    if mass < 0.0 {
+        return Err(format!("{} has a negative mass", context));
+    }
+    let species = match value.get("species") {
+        None => 0,
+        Some(v) => v
+            .as_integer()
+            .filter(|&s| s >= 0)
+            .ok_or_else(|| format!("'species' in {} must be a non-negative integer", context))?
+            as usize,
+    };
+    let group = match value.get("group") {
+        None => group,
+        Some(v) => v
+            .as_integer()
+            .filter(|&g| (0..=u32::MAX as i64).contains(&g))
+            .ok_or_else(|| format!("'group' in {} must be a non-negative integer", context))?
+            as u32,
+    };
+
+    let mut nodes = vec![Node {
+        mass,
+        species,
+        group,
+        position: [0.0; 2],
+        velocity: [0.0; 2],
+    }];
+    for (i, child) in children(value, "satellite", context)?.iter().enumerate() {
+        let child_context = describe(child, &format!("satellite {} of {}", i + 1, context));
+        let orbit = elements(child, &child_context)?;
+        let mut subsystem = build(child, g, &child_context, true, group)?;
+        let mu = g * (mass + subsystem_mass(&subsystem));
+        let (position, velocity) = orbits::to_state(&orbit, mu);
+        for n in &mut subsystem {
+            n.position[0] += position[0];
+            n.position[1] += position[1];
+            n.velocity[0] += velocity[0];
+            n.velocity[1] += velocity[1];
+        }
+        nodes.extend(subsystem);
+    }
+    centre(&mut nodes);
+    Ok(nodes)
+}
+
+pub fn parse(text: &str) -> Result<Scenario, String> {
+    let document: Value = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    check_keys(&document, TOP_KEYS, "the scenario file")?;
+    let g = number(&document, "gravity", "the scenario file")?.unwrap_or(1.0);
+    let dt = number(&document, "dt", "the scenario file")?.unwrap_or(1.0e-3);
+    let c = number(&document, "c", "the scenario file")?;
+    let halo = document.get("halo").map(halo).transpose()?;
+
+    let mut bodies = Vec::new();
+    for (i, body) in children(&document, "body", "the scenario file")?
+        .iter()
+        .enumerate()
+    {
+        let context = describe(body, &format!("body {}", i + 1));
+        let mut subsystem = build(body, g, &context, false, 0)?;
+        let offset = [
+            number(body, "x", &context)?.unwrap_or(0.0),
+            number(body, "y", &context)?.unwrap_or(0.0),
+            number(body, "vx", &context)?.unwrap_or(0.0),
+            number(body, "vy", &context)?.unwrap_or(0.0),
+        ];
+        for n in &mut subsystem {
+            n.position[0] += offset[0];
+            n.position[1] += offset[1];
+            n.velocity[0] += offset[2];
+            n.velocity[1] += offset[3];
+        }
+        bodies.extend(subsystem);
+    }
+    if bodies.is_empty() {
+        return Err("the scenario file has no bodies".to_string());
+    }
+
+    Ok(Scenario {
+        gravity: g as f32,
+        dt,
+        speed_of_light: c.map(|c| c as f32),
+        halo,
+        bodies: bodies
+            .into_iter()
+            .map(|n| BodyState {
+                mass: n.mass as f32,
+                species: n.species,
+                group: n.group,
+                position: Position {
+                    x: n.position[0] as f32,
+                    y: n.position[1] as f32,
+                },
+                velocity: Velocity {
+                    x: n.velocity[0] as f32,
+                    y: n.velocity[1] as f32,
+                },
+            })
+            .collect(),
+    })
+}
+
+pub fn load(path: &str) -> Result<Scenario, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path, e))
+}
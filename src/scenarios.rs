@@ -0,0 +1,436 @@
+// Named initial conditions selectable with `--scenario`. Apart from the random
+// default, each preset is fully deterministic (the globular cluster draws from
+// a fixed seed), so runs can be compared against each other as fixtures.
+use crate::components::{Position, Velocity};
+use crate::resources::Halo;
+use crate::restricted;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+// G in units of AU, solar masses and years.
+const G_ASTRONOMICAL: f32 = 4.0 * std::f32::consts::PI * std::f32::consts::PI;
+
+// The speed of light in AU per year.
+pub const C_ASTRONOMICAL: f32 = 63_239.73;
+
+pub const NAMES: &[&str] = &[
+    "two-body",
+    "figure-eight",
+    "pythagorean",
+    "sun-earth-moon",
+    "binary-planet",
+    "globular-cluster",
+    "cluster-merger",
+    "restricted-three-body",
+];
+
+#[derive(Copy, Clone)]
+pub struct BodyState {
+    pub mass: f32,
+    pub species: usize,
+    pub group: u32,
+    pub position: Position,
+    pub velocity: Velocity,
+}
+
+pub struct Scenario {
+    pub gravity: f32,
+    pub dt: f64,
+    // in the scenario's units, where they are physical ones
+    pub speed_of_light: Option<f32>,
+    // analytic background potential
+    pub halo: Option<Halo>,
+    pub bodies: Vec<BodyState>,
+}
+
+fn body(mass: f32, x: f32, y: f32, vx: f32, vy: f32) -> BodyState {
+    BodyState {
+        mass,
+        species: 0,
+        group: 0,
+        position: Position { x, y },
+        velocity: Velocity { x: vx, y: vy },
+    }
+}
+
+// `count` is only used by scenarios with a variable number of bodies.
+pub fn by_name(name: &str, count: Option<u32>) -> Option<Scenario> {
+    match name {
+        "two-body" => Some(two_body()),
+        "figure-eight" => Some(figure_eight()),
+        "pythagorean" => Some(pythagorean()),
+        "sun-earth-moon" => Some(sun_earth_moon()),
+        "binary-planet" => Some(binary_planet()),
+        "globular-cluster" => Some(globular_cluster(count.unwrap_or(1000))),
+        "cluster-merger" => Some(cluster_merger(count.unwrap_or(1000))),
+        "restricted-three-body" => Some(restricted_three_body(
+            RESTRICTED_MASS_RATIO,
+            count.unwrap_or(20),
+        )),
+        _ => None,
+    }
+}
+
+// The default: masses in [1, 5), velocities in [-1, 1) and positions in the
+// 10x10 box, with species drawn uniformly.
+pub fn random<R: Rng>(n: u32, species: usize, rng: &mut R) -> Vec<BodyState> {
+    (0..n)
+        .map(|_| BodyState {
+            mass: rng.gen_range(1.0, 5.0),
+            species: rng.gen_range(0, species),
+            group: 0,
+            position: Position {
+                x: rng.gen_range(0.0, 10.0),
+                y: rng.gen_range(0.0, 10.0),
+            },
+            velocity: Velocity {
+                x: rng.gen_range(-1.0, 1.0),
+                y: rng.gen_range(-1.0, 1.0),
+            },
+        })
+        .collect()
+}
+
+// `count` massless tracers at rest, scattered uniformly over the box holding
+// `bodies` and in a group after theirs, to be added to them.
+pub fn tracers<R: Rng>(count: u32, bodies: &[BodyState], rng: &mut R) -> Vec<BodyState> {
+    let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+    let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for b in bodies {
+        min_x = min_x.min(b.position.x);
+        min_y = min_y.min(b.position.y);
+        max_x = max_x.max(b.position.x);
+        max_y = max_y.max(b.position.y);
+    }
+    if bodies.is_empty() || min_x >= max_x || min_y >= max_y {
+        (min_x, min_y, max_x, max_y) = (-1.0, -1.0, 1.0, 1.0);
+    }
+    let group = bodies.iter().map(|b| b.group + 1).max().unwrap_or(0);
+    (0..count)
+        .map(|_| BodyState {
+            mass: 0.0,
+            species: 0,
+            group,
+            position: Position {
+                x: rng.gen_range(min_x, max_x),
+                y: rng.gen_range(min_y, max_y),
+            },
+            velocity: Velocity { x: 0.0, y: 0.0 },
+        })
+        .collect()
+}
+
+// Replaces each body's velocity with the circular-orbit speed about the centre
+// of mass, v = sqrt(G M(<r) / r), counter-clockwise, where M(<r) is the mass
+// strictly inside its radius, plus that of the halo if there is one. Bodies at
+// the centre are left at rest.
+pub fn circular_velocities(bodies: &mut [BodyState], g: f32, halo: Option<&Halo>) {
+    let total: f32 = bodies.iter().map(|b| b.mass).sum();
+    if total <= 0.0 {
+        return;
+    }
+    let (mut cx, mut cy) = (0.0, 0.0);
+    for b in bodies.iter() {
+        cx += b.mass * b.position.x / total;
+        cy += b.mass * b.position.y / total;
+    }
+
+    let radius = |b: &BodyState| {
+        let (dx, dy) = (b.position.x - cx, b.position.y - cy);
+        (dx * dx + dy * dy).sqrt()
+    };
+    let mut order: Vec<usize> = (0..bodies.len()).collect();
+    order.sort_by(|&i, &j| radius(&bodies[i]).partial_cmp(&radius(&bodies[j])).unwrap());
+
+    let mut enclosed = 0.0;
+    for i in order {
+        let b = &mut bodies[i];
+        let (dx, dy) = (b.position.x - cx, b.position.y - cy);
+        let r = (dx * dx + dy * dy).sqrt();
+        if r > 0.0 {
+            let halo_mass = halo.map_or(0.0, |h| h.enclosed_mass(r));
+            let speed = (g * (enclosed + halo_mass) / r).sqrt();
+            b.velocity = Velocity {
+                x: -speed * dy / r,
+                y: speed * dx / r,
+            };
+        } else {
+            b.velocity = Velocity { x: 0.0, y: 0.0 };
+        }
+        enclosed += b.mass;
+    }
+}
+
+// Adds solid-body rotation about the centre of mass, counter-clockwise, to give
+// the bodies a spin parameter lambda = J |E|^(1/2) / (G M^(5/2)) (Peebles
+// 1969), with J the angular momentum about the centre of mass, including any
+// the bodies already had, and E the energy in the centre-of-mass frame before
+// the rotation is added, as is usual when setting up a collapse. Fails for
+// bodies that are not bound.
+pub fn spin(bodies: &mut [BodyState], lambda: f32, g: f32) -> Result<(), String> {
+    let total: f32 = bodies.iter().map(|b| b.mass).sum();
+    if total <= 0.0 {
+        return Err("the spin parameter needs bodies with mass".to_string());
+    }
+    let (mut cx, mut cy, mut cvx, mut cvy) = (0.0, 0.0, 0.0, 0.0);
+    for b in bodies.iter() {
+        cx += b.mass * b.position.x / total;
+        cy += b.mass * b.position.y / total;
+        cvx += b.mass * b.velocity.x / total;
+        cvy += b.mass * b.velocity.y / total;
+    }
+
+    let (mut kinetic, mut potential) = (0.0f64, 0.0f64);
+    let (mut momentum, mut inertia) = (0.0f64, 0.0f64);
+    for (i, b) in bodies.iter().enumerate() {
+        let (dx, dy) = (b.position.x - cx, b.position.y - cy);
+        let (vx, vy) = (b.velocity.x - cvx, b.velocity.y - cvy);
+        let mass = b.mass as f64;
+        kinetic += 0.5 * mass * (vx * vx + vy * vy) as f64;
+        momentum += mass * (dx * vy - dy * vx) as f64;
+        inertia += mass * (dx * dx + dy * dy) as f64;
+        for other in &bodies[i + 1..] {
+            let r = (b.position.x - other.position.x).hypot(b.position.y - other.position.y);
+            if r > 0.0 {
+                potential -= g as f64 * mass * other.mass as f64 / r as f64;
+            }
+        }
+    }
+    let energy = kinetic + potential;
+    if energy >= 0.0 {
+        return Err("the spin parameter is only defined for bound bodies".to_string());
+    }
+    if inertia <= 0.0 {
+        return Err("bodies all at the centre of mass cannot be spun up".to_string());
+    }
+
+    let target = lambda as f64 * g as f64 * (total as f64).powf(2.5) / (-energy).sqrt();
+    let omega = ((target - momentum) / inertia) as f32;
+    for b in bodies.iter_mut() {
+        b.velocity.x -= omega * (b.position.y - cy);
+        b.velocity.y += omega * (b.position.x - cx);
+    }
+    Ok(())
+}
+
+// Two bodies of mass 0.5 on a circular orbit of separation 1 with G = 1; the
+// period is exactly 2*pi, and the timestep divides it evenly.
+pub fn two_body() -> Scenario {
+    Scenario {
+        gravity: 1.0,
+        speed_of_light: None,
+        halo: None,
+        dt: 2.0 * std::f64::consts::PI / 2000.0,
+        bodies: vec![
+            body(0.5, -0.5, 0.0, 0.0, -0.5),
+            body(0.5, 0.5, 0.0, 0.0, 0.5),
+        ],
+    }
+}
+
+// Chenciner & Montgomery's equal-mass choreography with G = 1.
+pub const FIGURE_EIGHT_PERIOD: f64 = 6.325_913_98;
+
+// Initial conditions as published, beyond f32 precision.
+#[allow(clippy::excessive_precision)]
+pub fn figure_eight() -> Scenario {
+    let (x, y) = (0.970_004_36, -0.243_087_53);
+    let (vx, vy) = (-0.932_407_37, -0.864_731_46);
+    Scenario {
+        gravity: 1.0,
+        speed_of_light: None,
+        halo: None,
+        dt: FIGURE_EIGHT_PERIOD / 5000.0,
+        bodies: vec![
+            body(1.0, x, y, -vx / 2.0, -vy / 2.0),
+            body(1.0, -x, -y, -vx / 2.0, -vy / 2.0),
+            body(1.0, 0.0, 0.0, vx, vy),
+        ],
+    }
+}
+
+// Burrau's problem: masses 3, 4 and 5 at rest on the corners of a 3-4-5
+// right triangle, with G = 1.
+pub fn pythagorean() -> Scenario {
+    Scenario {
+        gravity: 1.0,
+        speed_of_light: None,
+        halo: None,
+        dt: 1.0e-4,
+        bodies: vec![
+            body(3.0, 1.0, 3.0, 0.0, 0.0),
+            body(4.0, -2.0, -1.0, 0.0, 0.0),
+            body(5.0, 1.0, -1.0, 0.0, 0.0),
+        ],
+    }
+}
+
+// Circular orbits in AU, solar masses and years.
+pub fn sun_earth_moon() -> Scenario {
+    let (m_earth, m_moon) = (3.003e-6, 3.694e-8);
+    let moon_distance = 2.57e-3;
+    let v_earth = (G_ASTRONOMICAL / 1.0_f32).sqrt();
+    let v_moon = v_earth + (G_ASTRONOMICAL * m_earth / moon_distance).sqrt();
+    Scenario {
+        gravity: G_ASTRONOMICAL,
+        speed_of_light: Some(C_ASTRONOMICAL),
+        halo: None,
+        dt: 1.0e-5,
+        bodies: vec![
+            body(1.0, 0.0, 0.0, 0.0, 0.0),
+            body(m_earth, 1.0, 0.0, 0.0, v_earth),
+            body(m_moon, 1.0 + moon_distance, 0.0, 0.0, v_moon),
+        ],
+    }
+}
+
+// An equal-mass circular binary 1 AU apart with a Jupiter-mass planet on a
+// circumbinary orbit at 5 AU.
+pub fn binary_planet() -> Scenario {
+    let separation = 1.0;
+    let v_star = (G_ASTRONOMICAL * 2.0 / separation).sqrt() / 2.0;
+    let planet_radius = 5.0;
+    let v_planet = (G_ASTRONOMICAL * 2.0 / planet_radius).sqrt();
+    Scenario {
+        gravity: G_ASTRONOMICAL,
+        speed_of_light: Some(C_ASTRONOMICAL),
+        halo: None,
+        dt: 1.0e-4,
+        bodies: vec![
+            body(1.0, -separation / 2.0, 0.0, 0.0, -v_star),
+            body(1.0, separation / 2.0, 0.0, 0.0, v_star),
+            body(9.5e-4, planet_radius, 0.0, 0.0, v_planet),
+        ],
+    }
+}
+
+// A Plummer sphere of total mass 1 and scale radius 1 with G = 1 (Aarseth,
+// Henon & Wielen 1974), laid into the simulation plane and shifted to its
+// centre-of-mass frame.
+pub fn globular_cluster(n: u32) -> Scenario {
+    let mut rng = ChaCha8Rng::seed_from_u64(0x5eed);
+    let mass = 1.0 / n as f32;
+
+    let mut bodies: Vec<BodyState> = (0..n)
+        .map(|_| {
+            // radius from the inverted cumulative mass profile, clipped at 10a
+            let r = loop {
+                let x: f32 = rng.gen_range(1.0e-3, 1.0);
+                let r = 1.0 / (x.powf(-2.0 / 3.0) - 1.0).sqrt();
+                if r < 10.0 {
+                    break r;
+                }
+            };
+
+            // speed as a fraction q of the local escape speed, by rejection
+            let q = loop {
+                let q: f32 = rng.gen_range(0.0, 1.0);
+                let g: f32 = rng.gen_range(0.0, 0.1);
+                if g < q * q * (1.0 - q * q).powf(3.5) {
+                    break q;
+                }
+            };
+            let speed = q * 2.0_f32.sqrt() * (1.0 + r * r).powf(-0.25);
+
+            let theta = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+            let phi = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+            body(
+                mass,
+                r * theta.cos(),
+                r * theta.sin(),
+                speed * phi.cos(),
+                speed * phi.sin(),
+            )
+        })
+        .collect();
+
+    let (mut x, mut y, mut vx, mut vy) = (0.0, 0.0, 0.0, 0.0);
+    for b in &bodies {
+        x += b.position.x / n as f32;
+        y += b.position.y / n as f32;
+        vx += b.velocity.x / n as f32;
+        vy += b.velocity.y / n as f32;
+    }
+    for b in &mut bodies {
+        b.position.x -= x;
+        b.position.y -= y;
+        b.velocity.x -= vx;
+        b.velocity.y -= vy;
+    }
+
+    Scenario {
+        gravity: 1.0,
+        speed_of_light: None,
+        halo: None,
+        dt: 1.0e-3,
+        bodies,
+    }
+}
+
+// Two `globular_cluster`s of n/2 bodies each, groups 0 and 1, falling together
+// from 10 scale radii apart on a parabolic orbit with an impact parameter of 2.
+// The second is the first turned half a revolution.
+pub fn cluster_merger(n: u32) -> Scenario {
+    let half = globular_cluster(n.div_ceil(2).max(1)).bodies;
+    // half the relative speed of a parabolic orbit of the combined mass
+    let speed = 0.5 * (2.0 * 2.0 / 10.0_f32).sqrt();
+
+    let mut bodies = Vec::with_capacity(2 * half.len());
+    for (group, sign) in [(0, -1.0), (1, 1.0)] {
+        bodies.extend(half.iter().map(|b| BodyState {
+            group,
+            position: Position {
+                x: sign * b.position.x + sign * 5.0,
+                y: sign * b.position.y + sign * 1.0,
+            },
+            velocity: Velocity {
+                x: sign * b.velocity.x - sign * speed,
+                y: sign * b.velocity.y,
+            },
+            ..*b
+        }));
+    }
+
+    Scenario {
+        gravity: 1.0,
+        speed_of_light: None,
+        halo: None,
+        dt: 1.0e-3,
+        bodies,
+    }
+}
+
+// Below Routh's critical ratio of 0.0385, so orbits about L4 and L5 are stable.
+pub const RESTRICTED_MASS_RATIO: f64 = 0.01;
+
+// The primaries of `restricted` on their circular orbit, with `n` massless test
+// particles co-rotating with them at small offsets (from a fixed seed) from L4
+// and L5, alternately, in group 1. The timestep divides the period evenly.
+pub fn restricted_three_body(mu: f64, n: u32) -> Scenario {
+    let mut rng = ChaCha8Rng::seed_from_u64(0x1a9);
+    // inertial velocity of a point at rest in the frame rotating at unit rate
+    let corotating = |mass: f32, x: f32, y: f32| body(mass, x, y, -y, x);
+
+    let mut bodies = vec![
+        corotating((1.0 - mu) as f32, -mu as f32, 0.0),
+        corotating(mu as f32, (1.0 - mu) as f32, 0.0),
+    ];
+    let points = restricted::lagrange_points(mu);
+    for i in 0..n {
+        let (x, y) = points[3 + (i % 2) as usize];
+        let dx: f32 = rng.gen_range(-0.01, 0.01);
+        let dy: f32 = rng.gen_range(-0.01, 0.01);
+        bodies.push(BodyState {
+            group: 1,
+            ..corotating(0.0, x as f32 + dx, y as f32 + dy)
+        });
+    }
+
+    Scenario {
+        gravity: 1.0,
+        speed_of_light: None,
+        halo: None,
+        dt: 2.0 * std::f64::consts::PI / 2000.0,
+        bodies,
+    }
+}
@@ -0,0 +1,64 @@
+// Extra per-body forces from a Rhai script, so new force laws can be tried
+// without recompiling. The script defines
+//
+//   fn force(x, y, vx, vy, mass, time) { [ax, ay] }
+//
+// which is called for every body each tick and returns an acceleration added
+// on top of gravity. Only built with the `scripting` feature.
+use crate::components::*;
+use crate::resources::*;
+use rhai::{Array, Engine, Scope, AST};
+use SmolECS::{component::*, system::*, world::*};
+
+pub struct ScriptForce {
+    engine: Engine,
+    ast: AST,
+}
+
+impl ScriptForce {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let engine = Engine::new();
+        let ast = engine
+            .compile(&text)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        Ok(ScriptForce { engine, ast })
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for ScriptForce {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        WriteComp<'d, Acceleration>,
+        Read<'d, Time>,
+    );
+
+    fn run(&self, (masses, positions, vels, mut accels, time): Self::SystemData) {
+        let mut scope = Scope::new();
+        for (mass, pos, vel, accel) in (&masses, &positions, &vels, &mut accels).join() {
+            let args = (
+                pos.x as f64,
+                pos.y as f64,
+                vel.x as f64,
+                vel.y as f64,
+                mass.mass as f64,
+                time.total,
+            );
+            let result: Array = self
+                .engine
+                .call_fn(&mut scope, &self.ast, "force", args)
+                .unwrap_or_else(|e| panic!("script force failed: {}", e));
+            let component = |i: usize| {
+                result
+                    .get(i)
+                    .and_then(|v| v.clone().try_cast::<f64>())
+                    .expect("script force must return [ax, ay]") as f32
+            };
+            accel.x += component(0);
+            accel.y += component(1);
+        }
+    }
+}
@@ -0,0 +1,405 @@
+// Headless server mode (`--serve`): streams body positions to any number of
+// WebSocket viewers and queues control commands sent back by them.
+//
+// Every frame is a binary message, little-endian:
+//
+//   tick u64, time f64, bodies u32, then per body x f32, y f32
+//
+// With level of detail (`--lod-keyframes`) frames are quantized instead and
+// start with a kind byte. A key frame holds every body, on a grid of 65536
+// steps per axis across a box around them:
+//
+//   1 u8, tick u64, time f64, key u32, x0 f32, y0 f32, step_x f32, step_y f32,
+//   bodies u32, then per body x u16, y u16 (position x0 + x * step_x, ...)
+//
+// and the frames between key frames only the bodies that are moving fast or
+// near the focus, on the grid of the last key frame `key`, each identified
+// by the gap between its index and the one after the previous body's:
+//
+//   2 u8, tick u64, time f64, key u32, bodies u32,
+//   then per body gap LEB128, x u16, y u16
+//
+// Commands are text messages, one per message:
+//
+//   pause | resume | dt <seconds> | dt wall | spawn <mass> <x> <y> <vx> <vy>
+//   | remove <x> <y> | kick <x> <y> <vx> <vy>
+//
+// `remove` deletes the body nearest the point and `kick` adds the velocity to
+// it, so a viewer can map clicks straight onto commands.
+//
+// Clients that fall behind skip to the newest frame rather than queueing,
+// sending the latest key frame first when they skipped one.
+use crate::components::{Position, Velocity};
+use crate::scenarios::BodyState;
+use std::io;
+#[cfg(feature = "server")]
+use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "server")]
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+#[cfg(feature = "server")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "server")]
+use std::thread;
+#[cfg(feature = "server")]
+use std::time::Duration;
+#[cfg(feature = "server")]
+use tungstenite::{Message, WebSocket};
+
+// A frame and whether it can be shown on its own, rather than on top of the
+// last key frame.
+#[cfg(feature = "server")]
+#[derive(Clone)]
+struct Frame {
+    data: Arc<Vec<u8>>,
+    key: bool,
+}
+
+pub enum Command {
+    Pause,
+    Resume,
+    // None switches back to wall-clock time
+    SetTimestep(Option<f64>),
+    Spawn(BodyState),
+    // the body nearest the point
+    Remove(Position),
+    Kick(Position, Velocity),
+}
+
+impl Command {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let number = |word: &str| {
+            word.parse::<f32>()
+                .map_err(|_| format!("'{}' is not a number", word))
+        };
+        match words.as_slice() {
+            ["pause"] => Ok(Command::Pause),
+            ["resume"] => Ok(Command::Resume),
+            ["dt", "wall"] => Ok(Command::SetTimestep(None)),
+            ["dt", dt] => match dt.parse::<f64>() {
+                Ok(dt) if dt > 0.0 => Ok(Command::SetTimestep(Some(dt))),
+                _ => Err(format!("'{}' is not a positive timestep", dt)),
+            },
+            ["spawn", mass, x, y, vx, vy] => Ok(Command::Spawn(BodyState {
+                mass: number(mass)?,
+                species: 0,
+                group: 0,
+                position: Position {
+                    x: number(x)?,
+                    y: number(y)?,
+                },
+                velocity: Velocity {
+                    x: number(vx)?,
+                    y: number(vy)?,
+                },
+            })),
+            ["remove", x, y] => Ok(Command::Remove(Position {
+                x: number(x)?,
+                y: number(y)?,
+            })),
+            ["kick", x, y, vx, vy] => Ok(Command::Kick(
+                Position {
+                    x: number(x)?,
+                    y: number(y)?,
+                },
+                Velocity {
+                    x: number(vx)?,
+                    y: number(vy)?,
+                },
+            )),
+            _ => Err(format!("unknown command '{}'", text.trim())),
+        }
+    }
+}
+
+pub fn encode_frame(tick: u64, time: f64, bodies: &[BodyState]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(20 + 8 * bodies.len());
+    frame.extend_from_slice(&tick.to_le_bytes());
+    frame.extend_from_slice(&time.to_le_bytes());
+    frame.extend_from_slice(&(bodies.len() as u32).to_le_bytes());
+    for body in bodies {
+        frame.extend_from_slice(&body.position.x.to_le_bytes());
+        frame.extend_from_slice(&body.position.y.to_le_bytes());
+    }
+    frame
+}
+
+// Which bodies frames between key frames carry: those faster than `speed` or
+// within `radius` of `focus`. Every `keyframes`-th frame, and any frame after
+// the body count changes, is a key frame.
+#[derive(Copy, Clone)]
+pub struct LevelOfDetail {
+    pub keyframes: u64,
+    pub speed: f32,
+    pub focus: (f32, f32),
+    pub radius: f32,
+}
+
+const KEY_FRAME: u8 = 1;
+const DELTA_FRAME: u8 = 2;
+
+// Encodes frames with a `LevelOfDetail`, remembering the grid of the last key
+// frame.
+pub struct LodEncoder {
+    detail: LevelOfDetail,
+    // frames to go before the next scheduled key frame
+    until_key: u64,
+    key: u32,
+    bodies: usize,
+    // x0, y0, step_x, step_y
+    grid: (f32, f32, f32, f32),
+}
+
+impl LodEncoder {
+    pub fn new(detail: LevelOfDetail) -> Self {
+        LodEncoder {
+            detail,
+            until_key: 0,
+            key: 0,
+            bodies: 0,
+            grid: (0.0, 0.0, 1.0, 1.0),
+        }
+    }
+
+    // The next frame and whether it is a key frame.
+    pub fn encode(&mut self, tick: u64, time: f64, bodies: &[BodyState]) -> (Vec<u8>, bool) {
+        let key = self.until_key == 0 || bodies.len() != self.bodies;
+        if key {
+            self.until_key = self.detail.keyframes.max(1) - 1;
+            self.key = self.key.wrapping_add(1);
+            self.bodies = bodies.len();
+            self.grid = grid(bodies);
+        } else {
+            self.until_key -= 1;
+        }
+
+        let mut frame = Vec::with_capacity(if key { 41 + 4 * bodies.len() } else { 64 });
+        frame.push(if key { KEY_FRAME } else { DELTA_FRAME });
+        frame.extend_from_slice(&tick.to_le_bytes());
+        frame.extend_from_slice(&time.to_le_bytes());
+        frame.extend_from_slice(&self.key.to_le_bytes());
+        if key {
+            let (x0, y0, step_x, step_y) = self.grid;
+            for value in [x0, y0, step_x, step_y] {
+                frame.extend_from_slice(&value.to_le_bytes());
+            }
+            frame.extend_from_slice(&(bodies.len() as u32).to_le_bytes());
+            for body in bodies {
+                self.quantized(&mut frame, &body.position);
+            }
+            return (frame, true);
+        }
+
+        let (fx, fy) = self.detail.focus;
+        let (speed2, radius2) = (
+            self.detail.speed * self.detail.speed,
+            self.detail.radius * self.detail.radius,
+        );
+        let selected: Vec<usize> = (0..bodies.len())
+            .filter(|&i| {
+                let (p, v) = (&bodies[i].position, &bodies[i].velocity);
+                let (dx, dy) = (p.x - fx, p.y - fy);
+                v.x * v.x + v.y * v.y > speed2 || dx * dx + dy * dy < radius2
+            })
+            .collect();
+        frame.extend_from_slice(&(selected.len() as u32).to_le_bytes());
+        let mut next = 0;
+        for i in selected {
+            leb128(&mut frame, (i - next) as u32);
+            next = i + 1;
+            self.quantized(&mut frame, &bodies[i].position);
+        }
+        (frame, false)
+    }
+
+    fn quantized(&self, frame: &mut Vec<u8>, position: &Position) {
+        let (x0, y0, step_x, step_y) = self.grid;
+        let cell = |value: f32, origin: f32, step: f32| {
+            ((value - origin) / step)
+                .round()
+                .clamp(0.0, u16::MAX as f32) as u16
+        };
+        frame.extend_from_slice(&cell(position.x, x0, step_x).to_le_bytes());
+        frame.extend_from_slice(&cell(position.y, y0, step_y).to_le_bytes());
+    }
+}
+
+// The grid of a key frame: the bounding box of the bodies, with a margin for
+// them to move into before the next key frame, in 65535 steps per axis.
+fn grid(bodies: &[BodyState]) -> (f32, f32, f32, f32) {
+    let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+    let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for b in bodies
+        .iter()
+        .filter(|b| b.position.x.is_finite() && b.position.y.is_finite())
+    {
+        min_x = min_x.min(b.position.x);
+        min_y = min_y.min(b.position.y);
+        max_x = max_x.max(b.position.x);
+        max_y = max_y.max(b.position.y);
+    }
+    if min_x > max_x {
+        return (0.0, 0.0, 1.0, 1.0);
+    }
+    let margin_x = 0.1 * (max_x - min_x) + f32::MIN_POSITIVE;
+    let margin_y = 0.1 * (max_y - min_y) + f32::MIN_POSITIVE;
+    let steps = u16::MAX as f32;
+    (
+        min_x - margin_x,
+        min_y - margin_y,
+        (max_x - min_x + 2.0 * margin_x) / steps,
+        (max_y - min_y + 2.0 * margin_y) / steps,
+    )
+}
+
+fn leb128(frame: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        frame.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    frame.push(value as u8);
+}
+
+#[cfg(feature = "server")]
+pub struct Server {
+    clients: Arc<Mutex<Vec<Sender<Frame>>>>,
+    commands: Receiver<Command>,
+}
+
+#[cfg(feature = "server")]
+impl Server {
+    // Binds `addr` and accepts viewers on a background thread.
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let (command_tx, commands) = mpsc::channel();
+
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (frame_tx, frames) = mpsc::channel();
+                accepted.lock().unwrap().push(frame_tx);
+                let commands = command_tx.clone();
+                thread::spawn(move || serve_client(stream, frames, commands));
+            }
+        });
+
+        Ok(Server { clients, commands })
+    }
+
+    // Sends a frame to every connected viewer, dropping any that went away.
+    pub fn broadcast(&self, frame: Vec<u8>) {
+        self.send(Frame {
+            data: Arc::new(frame),
+            key: true,
+        });
+    }
+
+    // Sends a frame from a `LodEncoder`.
+    pub fn broadcast_lod(&self, (frame, key): (Vec<u8>, bool)) {
+        self.send(Frame {
+            data: Arc::new(frame),
+            key,
+        });
+    }
+
+    fn send(&self, frame: Frame) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(frame.clone()).is_ok());
+    }
+
+    // Commands received since the last call, in arrival order.
+    pub fn poll(&self) -> Vec<Command> {
+        self.commands.try_iter().collect()
+    }
+}
+
+#[cfg(feature = "server")]
+fn serve_client(stream: TcpStream, frames: Receiver<Frame>, commands: Sender<Command>) {
+    let mut socket: WebSocket<TcpStream> = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    // short reads so outgoing frames are not held up by a quiet client
+    if socket
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(5)))
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        // the newest frame, and the newest key frame if it was skipped
+        let (mut latest, mut key): (Option<Frame>, Option<Frame>) = (None, None);
+        loop {
+            match frames.try_recv() {
+                Ok(frame) => {
+                    if let Some(skipped) = latest.take() {
+                        if skipped.key {
+                            key = Some(skipped);
+                        }
+                    }
+                    if frame.key {
+                        key = None;
+                    }
+                    latest = Some(frame);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        for frame in key.into_iter().chain(latest) {
+            if socket
+                .write_message(Message::Binary(frame.data.to_vec()))
+                .is_err()
+            {
+                return;
+            }
+        }
+
+        match socket.read_message() {
+            Ok(Message::Text(text)) => match Command::parse(&text) {
+                Ok(command) => {
+                    if commands.send(command).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = socket.write_message(Message::Text(format!("error: {}", e)));
+                }
+            },
+            Ok(Message::Close(_)) => return,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(ref e))
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+// Builds without the "server" feature have no WebSocket support, so nothing
+// can be served.
+#[cfg(not(feature = "server"))]
+pub struct Server;
+
+#[cfg(not(feature = "server"))]
+impl Server {
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        Err(io::Error::other(format!(
+            "serving {} needs a build with the \"server\" feature",
+            addr
+        )))
+    }
+
+    pub fn broadcast(&self, _frame: Vec<u8>) {}
+
+    pub fn broadcast_lod(&self, _frame: (Vec<u8>, bool)) {}
+
+    pub fn poll(&self) -> Vec<Command> {
+        Vec::new()
+    }
+}
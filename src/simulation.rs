@@ -0,0 +1,1765 @@
+// Library entry point: collects the initial bodies and the optional systems,
+// then owns the world and scheduler for the length of a run.
+use crate::analysis::{
+    BinaryCatalog, ClumpCatalog, Distributions, FriendsOfFriends, OrbitalElements,
+    OrbitalElementsOutput, PairCorrelation, RotationCurve, RotationCurveOutput,
+};
+use crate::cells::{cell_size_for, NeighborGrid};
+use crate::checkpoint::Checkpoint;
+use crate::components::*;
+use crate::control::{CommandChannel, CommandSystem};
+use crate::events::{CloseEncounters, EventLog, PeriapsisPassages};
+use crate::history::{BodyHistory, HistorySample, RecordHistory};
+use crate::resources::*;
+use crate::scenarios::BodyState;
+use crate::stats::{
+    ComputeStatistics, GroupStatistics, Statistics, StatsOutput, StatsSample, WriteStatistics,
+};
+use crate::systems::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write as _};
+use std::mem::size_of;
+use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+use SmolECS::{component::*, entity::*, rayon::*, system::*, world::*};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Integrator {
+    // semi-implicit Euler, one force evaluation per tick
+    Euler,
+    // fourth-order Hermite predictor-corrector, with jerk
+    Hermite4,
+    // kick-drift-kick leapfrog: symplectic and time-reversible, one force
+    // evaluation per tick
+    Leapfrog,
+}
+
+// The names `--integrator` takes.
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Integrator::Euler => write!(f, "euler"),
+            Integrator::Hermite4 => write!(f, "hermite4"),
+            Integrator::Leapfrog => write!(f, "leapfrog"),
+        }
+    }
+}
+
+// Where a system added with `SimulationBuilder::add_system_at` runs in a tick,
+// in order.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    // after the clock advances, before any forces are computed
+    PreForce,
+    // after gravity and the built-in corrections, before velocities are
+    // updated, so systems here add to `Acceleration`. With Hermite4 they add
+    // no jerk, and with Hermite4 and Leapfrog they are left out of the force
+    // evaluation before the first tick.
+    Force,
+    // once positions and velocities are updated, before mass evolution, tidal
+    // disruption, escapes, sorting and statistics
+    PostIntegrate,
+    // after every built-in system of the tick
+    PostStep,
+}
+
+type AddSystem = Box<dyn FnOnce(&mut Schedule, Vec<&'static str>)>;
+
+// A set of added systems declared with `SimulationBuilder::system_set`.
+struct SystemSet {
+    name: &'static str,
+    stage: Stage,
+    after: Vec<&'static str>,
+    members: Vec<&'static str>,
+}
+
+// Capacity of the `Statistics` ring buffer.
+const STATS_SAMPLES: usize = 1024;
+
+pub struct SimulationBuilder {
+    bodies: Vec<BodyState>,
+    gravity: Gravity,
+    step: Option<f64>,
+    start: (u64, f64),
+    matrix: InteractionMatrix,
+    threads: usize,
+    rng: Option<RandomState>,
+    stats_output: StatsOutput,
+    stats_interval: Option<u64>,
+    tidal: Option<TidalDisruption>,
+    escapes: Option<bool>,
+    rotation_curve: Option<RotationCurveOutput>,
+    correlation: Option<PairCorrelation>,
+    distributions: Option<Distributions>,
+    binaries: Option<BinaryCatalog>,
+    clumps: Option<FriendsOfFriends>,
+    elements: Option<(OrbitalElementsOutput, usize, Vec<usize>)>,
+    sort: Option<SortBodies>,
+    far_field: Option<(u64, f32)>,
+    neighbors: Option<f32>,
+    density: Option<usize>,
+    history: usize,
+    spins: Vec<(usize, Spin)>,
+    compensated: bool,
+    softening: Option<Softening>,
+    exponent: Option<f32>,
+    integrator: Integrator,
+    relativity: Option<Relativity>,
+    halo: Option<Halo>,
+    friction: Option<DynamicalFriction>,
+    mass_evolution: Option<MassEvolution>,
+    substeps: Option<SubstepDrift>,
+    commands: Option<CommandChannel>,
+    interpolation: bool,
+    systems: Vec<(Stage, &'static str, AddSystem)>,
+    sets: Vec<SystemSet>,
+    events: EventLog,
+    encounter_radius: Option<f32>,
+    exclusions: Exclusions,
+}
+
+impl SimulationBuilder {
+    pub fn new(bodies: Vec<BodyState>) -> Self {
+        SimulationBuilder {
+            bodies,
+            gravity: Gravity::default(),
+            step: None,
+            start: (0, 0.0),
+            matrix: InteractionMatrix::uniform(1),
+            threads: 4,
+            rng: None,
+            stats_output: StatsOutput::none(),
+            stats_interval: None,
+            tidal: None,
+            escapes: None,
+            rotation_curve: None,
+            correlation: None,
+            distributions: None,
+            binaries: None,
+            clumps: None,
+            elements: None,
+            sort: None,
+            far_field: None,
+            neighbors: None,
+            density: None,
+            history: 0,
+            spins: Vec::new(),
+            compensated: false,
+            softening: None,
+            exponent: None,
+            integrator: Integrator::Euler,
+            relativity: None,
+            halo: None,
+            friction: None,
+            mass_evolution: None,
+            substeps: None,
+            commands: None,
+            interpolation: false,
+            systems: Vec::new(),
+            sets: Vec::new(),
+            events: EventLog::none(),
+            encounter_radius: None,
+            exclusions: Exclusions::none(),
+        }
+    }
+
+    pub fn gravity(mut self, gravity: Gravity) -> Self {
+        self.gravity = gravity;
+        self
+    }
+
+    // Fixed timestep; without one each tick advances by the wall-clock time
+    // since the previous tick.
+    pub fn timestep(mut self, step: Option<f64>) -> Self {
+        self.step = step;
+        self
+    }
+
+    // Tick count and simulated time to continue from, for resumed runs.
+    pub fn start_at(mut self, tick: u64, time: f64) -> Self {
+        self.start = (tick, time);
+        self
+    }
+
+    // Restores the state saved by `Simulation::checkpoint`.
+    pub fn from_checkpoint(checkpoint: Checkpoint) -> Self {
+        let mut builder = SimulationBuilder::new(checkpoint.bodies)
+            .gravity(checkpoint.gravity)
+            .timestep(checkpoint.step)
+            .interaction_matrix(checkpoint.matrix)
+            .start_at(checkpoint.tick, checkpoint.time);
+        builder.rng = checkpoint.rng;
+        builder.spins = checkpoint.spins;
+        builder
+    }
+
+    // Seeds the generator stochastic systems such as tidal disruption draw
+    // from; without a seed it is seeded from the operating system. It uses its
+    // own stream, so it doesn't repeat draws made from the same seed while
+    // setting up the bodies.
+    pub fn seed(mut self, seed: u64) -> Self {
+        let mut bytes = [0; 32];
+        ChaCha8Rng::seed_from_u64(seed).fill(&mut bytes);
+        self.rng = Some(RandomState::new(bytes, 1));
+        self
+    }
+
+    pub fn interaction_matrix(mut self, matrix: InteractionMatrix) -> Self {
+        self.matrix = matrix;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn stats_output(mut self, output: StatsOutput) -> Self {
+        self.stats_output = output;
+        self
+    }
+
+    // Takes a statistics sample every `interval` ticks, for
+    // `Simulation::latest_stats` and the statistics output. Without it
+    // statistics are only taken when there is an output to write them to, and
+    // then every tick.
+    pub fn statistics(mut self, interval: u64) -> Self {
+        self.stats_interval = Some(interval);
+        self
+    }
+
+    pub fn tidal_disruption(mut self, tidal: TidalDisruption) -> Self {
+        self.tidal = Some(tidal);
+        self
+    }
+
+    pub fn detect_escapes(mut self, remove: bool) -> Self {
+        self.escapes = Some(remove);
+        self
+    }
+
+    pub fn rotation_curve(mut self, output: RotationCurveOutput) -> Self {
+        self.rotation_curve = Some(output);
+        self
+    }
+
+    pub fn pair_correlation(mut self, correlation: PairCorrelation) -> Self {
+        self.correlation = Some(correlation);
+        self
+    }
+
+    pub fn distributions(mut self, distributions: Distributions) -> Self {
+        self.distributions = Some(distributions);
+        self
+    }
+
+    pub fn binary_catalog(mut self, catalog: BinaryCatalog) -> Self {
+        self.binaries = Some(catalog);
+        self
+    }
+
+    pub fn clump_finder(mut self, finder: FriendsOfFriends) -> Self {
+        self.clumps = Some(finder);
+        self
+    }
+
+    // Logs the elements of the initial bodies at `orbiters` about the one at
+    // `primary` (indices into the bodies passed to `new`).
+    pub fn orbital_elements(
+        mut self,
+        output: OrbitalElementsOutput,
+        primary: usize,
+        orbiters: Vec<usize>,
+    ) -> Self {
+        self.elements = Some((output, primary, orbiters));
+        self
+    }
+
+    pub fn integrator(mut self, integrator: Integrator) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    // Adds the 1PN correction with the speed of light `c` in simulation units.
+    pub fn relativistic(mut self, c: f32) -> Self {
+        self.relativity = Some(Relativity { c });
+        self
+    }
+
+    // Adds a fixed analytic background potential.
+    pub fn halo(mut self, halo: Halo) -> Self {
+        self.halo = Some(halo);
+        self
+    }
+
+    pub fn dynamical_friction(mut self, friction: DynamicalFriction) -> Self {
+        self.friction = Some(friction);
+        self
+    }
+
+    // Changes the masses of the bodies every tick by wind loss and accretion;
+    // see `MassEvolution`.
+    pub fn mass_evolution(mut self, evolution: MassEvolution) -> Self {
+        self.mass_evolution = Some(evolution);
+        self
+    }
+
+    // Integrates pairs closer than `radius` in up to `max_substeps` substeps of
+    // their own interaction; see `SubstepDrift`.
+    pub fn substeps(mut self, radius: f32, max_substeps: u32) -> Self {
+        self.substeps = Some(SubstepDrift {
+            radius,
+            max_substeps,
+        });
+        self
+    }
+
+    // Escapes are always logged when escape detection is on, and periapsis
+    // passages when orbital elements are; close encounters need `radius`.
+    pub fn event_log(mut self, log: EventLog, encounter_radius: Option<f32>) -> Self {
+        self.events = log;
+        self.encounter_radius = encounter_radius;
+        self
+    }
+
+    // Applies commands from the channel at the start of every tick.
+    pub fn commands(mut self, channel: CommandChannel) -> Self {
+        self.commands = Some(channel);
+        self
+    }
+
+    // Keeps the positions from before each tick so that `interpolated` can
+    // render in between ticks.
+    pub fn interpolation(mut self) -> Self {
+        self.interpolation = true;
+        self
+    }
+
+    // Schedules a user system as `name` at the end of every tick, after `deps`
+    // (names of built-in or other added systems) as well as everything built
+    // in. Systems work on the built-in components and resources and keep any
+    // state of their own in themselves, as `CloseEncounters` does.
+    pub fn add_system<S>(self, system: S, name: &'static str, deps: Vec<&'static str>) -> Self
+    where
+        S: for<'w> System<'w, 'w, World> + Send + Sync + 'static,
+    {
+        self.add_system_at(Stage::PostStep, system, name, deps)
+    }
+
+    // As `add_system`, in `stage` of the tick. Force systems run one after
+    // another in the order they were added.
+    pub fn add_system_at<S>(
+        mut self,
+        stage: Stage,
+        system: S,
+        name: &'static str,
+        deps: Vec<&'static str>,
+    ) -> Self
+    where
+        S: for<'w> System<'w, 'w, World> + Send + Sync + 'static,
+    {
+        let add: AddSystem = Box::new(move |scheduler, mut after| {
+            after.extend(deps);
+            scheduler.add(system, name, after);
+        });
+        self.systems.push((stage, name, add));
+        self
+    }
+
+    // Declares a set of added systems, `name`, that runs in `stage` once every
+    // system of the sets in `after` is done. Those sets must be declared first
+    // and run no later in the tick. Systems of a set may run at the same time
+    // as each other and as those of any set they don't wait for, e.g. analysis
+    // and output sets both after `PostIntegrate`, with a third after the two.
+    // Force systems always run one after another, so sets can't be in `Force`.
+    pub fn system_set(
+        mut self,
+        name: &'static str,
+        stage: Stage,
+        after: Vec<&'static str>,
+    ) -> Self {
+        assert!(
+            stage != Stage::Force,
+            "system set {} cannot run in the force stage",
+            name
+        );
+        assert!(
+            self.sets.iter().all(|set| set.name != name),
+            "system set {} is declared twice",
+            name
+        );
+        for before in &after {
+            let before = self
+                .sets
+                .iter()
+                .find(|set| set.name == *before)
+                .unwrap_or_else(|| panic!("system set {} is not declared", before));
+            assert!(
+                before.stage <= stage,
+                "system set {} runs later in the tick than {}, which waits for it",
+                before.name,
+                name
+            );
+        }
+        self.sets.push(SystemSet {
+            name,
+            stage,
+            after,
+            members: Vec::new(),
+        });
+        self
+    }
+
+    // Schedules a user system as `name` in the system set `set`.
+    pub fn add_system_to<S>(mut self, set: &'static str, system: S, name: &'static str) -> Self
+    where
+        S: for<'w> System<'w, 'w, World> + Send + Sync + 'static,
+    {
+        let set = self
+            .sets
+            .iter_mut()
+            .find(|s| s.name == set)
+            .unwrap_or_else(|| panic!("system set {} is not declared", set));
+        set.members.push(name);
+        let stage = set.stage;
+        self.add_system_at(stage, system, name, vec![])
+    }
+
+    // Rebuilds the `NeighborGrid` resource with cells of side `cell_size` at the
+    // start of every tick, for downstream systems to query. Far field reuse
+    // enables it with the near radius as the cell size.
+    pub fn neighbor_grid(mut self, cell_size: f32) -> Self {
+        self.neighbors = Some(cell_size);
+        self
+    }
+
+    // Estimates the `Density` of every body from its `neighbors` nearest
+    // neighbours each tick. Enables the neighbor grid, with cells sized from
+    // the initial bodies unless it is already enabled.
+    pub fn density_estimate(mut self, neighbors: usize) -> Self {
+        self.density = Some(neighbors.max(1));
+        self
+    }
+
+    // Keeps the states of every body after the last `depth` ticks, and before
+    // the first, in memory for `Simulation::body_history`.
+    pub fn history(mut self, depth: usize) -> Self {
+        self.history = depth;
+        self
+    }
+
+    // Gives the body at `index` of the initial conditions a `Spin`, which it
+    // keeps turning through every tick and which its fragments inherit if it
+    // is tidally disrupted.
+    pub fn spin(mut self, index: usize, spin: Spin) -> Self {
+        self.spins.push((index, spin));
+        self
+    }
+
+    // Recomputes forces from bodies further than `radius` only every
+    // `interval` ticks; see `SplitGravity`.
+    pub fn far_field(mut self, interval: u64, radius: f32) -> Self {
+        self.far_field = Some((interval, radius));
+        self
+    }
+
+    // Adds up the gravity on each body with Kahan summation; see `ForceSum`.
+    pub fn compensated_sum(mut self) -> Self {
+        self.compensated = true;
+        self
+    }
+
+    // Softens gravity at short range in every force backend, whatever
+    // `gravity` sets; see `Softening`.
+    pub fn softening(mut self, softening: Softening) -> Self {
+        self.softening = Some(softening);
+        self
+    }
+
+    // Skips the mutual force between members of groups `a` and `b`, or within
+    // group `a` when they are the same, in every force backend and the
+    // potential energy; see `Exclusions`.
+    pub fn exclude(mut self, a: u32, b: u32) -> Self {
+        self.exclusions.add(a, b);
+        self
+    }
+
+    // Replaces the 1/r^2 force law with 1/r^`exponent` in every force backend
+    // and the potential energy, whatever `gravity` sets.
+    pub fn force_exponent(mut self, exponent: f32) -> Self {
+        self.exponent = Some(exponent);
+        self
+    }
+
+    // Re-sorts bodies into Z-order every `interval` ticks; see `SortBodies`.
+    pub fn sort_interval(mut self, interval: u64) -> Self {
+        self.sort = Some(SortBodies { interval });
+        self
+    }
+
+    // Rough size in bytes of what the run will hold: a slot per body in every
+    // component storage, the per-body buffers of the enabled systems and two
+    // copies of the bodies for the systems that collect them each tick, plus
+    // the statistics ring buffer. Outputs and the scheduler are left out.
+    pub fn estimated_memory(&self) -> usize {
+        self.bodies.len() * self.memory_per_body() + STATS_SAMPLES * size_of::<StatsSample>()
+    }
+
+    // The most bodies whose estimated memory fits in `budget` bytes with the
+    // systems enabled so far.
+    pub fn max_bodies(&self, budget: usize) -> usize {
+        budget.saturating_sub(STATS_SAMPLES * size_of::<StatsSample>()) / self.memory_per_body()
+    }
+
+    // Drops all but the first `count` bodies.
+    pub fn truncate(mut self, count: usize) -> Self {
+        self.bodies.truncate(count);
+        self
+    }
+
+    // A builder for a copy of the run with every body moved `delta` in a random
+    // direction, for `Simulation::shadow_run`. Only the physics is copied: the
+    // bodies, gravity, timestep, interaction matrix, integrator and force
+    // options, not outputs, analysis or added systems. Tidal disruption, escape
+    // removal and sorting would stop bodies pairing up by storage order, so
+    // they are not allowed.
+    pub fn shadow<R: Rng>(&self, delta: f32, rng: &mut R) -> SimulationBuilder {
+        assert!(
+            self.tidal.is_none() && self.escapes != Some(true) && self.sort.is_none(),
+            "shadow runs cannot be combined with tidal disruption, escape removal or sorting"
+        );
+        let bodies = self
+            .bodies
+            .iter()
+            .map(|b| {
+                let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+                let mut body = *b;
+                body.position.x += delta * angle.cos();
+                body.position.y += delta * angle.sin();
+                body
+            })
+            .collect();
+        let mut shadow = SimulationBuilder::new(bodies)
+            .gravity(self.gravity)
+            .timestep(self.step)
+            .start_at(self.start.0, self.start.1)
+            .interaction_matrix(self.matrix.clone())
+            .threads(self.threads)
+            .integrator(self.integrator);
+        shadow.far_field = self.far_field;
+        shadow.compensated = self.compensated;
+        shadow.softening = self.softening;
+        shadow.exponent = self.exponent;
+        shadow.relativity = self.relativity;
+        shadow.halo = self.halo;
+        shadow.friction = self.friction;
+        shadow.mass_evolution = self.mass_evolution;
+        shadow.substeps = self.substeps;
+        shadow.exclusions = self.exclusions.clone();
+        shadow
+    }
+
+    fn memory_per_body(&self) -> usize {
+        fn slot<T>() -> usize {
+            size_of::<Option<T>>()
+        }
+        let mut per_body = size_of::<Entity>()
+            + slot::<Body>()
+            + slot::<Mass>()
+            + slot::<Species>()
+            + slot::<Group>()
+            + slot::<BodyId>()
+            + slot::<Acceleration>()
+            + slot::<Jerk>()
+            + slot::<Velocity>()
+            + slot::<Position>()
+            + slot::<PreviousPosition>()
+            + slot::<Escaped>()
+            + slot::<Density>()
+            + slot::<ClumpId>()
+            + slot::<Primary>()
+            + slot::<Orbiter>()
+            + slot::<Spin>()
+            + 2 * size_of::<BodyState>()
+            + self.history * size_of::<HistorySample>();
+        if self.integrator == Integrator::Hermite4 {
+            per_body += size_of::<(Position, Velocity, Acceleration, Jerk)>();
+        }
+        if self.neighbors.is_some() || self.far_field.is_some() || self.density.is_some() {
+            // the grid's points and its cell list
+            per_body += size_of::<(f32, f32)>() + size_of::<usize>();
+        }
+        if self.far_field.is_some() {
+            per_body += size_of::<(f32, f32)>();
+        }
+        per_body
+    }
+
+    pub fn build(self) -> Simulation {
+        assert!(
+            self.sort.is_none() || self.far_field.is_none(),
+            "body sorting cannot be combined with a far-field interval"
+        );
+        assert!(
+            self.spins
+                .iter()
+                .all(|(index, _)| *index < self.bodies.len()),
+            "a spin is given to a body that does not exist"
+        );
+        if let Some((index, body)) = self
+            .bodies
+            .iter()
+            .enumerate()
+            .find(|(_, body)| body.species >= self.matrix.species())
+        {
+            panic!(
+                "body {} has species {}, but the interaction matrix has {} species",
+                index,
+                body.species,
+                self.matrix.species()
+            );
+        }
+        assert!(
+            self.far_field.is_none() || self.integrator == Integrator::Euler,
+            "far-field reuse is only available with the Euler integrator"
+        );
+        assert!(
+            self.substeps.is_none() || self.integrator == Integrator::Euler,
+            "substepping is only available with the Euler integrator"
+        );
+        assert!(
+            !self.compensated || self.integrator != Integrator::Hermite4,
+            "compensated summation is not available with the Hermite4 integrator"
+        );
+        let estimated_memory = self.estimated_memory();
+
+        let mut world = World::new();
+        world.register_comp::<Body>();
+        world.register_comp::<Mass>();
+        world.register_comp::<Species>();
+        world.register_comp::<Group>();
+        world.register_comp::<BodyId>();
+        world.register_comp::<Acceleration>();
+        world.register_comp::<Jerk>();
+        world.register_comp::<Velocity>();
+        world.register_comp::<Position>();
+        world.register_comp::<PreviousPosition>();
+        world.register_comp::<Escaped>();
+        world.register_comp::<Density>();
+        world.register_comp::<ClumpId>();
+        world.register_comp::<Primary>();
+        world.register_comp::<Orbiter>();
+        world.register_comp::<Spin>();
+
+        world.insert(WorldBounds { x: 10.0, y: 10.0 });
+        let mut gravity = self.gravity;
+        if let Some(softening) = self.softening {
+            gravity.softening = softening;
+        }
+        if let Some(exponent) = self.exponent {
+            gravity.exponent = exponent;
+        }
+        assert!(
+            gravity.exponent == 2.0 || !matches!(gravity.softening, Softening::Spline(_)),
+            "spline softening is only available with the inverse-square force law"
+        );
+        world.insert(gravity);
+        let mut time = Time::new(self.step);
+        time.tick = self.start.0;
+        time.total = self.start.1;
+        world.insert(time);
+        world.insert(EntityStorage::new());
+        world.insert(self.matrix);
+        world.insert(self.exclusions);
+        world.insert(BodyIds::starting_at(self.bodies.len() as u64));
+        // sized up front so that nothing reallocates while bodies are added or
+        // during the first ticks
+        let count = self.bodies.len();
+        let grouped = self.bodies.iter().any(|b| b.group != 0);
+        world.insert(HermiteStart {
+            state: match self.integrator {
+                Integrator::Hermite4 => Vec::with_capacity(count),
+                Integrator::Euler | Integrator::Leapfrog => Vec::new(),
+            },
+        });
+        let relativistic = self.relativity.is_some();
+        if let Some(relativity) = self.relativity {
+            world.insert(relativity);
+        }
+        let log_events = self.events.enabled();
+        world.insert(self.events);
+        let commands = self.commands.is_some();
+        if let Some(channel) = self.commands {
+            world.insert(channel);
+        }
+        let halo = self.halo.is_some();
+        if let Some(profile) = self.halo {
+            world.insert(profile);
+        }
+        let bodies = &self.bodies;
+        let neighbors = self
+            .neighbors
+            .or(self.far_field.map(|(_, radius)| radius))
+            .or(self.density.map(|k| {
+                let points: Vec<(f32, f32)> = bodies
+                    .iter()
+                    .map(|b| (b.position.x, b.position.y))
+                    .collect();
+                cell_size_for(&points, k)
+            }));
+        world.insert(NeighborGrid::new(neighbors.unwrap_or(1.0)));
+        world.insert(Statistics::new(STATS_SAMPLES));
+        world.insert(ClumpCatalog::default());
+        world.insert(BodyHistory::new(self.history));
+        world.insert(self.rng.unwrap_or_else(RandomState::from_entropy));
+        let stats_interval = match self.stats_interval {
+            Some(interval) => Some(interval),
+            None if self.stats_output.is_enabled() => Some(1),
+            None => None,
+        };
+        world.insert(self.stats_output);
+        let rotation_curve = self.rotation_curve.is_some();
+        if let Some(output) = self.rotation_curve {
+            world.insert(output);
+        }
+        let (primary, orbiters) = match self.elements {
+            Some((output, primary, orbiters)) => {
+                world.insert(output);
+                (Some(primary), orbiters)
+            }
+            None => (None, vec![]),
+        };
+        let orbital_elements = primary.is_some();
+
+        let mut ents = Write::<EntityStorage>::get_data(&world);
+        let mut bodies = WriteComp::<Body>::get_data(&world);
+        let mut masses = WriteComp::<Mass>::get_data(&world);
+        let mut species = WriteComp::<Species>::get_data(&world);
+        let mut groups = WriteComp::<Group>::get_data(&world);
+        let mut ids = WriteComp::<BodyId>::get_data(&world);
+        let mut accels = WriteComp::<Acceleration>::get_data(&world);
+        let mut jerks = WriteComp::<Jerk>::get_data(&world);
+        let mut vels = WriteComp::<Velocity>::get_data(&world);
+        let mut positions = WriteComp::<Position>::get_data(&world);
+        let mut primaries = WriteComp::<Primary>::get_data(&world);
+        let mut tracked = WriteComp::<Orbiter>::get_data(&world);
+        let mut spins = WriteComp::<Spin>::get_data(&world);
+        ents.reserve(count);
+        bodies.reserve(count);
+        masses.reserve(count);
+        species.reserve(count);
+        groups.reserve(count);
+        ids.reserve(count);
+        accels.reserve(count);
+        jerks.reserve(count);
+        vels.reserve(count);
+        positions.reserve(count);
+
+        for (index, state) in self.bodies.into_iter().enumerate() {
+            let mut entity = ents
+                .create_entity()
+                .add(&mut bodies, Body {})
+                .add(&mut masses, Mass { mass: state.mass })
+                .add(&mut species, Species { id: state.species })
+                .add(&mut groups, Group(state.group))
+                .add(&mut ids, BodyId(index as u64))
+                .add(&mut accels, Acceleration { x: 0.0, y: 0.0 })
+                .add(&mut jerks, Jerk { x: 0.0, y: 0.0 })
+                .add(&mut vels, state.velocity)
+                .add(&mut positions, state.position);
+            if primary == Some(index) {
+                entity = entity.add(&mut primaries, Primary {});
+            }
+            if let Some((_, spin)) = self.spins.iter().rev().find(|(i, _)| *i == index) {
+                entity = entity.add(&mut spins, *spin);
+            }
+            if orbiters.contains(&index) {
+                entity.add(&mut tracked, Orbiter { index });
+            }
+        }
+
+        drop(ents);
+        drop(bodies);
+        drop(masses);
+        drop(species);
+        drop(groups);
+        drop(ids);
+        drop(accels);
+        drop(jerks);
+        drop(vels);
+        drop(positions);
+        drop(primaries);
+        drop(tracked);
+        drop(spins);
+        if self.history > 0 {
+            RecordHistory {}.run(SystemData::get_data(&world));
+        }
+
+        let mut scheduler = Schedule {
+            scheduler: SystemScheduler::new(Arc::new(
+                ThreadPoolBuilder::new()
+                    .num_threads(self.threads)
+                    .build()
+                    .unwrap(),
+            )),
+            names: Vec::new(),
+        };
+        let mut first = vec![];
+        if commands {
+            scheduler.add(CommandSystem {}, "commands", first);
+            first = vec!["commands"];
+        }
+        if self.interpolation {
+            scheduler.add(StorePreviousPositions {}, "store_previous", first);
+            first = vec!["store_previous"];
+        }
+        scheduler.add(UpdateTime {}, "update_time", first);
+        let started = if neighbors.is_some() {
+            scheduler.add(BuildNeighborGrid {}, "build_neighbors", vec!["update_time"]);
+            "build_neighbors"
+        } else {
+            "update_time"
+        };
+        let mut systems = order_sets(self.systems, &self.sets);
+        let mut force_deps = vec![started];
+        force_deps.extend(add_stage(
+            &mut scheduler,
+            &mut systems,
+            Stage::PreForce,
+            vec![started],
+        ));
+        let integrated = match self.integrator {
+            Integrator::Euler => {
+                match self.far_field {
+                    Some((interval, radius)) => {
+                        let mut split = SplitGravity::new(interval, radius);
+                        split.compensated = self.compensated;
+                        scheduler.add(split, "apply_gravity", force_deps)
+                    }
+                    None => scheduler.add(
+                        ApplyGravity {
+                            compensated: self.compensated,
+                        },
+                        "apply_gravity",
+                        force_deps,
+                    ),
+                }
+                let mut forces = add_corrections(&mut scheduler, relativistic, halo, self.friction);
+                for name in add_stage(&mut scheduler, &mut systems, Stage::Force, vec![forces]) {
+                    forces = name;
+                }
+                scheduler.add(
+                    ApplyAccelerations {},
+                    "update_vels",
+                    vec!["update_time", forces],
+                );
+                match self.substeps {
+                    Some(drift) => scheduler.add(
+                        drift,
+                        "update_positions",
+                        vec!["update_time", "update_vels"],
+                    ),
+                    None => scheduler.add(
+                        ApplyVelocities {},
+                        "update_positions",
+                        vec!["update_time", "update_vels"],
+                    ),
+                }
+                "update_positions"
+            }
+            Integrator::Hermite4 => {
+                // start-of-step acceleration and jerk for the first tick
+                HermiteForces {}.run(SystemData::get_data(&world));
+                run_corrections(&world, relativistic, halo, &self.friction);
+                scheduler.add(HermitePredict {}, "hermite_predict", force_deps);
+                scheduler.add(HermiteForces {}, "apply_gravity", vec!["hermite_predict"]);
+                let mut forces = add_corrections(&mut scheduler, relativistic, halo, self.friction);
+                for name in add_stage(&mut scheduler, &mut systems, Stage::Force, vec![forces]) {
+                    forces = name;
+                }
+                scheduler.add(HermiteCorrect {}, "hermite_correct", vec![forces]);
+                "hermite_correct"
+            }
+            Integrator::Leapfrog => {
+                // start-of-step acceleration for the first half kick
+                ApplyGravity {
+                    compensated: self.compensated,
+                }
+                .run(SystemData::get_data(&world));
+                run_corrections(&world, relativistic, halo, &self.friction);
+                scheduler.add(HalfKick {}, "kick_start", force_deps);
+                scheduler.add(
+                    ApplyVelocities {},
+                    "update_positions",
+                    vec!["update_time", "kick_start"],
+                );
+                scheduler.add(
+                    ApplyGravity {
+                        compensated: self.compensated,
+                    },
+                    "apply_gravity",
+                    vec!["update_positions"],
+                );
+                let mut forces = add_corrections(&mut scheduler, relativistic, halo, self.friction);
+                for name in add_stage(&mut scheduler, &mut systems, Stage::Force, vec![forces]) {
+                    forces = name;
+                }
+                scheduler.add(HalfKick {}, "kick_end", vec![forces]);
+                "kick_end"
+            }
+        };
+
+        let mut stats_deps = vec![integrated];
+        if let Some(k) = self.density {
+            scheduler.add(
+                EstimateDensity { neighbors: k },
+                "estimate_density",
+                vec![started],
+            );
+            stats_deps.push("estimate_density");
+        }
+        if !self.spins.is_empty() {
+            scheduler.add(RotateBodies {}, "rotate_bodies", vec![integrated]);
+            stats_deps.push("rotate_bodies");
+        }
+        stats_deps.extend(add_stage(
+            &mut scheduler,
+            &mut systems,
+            Stage::PostIntegrate,
+            stats_deps.clone(),
+        ));
+        if let Some(evolution) = self.mass_evolution {
+            scheduler.add(evolution, "evolve_masses", stats_deps.clone());
+            stats_deps.push("evolve_masses");
+        }
+        if let Some(tidal) = self.tidal {
+            scheduler.add(tidal, "tidal_disruption", stats_deps.clone());
+            stats_deps.push("tidal_disruption");
+        }
+        if let Some(remove) = self.escapes {
+            scheduler.add(DetectEscapes {}, "detect_escapes", stats_deps.clone());
+            stats_deps.push("detect_escapes");
+            if remove {
+                scheduler.add(RemoveEscaped {}, "remove_escaped", stats_deps.clone());
+                stats_deps.push("remove_escaped");
+            }
+        }
+        if let Some(sort) = self.sort {
+            scheduler.add(sort, "sort_bodies", stats_deps.clone());
+            stats_deps.push("sort_bodies");
+        }
+        // everything the post-step systems wait for
+        let mut step_deps = stats_deps.clone();
+        if let Some(interval) = stats_interval {
+            scheduler.add(
+                ComputeStatistics { interval },
+                "compute_stats",
+                stats_deps.clone(),
+            );
+            step_deps.push("compute_stats");
+        }
+        if grouped {
+            scheduler.add(GroupStatistics {}, "group_stats", stats_deps.clone());
+            step_deps.push("group_stats");
+        }
+        if rotation_curve {
+            scheduler.add(RotationCurve {}, "rotation_curve", stats_deps.clone());
+            step_deps.push("rotation_curve");
+        }
+        if orbital_elements {
+            scheduler.add(OrbitalElements {}, "orbital_elements", stats_deps.clone());
+            step_deps.push("orbital_elements");
+            if log_events {
+                scheduler.add(PeriapsisPassages::new(), "periapsis", stats_deps.clone());
+                step_deps.push("periapsis");
+            }
+        }
+        if let (true, Some(radius)) = (log_events, self.encounter_radius) {
+            scheduler.add(
+                CloseEncounters::new(radius),
+                "close_encounters",
+                stats_deps.clone(),
+            );
+            step_deps.push("close_encounters");
+        }
+        if let Some(binaries) = self.binaries {
+            scheduler.add(binaries, "binary_catalog", stats_deps.clone());
+            step_deps.push("binary_catalog");
+        }
+        if let Some(finder) = self.clumps {
+            scheduler.add(finder, "friends_of_friends", stats_deps.clone());
+            step_deps.push("friends_of_friends");
+        }
+        if self.history > 0 {
+            scheduler.add(RecordHistory {}, "record_history", stats_deps.clone());
+            step_deps.push("record_history");
+        }
+        if let Some(distributions) = self.distributions {
+            scheduler.add(distributions, "distributions", stats_deps.clone());
+            step_deps.push("distributions");
+        }
+        if let Some(correlation) = self.correlation {
+            scheduler.add(correlation, "pair_correlation", stats_deps);
+            step_deps.push("pair_correlation");
+        }
+        if stats_interval.is_some() {
+            scheduler.add(WriteStatistics {}, "write_stats", vec!["compute_stats"]);
+            step_deps.push("write_stats");
+        }
+        add_stage(&mut scheduler, &mut systems, Stage::PostStep, step_deps);
+
+        let mut simulation = Simulation {
+            world,
+            scheduler,
+            integrator: self.integrator,
+            estimated_memory,
+            rotation_curve,
+            orbital_elements,
+            threads: self.threads,
+            started: Instant::now(),
+            initial_tick: self.start.0,
+            initial_energy: 0.0,
+        };
+        simulation.initial_energy = simulation.energy();
+        simulation
+    }
+}
+
+// Schedules the added systems for `stage` after `after`, in the order they were
+// added, and returns their names. Force systems also wait for the one before.
+fn add_stage(
+    scheduler: &mut Schedule,
+    systems: &mut Vec<(Stage, &'static str, AddSystem)>,
+    stage: Stage,
+    mut after: Vec<&'static str>,
+) -> Vec<&'static str> {
+    let (now, later): (Vec<_>, Vec<_>) = std::mem::take(systems)
+        .into_iter()
+        .partition(|(s, _, _)| *s == stage);
+    *systems = later;
+
+    let mut names = Vec::new();
+    for (_, name, add) in now {
+        add(scheduler, after.clone());
+        if stage == Stage::Force {
+            after = vec![name];
+        }
+        names.push(name);
+    }
+    names
+}
+
+// Makes the systems of each set wait for those of the sets it comes after, and
+// moves them behind the systems of earlier sets so those are scheduled first.
+fn order_sets(
+    systems: Vec<(Stage, &'static str, AddSystem)>,
+    sets: &[SystemSet],
+) -> Vec<(Stage, &'static str, AddSystem)> {
+    let mut ordered: Vec<(usize, (Stage, &'static str, AddSystem))> = systems
+        .into_iter()
+        .map(|(stage, name, add)| {
+            let index = match sets.iter().position(|set| set.members.contains(&name)) {
+                Some(index) => index,
+                None => return (0, (stage, name, add)),
+            };
+            let deps: Vec<&'static str> = sets[index]
+                .after
+                .iter()
+                .flat_map(|before| {
+                    let before = sets.iter().find(|set| set.name == *before).unwrap();
+                    before.members.iter().copied()
+                })
+                .collect();
+            let add: AddSystem = Box::new(move |scheduler, mut after| {
+                after.extend(deps);
+                add(scheduler, after);
+            });
+            (index + 1, (stage, name, add))
+        })
+        .collect();
+    ordered.sort_by_key(|(index, _)| *index);
+    ordered.into_iter().map(|(_, system)| system).collect()
+}
+
+// Schedules the optional terms added on top of "apply_gravity", in order, and
+// returns the name of the last force system.
+fn add_corrections(
+    scheduler: &mut Schedule,
+    relativistic: bool,
+    halo: bool,
+    friction: Option<DynamicalFriction>,
+) -> &'static str {
+    let mut last = "apply_gravity";
+    if relativistic {
+        scheduler.add(PostNewtonian {}, "post_newtonian", vec![last]);
+        last = "post_newtonian";
+    }
+    if halo {
+        scheduler.add(HaloForce {}, "halo", vec![last]);
+        last = "halo";
+    }
+    if let Some(friction) = friction {
+        scheduler.add(friction, "dynamical_friction", vec![last]);
+        last = "dynamical_friction";
+    }
+    last
+}
+
+// Applies the optional terms of `add_corrections` once, outside the scheduler,
+// for integrators that need forces before the first tick.
+fn run_corrections(
+    world: &World,
+    relativistic: bool,
+    halo: bool,
+    friction: &Option<DynamicalFriction>,
+) {
+    if relativistic {
+        PostNewtonian {}.run(SystemData::get_data(world));
+    }
+    if halo {
+        HaloForce {}.run(SystemData::get_data(world));
+    }
+    if let Some(friction) = friction {
+        friction.run(SystemData::get_data(world));
+    }
+}
+
+// The scheduler along with the names of the systems added to it, in order.
+struct Schedule {
+    scheduler: SystemScheduler,
+    names: Vec<&'static str>,
+}
+
+impl Schedule {
+    fn add<S>(&mut self, system: S, name: &'static str, deps: Vec<&'static str>)
+    where
+        S: for<'w> System<'w, 'w, World> + Send + Sync + 'static,
+    {
+        self.scheduler.add(system, name, deps);
+        self.names.push(name);
+    }
+}
+
+pub struct Simulation {
+    world: World,
+    scheduler: Schedule,
+    integrator: Integrator,
+    estimated_memory: usize,
+    rotation_curve: bool,
+    orbital_elements: bool,
+    threads: usize,
+    started: Instant,
+    initial_tick: u64,
+    initial_energy: f32,
+}
+
+// Printed at the end of a run, whether it completed or was interrupted.
+pub struct RunSummary {
+    pub ticks: u64,
+    pub time: f64,
+    pub wall_seconds: f64,
+    pub bodies: usize,
+    pub energy_drift: f32,
+}
+
+// Reads back the `Display` form, ignoring any other lines around it.
+impl FromStr for RunSummary {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        fn field<T: FromStr>(text: &str, name: &str, unit: &str) -> Result<T, String> {
+            let value = text
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .ok_or_else(|| format!("no {} in the run summary", name))?;
+            let value = value.split_whitespace().next().unwrap_or("");
+            value
+                .trim_end_matches(unit)
+                .parse()
+                .map_err(|_| format!("invalid {} in the run summary", name))
+        }
+        Ok(RunSummary {
+            ticks: field(text, "ticks:", "")?,
+            time: field(text, "sim time:", "")?,
+            wall_seconds: field(text, "wall time:", "s")?,
+            bodies: field(text, "bodies:", "")?,
+            energy_drift: field(text, "energy drift:", "")?,
+        })
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "ticks:        {} ({:.1}/s)",
+            self.ticks,
+            self.ticks as f64 / self.wall_seconds.max(1.0e-9)
+        )?;
+        writeln!(f, "sim time:     {}", self.time)?;
+        writeln!(f, "wall time:    {:.2}s", self.wall_seconds)?;
+        writeln!(f, "bodies:       {}", self.bodies)?;
+        write!(f, "energy drift: {:e}", self.energy_drift)
+    }
+}
+
+// What a built simulation will run, printed by `--dry-run`.
+pub struct Configuration {
+    pub bodies: usize,
+    pub species: usize,
+    pub integrator: Integrator,
+    pub step: Option<f64>,
+    pub gravity: Gravity,
+    pub tick: u64,
+    pub time: f64,
+    pub threads: usize,
+    pub systems: Vec<&'static str>,
+    pub estimated_memory: usize,
+}
+
+impl fmt::Display for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "bodies:       {}", self.bodies)?;
+        writeln!(f, "species:      {}", self.species)?;
+        writeln!(f, "integrator:   {}", self.integrator)?;
+        match self.step {
+            Some(step) => writeln!(f, "timestep:     {}", step)?,
+            None => writeln!(f, "timestep:     wall clock")?,
+        }
+        writeln!(
+            f,
+            "gravity:      G = {}, 1/r^{}, epsilon = {}, softening {}",
+            self.gravity.g, self.gravity.exponent, self.gravity.epsilon, self.gravity.softening
+        )?;
+        writeln!(f, "start:        tick {}, time {}", self.tick, self.time)?;
+        writeln!(f, "threads:      {}", self.threads)?;
+        writeln!(f, "systems:      {}", self.systems.join(", "))?;
+        write!(
+            f,
+            "memory:       ~{:.1} MiB (estimated)",
+            self.estimated_memory as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+// How far each body ends up from where it started after `reverse_test`. With
+// a time-reversible integrator and symmetric forces only rounding is left.
+pub struct ReversalReport {
+    pub ticks: u64,
+    // in storage order
+    pub errors: Vec<f32>,
+}
+
+impl ReversalReport {
+    // The index and error of the body that ended up furthest from its start.
+    pub fn max(&self) -> Option<(usize, f32)> {
+        self.errors
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    pub fn rms(&self) -> f32 {
+        if self.errors.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self.errors.iter().map(|e| e * e).sum();
+        (sum / self.errors.len() as f32).sqrt()
+    }
+
+    // One `body,error` row per body.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "body,error")?;
+        for (i, error) in self.errors.iter().enumerate() {
+            writeln!(out, "{},{}", i, error)?;
+        }
+        out.flush()
+    }
+}
+
+impl fmt::Display for ReversalReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "reversed:     after {} ticks", self.ticks)?;
+        writeln!(f, "bodies:       {}", self.errors.len())?;
+        match self.max() {
+            Some((body, error)) => writeln!(f, "max error:    {:e} (body {})", error, body)?,
+            None => writeln!(f, "max error:    -")?,
+        }
+        write!(f, "rms error:    {:e}", self.rms())
+    }
+}
+
+// Divergence of a run from its shadow over `Simulation::shadow_run`.
+pub struct DivergenceReport {
+    // (tick, time, position divergence, velocity divergence) before the first
+    // tick and after every one, each divergence the root sum of squares over
+    // the bodies
+    pub samples: Vec<(u64, f64, f32, f32)>,
+}
+
+// Growth of the divergence by more than this factor is taken to be past the
+// exponential phase, so later samples are left out of the Lyapunov fit.
+const SATURATION: f32 = 1e3;
+
+impl DivergenceReport {
+    // Phase-space distance of a sample, with positions and velocities in
+    // simulation units.
+    fn distance(sample: &(u64, f64, f32, f32)) -> f32 {
+        (sample.2 * sample.2 + sample.3 * sample.3).sqrt()
+    }
+
+    // The largest Lyapunov exponent: the slope of a least-squares fit of the
+    // log of the phase-space distance against time, while it has grown by less
+    // than `SATURATION`. `None` with fewer than two such samples.
+    pub fn lyapunov_exponent(&self) -> Option<f64> {
+        let initial = Self::distance(self.samples.first()?);
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|s| (s.1, Self::distance(s)))
+            .take_while(|&(_, d)| d > 0.0 && d < SATURATION * initial)
+            .map(|(t, d)| (t, (d as f64).ln()))
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_l = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let covariance: f64 = points.iter().map(|p| (p.0 - mean_t) * (p.1 - mean_l)).sum();
+        let variance: f64 = points.iter().map(|p| (p.0 - mean_t).powi(2)).sum();
+        if variance > 0.0 {
+            Some(covariance / variance)
+        } else {
+            None
+        }
+    }
+
+    // One `tick,time,position,velocity,log_distance` row per sample.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "tick,time,position,velocity,log_distance")?;
+        for sample in &self.samples {
+            writeln!(
+                out,
+                "{},{},{},{},{}",
+                sample.0,
+                sample.1,
+                sample.2,
+                sample.3,
+                Self::distance(sample).ln()
+            )?;
+        }
+        out.flush()
+    }
+}
+
+impl fmt::Display for DivergenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (first, last) = match (self.samples.first(), self.samples.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return write!(f, "shadow run:   no samples"),
+        };
+        writeln!(f, "shadow run:   {} ticks", last.0 - first.0)?;
+        writeln!(f, "initial:      {:e}", Self::distance(first))?;
+        writeln!(f, "final:        {:e}", Self::distance(last))?;
+        match self.lyapunov_exponent() {
+            Some(lambda) if lambda > 0.0 => {
+                writeln!(f, "lyapunov:     {:e}", lambda)?;
+                write!(f, "e-folding:    {:e}", 1.0 / lambda)
+            }
+            Some(lambda) => write!(f, "lyapunov:     {:e} (not diverging)", lambda),
+            None => write!(f, "lyapunov:     -"),
+        }
+    }
+}
+
+impl Simulation {
+    pub fn step(&mut self) {
+        self.scheduler.scheduler.run(&self.world);
+    }
+
+    pub fn run(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    // Negates every velocity and jerk, so that the following ticks retrace
+    // the run so far.
+    pub fn reverse(&mut self) {
+        let ents = Read::<EntityStorage>::get_data(&self.world);
+        let mut vels = WriteComp::<Velocity>::get_data(&self.world);
+        for (vel, _) in (&mut vels, ents.deref()).join() {
+            vel.x = -vel.x;
+            vel.y = -vel.y;
+        }
+        let mut jerks = WriteComp::<Jerk>::get_data(&self.world);
+        for (jerk, _) in (&mut jerks, ents.deref()).join() {
+            jerk.x = -jerk.x;
+            jerk.y = -jerk.y;
+        }
+    }
+
+    // Runs `ticks` forward, reverses, runs `ticks` back and reverses again,
+    // then measures each body's distance from its starting position. Bodies
+    // removed during the run leave the rest misaligned, so leave escape
+    // removal and tidal disruption off.
+    pub fn reverse_test(&mut self, ticks: u64) -> ReversalReport {
+        let initial = self.bodies();
+        self.run(ticks);
+        self.reverse();
+        self.run(ticks);
+        self.reverse();
+        ReversalReport {
+            ticks,
+            errors: initial
+                .iter()
+                .zip(self.bodies().iter())
+                .map(|(start, end)| distance(&start.position, &end.position))
+                .collect(),
+        }
+    }
+
+    // Runs `ticks` ticks in lockstep with `shadow`, a copy started from
+    // perturbed positions (see `SimulationBuilder::shadow`), measuring how far
+    // apart the two are after each one.
+    pub fn shadow_run(&mut self, shadow: &mut Simulation, ticks: u64) -> DivergenceReport {
+        let mut report = DivergenceReport {
+            samples: Vec::with_capacity(ticks as usize + 1),
+        };
+        let mut sample = |one: &Simulation, two: &Simulation| {
+            let (mut position, mut velocity) = (0.0, 0.0);
+            for (a, b) in one.bodies().iter().zip(&two.bodies()) {
+                position += distance(&a.position, &b.position).powi(2);
+                let (dx, dy) = (a.velocity.x - b.velocity.x, a.velocity.y - b.velocity.y);
+                velocity += dx * dx + dy * dy;
+            }
+            report
+                .samples
+                .push((one.tick(), one.time(), position.sqrt(), velocity.sqrt()));
+        };
+        sample(self, shadow);
+        for _ in 0..ticks {
+            self.step();
+            shadow.step();
+            sample(self, shadow);
+        }
+        report
+    }
+
+    // Adds a body between ticks, unless its species is not in the
+    // interaction matrix.
+    pub fn spawn(&mut self, state: BodyState) -> Result<(), String> {
+        let matrix = Read::<InteractionMatrix>::get_data(&self.world).species();
+        if state.species >= matrix {
+            return Err(format!(
+                "species {} is not in the {}-species interaction matrix",
+                state.species, matrix
+            ));
+        }
+        let mut ents = Write::<EntityStorage>::get_data(&self.world);
+        let mut bodies = WriteComp::<Body>::get_data(&self.world);
+        let mut masses = WriteComp::<Mass>::get_data(&self.world);
+        let mut species = WriteComp::<Species>::get_data(&self.world);
+        let mut groups = WriteComp::<Group>::get_data(&self.world);
+        let mut ids = WriteComp::<BodyId>::get_data(&self.world);
+        let mut accels = WriteComp::<Acceleration>::get_data(&self.world);
+        let mut jerks = WriteComp::<Jerk>::get_data(&self.world);
+        let mut vels = WriteComp::<Velocity>::get_data(&self.world);
+        let mut positions = WriteComp::<Position>::get_data(&self.world);
+        let id = Write::<BodyIds>::get_data(&self.world).issue();
+        ents.create_entity()
+            .add(&mut bodies, Body {})
+            .add(&mut masses, Mass { mass: state.mass })
+            .add(&mut species, Species { id: state.species })
+            .add(&mut groups, Group(state.group))
+            .add(&mut ids, id)
+            .add(&mut accels, Acceleration { x: 0.0, y: 0.0 })
+            .add(&mut jerks, Jerk { x: 0.0, y: 0.0 })
+            .add(&mut vels, state.velocity)
+            .add(&mut positions, state.position);
+        Ok(())
+    }
+
+    // Deletes the body at `index` in storage order, returning whether there
+    // was one.
+    pub fn remove(&mut self, index: usize) -> bool {
+        let mut ents = Write::<EntityStorage>::get_data(&self.world);
+        let ent = {
+            let masses = ReadComp::<Mass>::get_data(&self.world);
+            let species = ReadComp::<Species>::get_data(&self.world);
+            let positions = ReadComp::<Position>::get_data(&self.world);
+            let vels = ReadComp::<Velocity>::get_data(&self.world);
+            match (&masses, &species, &positions, &vels, ents.deref())
+                .join()
+                .nth(index)
+            {
+                Some((_, _, _, _, ent)) => *ent,
+                None => return false,
+            }
+        };
+        despawn(&mut BodyStorages::get_data(&self.world), &mut ents, &ent);
+        true
+    }
+
+    // Adds `dv` to the velocity of the body at `index` in storage order,
+    // returning whether there was one.
+    pub fn kick(&mut self, index: usize, dv: Velocity) -> bool {
+        let masses = ReadComp::<Mass>::get_data(&self.world);
+        let species = ReadComp::<Species>::get_data(&self.world);
+        let positions = ReadComp::<Position>::get_data(&self.world);
+        let mut vels = WriteComp::<Velocity>::get_data(&self.world);
+        match (&masses, &species, &positions, &mut vels).join().nth(index) {
+            Some((_, _, _, vel)) => {
+                vel.x += dv.x;
+                vel.y += dv.y;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The index in storage order of the body nearest `point`, if there are any.
+    pub fn nearest(&self, point: Position) -> Option<usize> {
+        self.bodies()
+            .iter()
+            .map(|b| (b.position.x - point.x).hypot(b.position.y - point.y))
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index)
+    }
+
+    pub fn set_timestep(&mut self, step: Option<f64>) {
+        Write::<Time>::get_data(&self.world).step = step;
+    }
+
+    // Restarts the wall clock so time spent paused is not simulated.
+    pub fn reset_clock(&mut self) {
+        Write::<Time>::get_data(&self.world).last = Instant::now();
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn configuration(&self) -> Configuration {
+        let time = Read::<Time>::get_data(&self.world);
+        Configuration {
+            bodies: self.bodies().len(),
+            species: Read::<InteractionMatrix>::get_data(&self.world).species(),
+            integrator: self.integrator,
+            step: time.step,
+            gravity: *Read::<Gravity>::get_data(&self.world),
+            tick: time.tick,
+            time: time.total,
+            threads: self.threads,
+            systems: self.scheduler.names.clone(),
+            estimated_memory: self.estimated_memory,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        Read::<Time>::get_data(&self.world).tick
+    }
+
+    // Current state of every body, in storage order.
+    pub fn bodies(&self) -> Vec<BodyState> {
+        let masses = ReadComp::<Mass>::get_data(&self.world);
+        let species = ReadComp::<Species>::get_data(&self.world);
+        let groups = ReadComp::<Group>::get_data(&self.world);
+        let positions = ReadComp::<Position>::get_data(&self.world);
+        let vels = ReadComp::<Velocity>::get_data(&self.world);
+        (&masses, &species, &groups, &positions, &vels)
+            .join()
+            .map(|(mass, species, group, position, velocity)| BodyState {
+                mass: mass.mass,
+                species: species.id,
+                group: group.0,
+                position: *position,
+                velocity: *velocity,
+            })
+            .collect()
+    }
+
+    // The estimated `Density` of every body, in storage order, or zero for
+    // bodies without one yet: all of them unless `density_estimate` is set,
+    // and those spawned since the last tick.
+    pub fn densities(&self) -> Vec<f32> {
+        let positions = ReadComp::<Position>::get_data(&self.world);
+        let densities = ReadComp::<Density>::get_data(&self.world);
+        let ents = Read::<EntityStorage>::get_data(&self.world);
+        (&positions, ents.deref())
+            .join()
+            .map(|(_, ent)| densities.get(ent).map_or(0.0, |d| d.rho))
+            .collect()
+    }
+
+    // The `Spin` of every body, in storage order; `None` for bodies without
+    // one.
+    pub fn spins(&self) -> Vec<Option<Spin>> {
+        let positions = ReadComp::<Position>::get_data(&self.world);
+        let spins = ReadComp::<Spin>::get_data(&self.world);
+        let ents = Read::<EntityStorage>::get_data(&self.world);
+        (&positions, ents.deref())
+            .join()
+            .map(|(_, ent)| spins.get(ent).copied())
+            .collect()
+    }
+
+    // The recorded states of the body at `index` in storage order, oldest
+    // first, up to the depth set with `SimulationBuilder::history`. Empty
+    // without it or if there is no such body.
+    pub fn body_history(&self, index: usize) -> Vec<HistorySample> {
+        let masses = ReadComp::<Mass>::get_data(&self.world);
+        let species = ReadComp::<Species>::get_data(&self.world);
+        let positions = ReadComp::<Position>::get_data(&self.world);
+        let vels = ReadComp::<Velocity>::get_data(&self.world);
+        let ents = Read::<EntityStorage>::get_data(&self.world);
+        match (&masses, &species, &positions, &vels, ents.deref())
+            .join()
+            .nth(index)
+        {
+            Some((_, _, _, _, ent)) => Read::<BodyHistory>::get_data(&self.world).of(ent),
+            None => Vec::new(),
+        }
+    }
+
+    // The clumps of the last `FriendsOfFriends` search, empty without
+    // `clump_finder` or before the first.
+    pub fn clumps(&self) -> ClumpCatalog {
+        Read::<ClumpCatalog>::get_data(&self.world).clone()
+    }
+
+    // The bodies a fraction `alpha` of the way from their positions before the
+    // last tick to the current ones, for rendering at a different rate from the
+    // physics. Needs `interpolation`; bodies created during the last tick are
+    // at their current positions.
+    pub fn interpolated(&self, alpha: f32) -> Vec<BodyState> {
+        let previous = ReadComp::<PreviousPosition>::get_data(&self.world);
+        let ents = Read::<EntityStorage>::get_data(&self.world);
+        let before: HashMap<Entity, PreviousPosition> = (&previous, ents.deref())
+            .join()
+            .map(|(pos, ent)| (*ent, *pos))
+            .collect();
+
+        let masses = ReadComp::<Mass>::get_data(&self.world);
+        let species = ReadComp::<Species>::get_data(&self.world);
+        let groups = ReadComp::<Group>::get_data(&self.world);
+        let positions = ReadComp::<Position>::get_data(&self.world);
+        let vels = ReadComp::<Velocity>::get_data(&self.world);
+        (&masses, &species, &groups, &positions, &vels, ents.deref())
+            .join()
+            .map(|(mass, species, group, position, velocity, ent)| {
+                let position = match before.get(ent) {
+                    Some(from) => Position {
+                        x: from.x + alpha * (position.x - from.x),
+                        y: from.y + alpha * (position.y - from.y),
+                    },
+                    None => *position,
+                };
+                BodyState {
+                    mass: mass.mass,
+                    species: species.id,
+                    group: group.0,
+                    position,
+                    velocity: *velocity,
+                }
+            })
+            .collect()
+    }
+
+    // Kinetic plus gravitational potential energy of the current state.
+    pub fn energy(&self) -> f32 {
+        let gravity = *Read::<Gravity>::get_data(&self.world);
+        let exclusions = Read::<Exclusions>::get_data(&self.world);
+        let bodies = self.bodies();
+        let mut energy = 0.0;
+        for (i, one) in bodies.iter().enumerate() {
+            let v = &one.velocity;
+            energy += 0.5 * one.mass * (v.x * v.x + v.y * v.y);
+            for two in &bodies[i + 1..] {
+                let d = distance(&one.position, &two.position);
+                if d > 0.0 && !exclusions.excludes(one.group, two.group) {
+                    energy -= gravity.g * one.mass * two.mass * gravity.potential(d);
+                }
+            }
+        }
+        energy
+    }
+
+    pub fn time(&self) -> f64 {
+        Read::<Time>::get_data(&self.world).total
+    }
+
+    // The fixed timestep, or `None` in wall-clock mode.
+    pub fn timestep(&self) -> Option<f64> {
+        Read::<Time>::get_data(&self.world).step
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        let time = *Read::<Time>::get_data(&self.world);
+        Checkpoint {
+            tick: time.tick,
+            time: time.total,
+            step: time.step,
+            gravity: *Read::<Gravity>::get_data(&self.world),
+            matrix: Read::<InteractionMatrix>::get_data(&self.world).clone(),
+            bodies: self.bodies(),
+            rng: Some(Read::<RandomState>::get_data(&self.world).clone()),
+            spins: self
+                .spins()
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, spin)| Some((index, spin?)))
+                .collect(),
+        }
+    }
+
+    // Relative energy drift is measured against the state at `build`.
+    pub fn summary(&self) -> RunSummary {
+        let energy = self.energy();
+        let drift = if self.initial_energy != 0.0 {
+            ((energy - self.initial_energy) / self.initial_energy).abs()
+        } else {
+            (energy - self.initial_energy).abs()
+        };
+        RunSummary {
+            ticks: self.tick() - self.initial_tick,
+            time: self.time(),
+            wall_seconds: self.started.elapsed().as_secs_f64(),
+            bodies: self.bodies().len(),
+            energy_drift: drift,
+        }
+    }
+
+    pub fn latest_stats(&self) -> Option<StatsSample> {
+        Read::<Statistics>::get_data(&self.world).latest().cloned()
+    }
+
+    // Flushes every buffered output; call once the run is over.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        Write::<StatsOutput>::get_data(&self.world).close()?;
+        if self.rotation_curve {
+            Write::<RotationCurveOutput>::get_data(&self.world).flush()?;
+        }
+        if self.orbital_elements {
+            Write::<OrbitalElementsOutput>::get_data(&self.world).flush()?;
+        }
+        Write::<EventLog>::get_data(&self.world).flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+// Dumps of body state, one row per body: CSV, gzip or zstd compressed CSV for
+// paths ending in `.gz` or `.zst`, or Parquet for paths ending in `.parquet`
+// (with the `columnar` feature), and read back.
+use crate::components::*;
+use crate::output::OutputHandle;
+use crate::resources::Time;
+use crate::scenarios::BodyState;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use SmolECS::{component::*, system::*, world::*};
+
+fn write_rows<W: Write>(
+    mut writer: W,
+    tick: u64,
+    time: f64,
+    bodies: &[BodyState],
+) -> io::Result<W> {
+    writeln!(writer, "# tick {} time {}", tick, time)?;
+    writeln!(writer, "index,mass,species,x,y,vx,vy,group")?;
+    for (i, body) in bodies.iter().enumerate() {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{}",
+            i,
+            body.mass,
+            body.species,
+            body.position.x,
+            body.position.y,
+            body.velocity.x,
+            body.velocity.y,
+            body.group
+        )?;
+    }
+    writer.flush()?;
+    Ok(writer)
+}
+
+pub fn write_csv<P: AsRef<Path>>(
+    path: P,
+    tick: u64,
+    time: f64,
+    bodies: &[BodyState],
+) -> io::Result<()> {
+    write_rows(BufWriter::new(File::create(path)?), tick, time, bodies).map(|_| ())
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression '{}'", name)),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "csv",
+            Compression::Gzip => "csv.gz",
+            Compression::Zstd => "csv.zst",
+        }
+    }
+
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+pub fn write<P: AsRef<Path>>(
+    path: P,
+    tick: u64,
+    time: f64,
+    bodies: &[BodyState],
+) -> io::Result<()> {
+    if path.as_ref().extension().is_some_and(|e| e == "parquet") {
+        #[cfg(feature = "columnar")]
+        return crate::columnar::write_snapshot(path, tick, time, bodies);
+        #[cfg(not(feature = "columnar"))]
+        return Err(io::Error::other(format!(
+            "{} needs a build with the \"columnar\" feature",
+            path.as_ref().display()
+        )));
+    }
+    let file = BufWriter::new(File::create(path.as_ref())?);
+    match Compression::of(path.as_ref()) {
+        Compression::None => write_rows(file, tick, time, bodies).map(|_| ()),
+        #[cfg(feature = "compression")]
+        Compression::Gzip => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            write_rows(encoder, tick, time, bodies)?.finish()?;
+            Ok(())
+        }
+        #[cfg(feature = "compression")]
+        Compression::Zstd => {
+            let encoder = zstd::stream::write::Encoder::new(file, 0)?;
+            write_rows(encoder, tick, time, bodies)?.finish()?;
+            Ok(())
+        }
+        #[cfg(not(feature = "compression"))]
+        Compression::Gzip | Compression::Zstd => Err(needs_compression(path.as_ref())),
+    }
+}
+
+// A snapshot read back from disk.
+pub struct Snapshot {
+    pub tick: u64,
+    pub time: f64,
+    pub bodies: Vec<BodyState>,
+}
+
+fn invalid(path: &Path, line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", path.display(), line, message),
+    )
+}
+
+fn read_rows<R: BufRead>(reader: R, path: &Path) -> io::Result<Snapshot> {
+    let mut snapshot = Snapshot {
+        tick: 0,
+        time: 0.0,
+        bodies: Vec::new(),
+    };
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if let Some(comment) = line.strip_prefix('#') {
+            let words: Vec<&str> = comment.split_whitespace().collect();
+            if let ["tick", tick, "time", time] = words[..] {
+                snapshot.tick = tick.parse().map_err(|_| invalid(path, i + 1, "bad tick"))?;
+                snapshot.time = time.parse().map_err(|_| invalid(path, i + 1, "bad time"))?;
+            }
+            continue;
+        }
+        if line.is_empty() || line.starts_with("index") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        // snapshots from before groups have no group column
+        if fields.len() != 7 && fields.len() != 8 {
+            return Err(invalid(path, i + 1, "expected 8 columns"));
+        }
+        let number = |j: usize| {
+            fields[j]
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| invalid(path, i + 1, "bad number"))
+        };
+        snapshot.bodies.push(BodyState {
+            mass: number(1)?,
+            species: fields[2]
+                .trim()
+                .parse()
+                .map_err(|_| invalid(path, i + 1, "bad species"))?,
+            group: match fields.get(7) {
+                Some(group) => group
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(path, i + 1, "bad group"))?,
+                None => 0,
+            },
+            position: Position {
+                x: number(3)?,
+                y: number(4)?,
+            },
+            velocity: Velocity {
+                x: number(5)?,
+                y: number(6)?,
+            },
+        });
+    }
+    Ok(snapshot)
+}
+
+// Reads a snapshot written by `write`, in any of its formats.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+    let path = path.as_ref();
+    if path.extension().is_some_and(|e| e == "parquet") {
+        #[cfg(feature = "columnar")]
+        return crate::columnar::read_snapshot(path);
+        #[cfg(not(feature = "columnar"))]
+        return Err(io::Error::other(format!(
+            "{} needs a build with the \"columnar\" feature",
+            path.display()
+        )));
+    }
+    let file = File::open(path)?;
+    match Compression::of(path) {
+        Compression::None => read_rows(BufReader::new(file), path),
+        #[cfg(feature = "compression")]
+        Compression::Gzip => read_rows(BufReader::new(flate2::read::GzDecoder::new(file)), path),
+        #[cfg(feature = "compression")]
+        Compression::Zstd => read_rows(
+            BufReader::new(zstd::stream::read::Decoder::new(file)?),
+            path,
+        ),
+        #[cfg(not(feature = "compression"))]
+        Compression::Gzip | Compression::Zstd => Err(needs_compression(path)),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn needs_compression(path: &Path) -> io::Error {
+    io::Error::other(format!(
+        "{} needs a build with the \"compression\" feature",
+        path.display()
+    ))
+}
+
+// Limits on the snapshots a `SnapshotSeries` keeps; the oldest are deleted
+// first, and the newest is always kept.
+#[derive(Copy, Clone, Default)]
+pub struct Retention {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+// Every `interval` ticks, writes the bodies to `<prefix>_<tick>.csv` (with the
+// tick zero-padded to six digits and the extension of `compression`), then
+// deletes old snapshots beyond the retention limits. Only snapshots written
+// by this run count towards them. With `queued` the writing and deleting is
+// done on an output thread, from a copy of the bodies.
+pub struct SnapshotSeries {
+    prefix: String,
+    interval: u64,
+    compression: Compression,
+    retention: Retention,
+    // path, size and time written, oldest first
+    written: Arc<Mutex<VecDeque<(PathBuf, u64, SystemTime)>>>,
+    queue: Option<OutputHandle>,
+}
+
+// Deletes the oldest snapshots in `written` beyond the limits of `retention`.
+fn prune(retention: Retention, written: &mut VecDeque<(PathBuf, u64, SystemTime)>) {
+    let now = SystemTime::now();
+    let mut total: u64 = written.iter().map(|w| w.1).sum();
+    while written.len() > 1 {
+        let (_, size, at) = &written[0];
+        let too_big = retention.max_bytes.is_some_and(|max| total > max);
+        let too_old = retention
+            .max_age
+            .is_some_and(|max| now.duration_since(*at).unwrap_or_default() > max);
+        if !too_big && !too_old {
+            break;
+        }
+        total -= size;
+        let (path, _, _) = written.pop_front().unwrap();
+        if let Err(e) = std::fs::remove_file(&path) {
+            eprintln!("failed to delete {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Writes a snapshot of the series and prunes the old ones.
+fn write_series(
+    path: PathBuf,
+    tick: u64,
+    time: f64,
+    bodies: &[BodyState],
+    retention: Retention,
+    written: &Mutex<VecDeque<(PathBuf, u64, SystemTime)>>,
+) -> io::Result<()> {
+    write(&path, tick, time, bodies)?;
+    let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+
+    let mut written = written.lock().unwrap();
+    written.push_back((path, size, SystemTime::now()));
+    prune(retention, &mut written);
+    Ok(())
+}
+
+impl SnapshotSeries {
+    pub fn new(
+        prefix: &str,
+        interval: u64,
+        compression: Compression,
+        retention: Retention,
+    ) -> Self {
+        SnapshotSeries {
+            prefix: prefix.to_string(),
+            interval,
+            compression,
+            retention,
+            written: Arc::new(Mutex::new(VecDeque::new())),
+            queue: None,
+        }
+    }
+
+    pub fn queued(mut self, queue: OutputHandle) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for SnapshotSeries {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Species>,
+        ReadComp<'d, Group>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        Read<'d, Time>,
+    );
+
+    fn run(&self, (masses, species, groups, positions, vels, time): Self::SystemData) {
+        if time.tick % self.interval != 0 {
+            return;
+        }
+        let bodies: Vec<BodyState> = (&masses, &species, &groups, &positions, &vels)
+            .join()
+            .map(|(mass, species, group, position, velocity)| BodyState {
+                mass: mass.mass,
+                species: species.id,
+                group: group.0,
+                position: *position,
+                velocity: *velocity,
+            })
+            .collect();
+
+        let path = PathBuf::from(format!(
+            "{}_{:06}.{}",
+            self.prefix,
+            time.tick,
+            self.compression.extension()
+        ));
+        let (tick, total, retention) = (time.tick, time.total, self.retention);
+        match &self.queue {
+            Some(queue) => {
+                let written = self.written.clone();
+                queue.submit(move || write_series(path, tick, total, &bodies, retention, &written));
+            }
+            None => write_series(path, tick, total, &bodies, retention, &self.written)
+                .expect("failed to write snapshot"),
+        }
+    }
+}
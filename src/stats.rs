@@ -0,0 +1,570 @@
+use crate::components::*;
+use crate::output::OutputHandle;
+use crate::resources::*;
+use crate::systems::distance;
+use std::fs::File;
+use std::io::{BufWriter, Write as IoWrite};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use SmolECS::{component::*, entity::*, system::*, world::*};
+
+#[derive(Copy, Clone)]
+pub struct StatsSample {
+    pub tick: u64,
+    pub time: f64,
+    pub kinetic: f32,
+    pub potential: f32,
+    pub max_speed: f32,
+    pub min_pair_distance: f32,
+    pub bodies: u32,
+    pub escaped: u32,
+    pub virial_ratio: f32,
+    pub half_mass_radius: f32,
+    pub core_density: f32,
+}
+
+// Per-tick aggregates, kept in a fixed-size ring buffer so it can stay on for
+// the whole run without growing.
+pub struct Statistics {
+    samples: Vec<StatsSample>,
+    capacity: usize,
+    head: usize,
+}
+
+impl Statistics {
+    pub fn new(capacity: usize) -> Self {
+        Statistics {
+            samples: Vec::with_capacity(capacity),
+            capacity,
+            head: 0,
+        }
+    }
+
+    pub fn push(&mut self, sample: StatsSample) {
+        if self.samples.len() < self.capacity {
+            self.samples.push(sample);
+        } else {
+            self.samples[self.head] = sample;
+        }
+        self.head = (self.head + 1) % self.capacity;
+    }
+
+    pub fn latest(&self) -> Option<&StatsSample> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let last = (self.head + self.capacity - 1) % self.capacity;
+        self.samples.get(last)
+    }
+
+    // Oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = &StatsSample> {
+        let (newer, older) = self.samples.split_at(self.head.min(self.samples.len()));
+        older.iter().chain(newer.iter())
+    }
+}
+
+// The per-tick table goes to the given path, as Parquet if it ends in
+// `.parquet` (with the `columnar` feature); analyses with rows of their own
+// (e.g. the pair correlation function) get sibling CSV tables named
+// `<stem>.<table>.csv` next to it. With `queued` the rows of the per-tick CSV
+// table are written on an output thread.
+pub struct StatsOutput {
+    path: Option<std::path::PathBuf>,
+    writer: Option<BufWriter<File>>,
+    queued: Option<(OutputHandle, Arc<Mutex<BufWriter<File>>>)>,
+    #[cfg(feature = "columnar")]
+    parquet: Option<crate::columnar::StatsWriter>,
+    tables: std::collections::HashMap<&'static str, BufWriter<File>>,
+}
+
+impl StatsOutput {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        if path.ends_with(".parquet") {
+            return Self::create_parquet(path);
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "tick,time,kinetic,potential,total,max_speed,min_pair_distance,bodies,\
+             escaped,virial_ratio,half_mass_radius,core_density"
+        )?;
+        Ok(StatsOutput {
+            path: Some(path.into()),
+            writer: Some(writer),
+            #[cfg(feature = "columnar")]
+            parquet: None,
+            queued: None,
+            tables: std::collections::HashMap::new(),
+        })
+    }
+
+    #[cfg(feature = "columnar")]
+    fn create_parquet(path: &str) -> std::io::Result<Self> {
+        Ok(StatsOutput {
+            path: Some(path.into()),
+            writer: None,
+            parquet: Some(crate::columnar::StatsWriter::create(path)?),
+            queued: None,
+            tables: std::collections::HashMap::new(),
+        })
+    }
+
+    #[cfg(not(feature = "columnar"))]
+    fn create_parquet(path: &str) -> std::io::Result<Self> {
+        Err(std::io::Error::other(format!(
+            "{} needs a build with the \"columnar\" feature",
+            path
+        )))
+    }
+
+    pub fn none() -> Self {
+        StatsOutput {
+            path: None,
+            writer: None,
+            #[cfg(feature = "columnar")]
+            parquet: None,
+            queued: None,
+            tables: std::collections::HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn queued(mut self, queue: OutputHandle) -> Self {
+        if let Some(writer) = self.writer.take() {
+            self.queued = Some((queue, Arc::new(Mutex::new(writer))));
+        }
+        self
+    }
+
+    // Returns the writer for an auxiliary table, creating it with `header` on
+    // first use. `None` when statistics output is disabled.
+    pub fn table(
+        &mut self,
+        name: &'static str,
+        header: &str,
+    ) -> std::io::Result<Option<&mut BufWriter<File>>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        if !self.tables.contains_key(name) {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let table_path = path.with_file_name(format!("{}.{}.csv", stem, name));
+            let mut writer = BufWriter::new(File::create(table_path)?);
+            writeln!(writer, "{}", header)?;
+            self.tables.insert(name, writer);
+        }
+        Ok(self.tables.get_mut(name))
+    }
+
+    pub fn append(&mut self, sample: &StatsSample) -> std::io::Result<()> {
+        #[cfg(feature = "columnar")]
+        if let Some(writer) = self.parquet.as_mut() {
+            writer.append(sample)?;
+        }
+        if self.writer.is_none() && self.queued.is_none() {
+            return Ok(());
+        }
+        let row = format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            sample.tick,
+            sample.time,
+            sample.kinetic,
+            sample.potential,
+            sample.kinetic + sample.potential,
+            sample.max_speed,
+            sample.min_pair_distance,
+            sample.bodies,
+            sample.escaped,
+            sample.virial_ratio,
+            sample.half_mass_radius,
+            sample.core_density
+        );
+        match (&mut self.writer, &self.queued) {
+            (Some(writer), _) => writeln!(writer, "{}", row)?,
+            (None, Some((queue, writer))) => {
+                let writer = writer.clone();
+                queue.submit(move || writeln!(writer.lock().unwrap(), "{}", row));
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        if let Some((queue, writer)) = &self.queued {
+            let writer = writer.clone();
+            queue.submit(move || writer.lock().unwrap().flush());
+        }
+        #[cfg(feature = "columnar")]
+        if let Some(writer) = self.parquet.as_mut() {
+            writer.flush()?;
+        }
+        for writer in self.tables.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Flushes everything and finishes a Parquet table, after which no more
+    // samples are written.
+    pub fn close(&mut self) -> std::io::Result<()> {
+        #[cfg(feature = "columnar")]
+        if let Some(writer) = self.parquet.as_mut() {
+            writer.close()?;
+        }
+        self.flush()
+    }
+}
+
+// Overview of a statistics table written by `StatsOutput`, for `analyze`.
+pub struct StatsReport {
+    pub samples: usize,
+    pub ticks: (u64, u64),
+    pub time: (f64, f64),
+    pub energy: (f64, f64),
+    // largest |E - E0| / |E0| over the run
+    pub max_energy_drift: f64,
+    pub bodies: (u64, u64),
+    pub escaped: u64,
+    pub min_pair_distance: f64,
+    pub virial_ratio: (f64, f64),
+    pub mean_virial_ratio: f64,
+    pub half_mass_radius: (f64, f64),
+}
+
+impl StatsReport {
+    // Reads a CSV statistics table; empty tables are an error.
+    pub fn read(path: &str) -> Result<Self, String> {
+        if path.ends_with(".parquet") {
+            return Err(format!(
+                "{}: reading Parquet statistics is not supported",
+                path
+            ));
+        }
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut lines = text.lines().filter(|line| !line.is_empty());
+        let header: Vec<&str> = lines
+            .next()
+            .ok_or_else(|| format!("{}: empty table", path))?
+            .split(',')
+            .collect();
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|h| *h == name)
+                .ok_or_else(|| format!("{}: no {} column", path, name))
+        };
+        let columns = [
+            column("tick")?,
+            column("time")?,
+            column("total")?,
+            column("bodies")?,
+            column("escaped")?,
+            column("min_pair_distance")?,
+            column("virial_ratio")?,
+            column("half_mass_radius")?,
+        ];
+        let mut rows: Vec<[f64; 8]> = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.split(',').collect();
+            let mut row = [0.0; 8];
+            for (value, &c) in row.iter_mut().zip(&columns) {
+                *value = fields
+                    .get(c)
+                    .and_then(|f| f.parse().ok())
+                    .ok_or_else(|| format!("{}:{}: bad row", path, i + 2))?;
+            }
+            rows.push(row);
+        }
+        let (first, last) = match (rows.first(), rows.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(format!("{}: no samples", path)),
+        };
+
+        let e0 = first[2];
+        let max_energy_drift = rows
+            .iter()
+            .map(|r| ((r[2] - e0) / e0.abs().max(f64::MIN_POSITIVE)).abs())
+            .fold(0.0, f64::max);
+        Ok(StatsReport {
+            samples: rows.len(),
+            ticks: (first[0] as u64, last[0] as u64),
+            time: (first[1], last[1]),
+            energy: (e0, last[2]),
+            max_energy_drift,
+            bodies: (first[3] as u64, last[3] as u64),
+            escaped: last[4] as u64,
+            min_pair_distance: rows.iter().map(|r| r[5]).fold(f64::INFINITY, f64::min),
+            virial_ratio: (first[6], last[6]),
+            mean_virial_ratio: rows.iter().map(|r| r[6]).sum::<f64>() / rows.len() as f64,
+            half_mass_radius: (first[7], last[7]),
+        })
+    }
+}
+
+impl std::fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "samples:          {}", self.samples)?;
+        writeln!(f, "ticks:            {} -> {}", self.ticks.0, self.ticks.1)?;
+        writeln!(f, "time:             {} -> {}", self.time.0, self.time.1)?;
+        writeln!(
+            f,
+            "energy:           {} -> {}",
+            self.energy.0, self.energy.1
+        )?;
+        writeln!(f, "max energy drift: {:e}", self.max_energy_drift)?;
+        writeln!(
+            f,
+            "bodies:           {} -> {}",
+            self.bodies.0, self.bodies.1
+        )?;
+        writeln!(f, "escaped:          {}", self.escaped)?;
+        writeln!(f, "min separation:   {}", self.min_pair_distance)?;
+        writeln!(
+            f,
+            "virial ratio:     {} -> {} (mean {})",
+            self.virial_ratio.0, self.virial_ratio.1, self.mean_virial_ratio
+        )?;
+        write!(
+            f,
+            "half-mass radius: {} -> {}",
+            self.half_mass_radius.0, self.half_mass_radius.1
+        )
+    }
+}
+
+// Fraction of the total mass treated as the core when estimating core density.
+const CORE_MASS_FRACTION: f32 = 0.1;
+
+pub fn center_of_mass(bodies: &[(f32, Position)]) -> (f32, f32) {
+    let total: f32 = bodies.iter().map(|b| b.0).sum();
+    if total <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let x: f32 = bodies.iter().map(|(m, p)| m * p.x).sum();
+    let y: f32 = bodies.iter().map(|(m, p)| m * p.y).sum();
+    (x / total, y / total)
+}
+
+// Radius about the centre of mass enclosing `fraction` of the total mass.
+// `sorted` holds (radius, mass) pairs ordered by radius.
+pub fn lagrangian_radius(sorted: &[(f32, f32)], total_mass: f32, fraction: f32) -> f32 {
+    let mut enclosed = 0.0;
+    for (r, m) in sorted {
+        enclosed += m;
+        if enclosed >= fraction * total_mass {
+            return *r;
+        }
+    }
+    sorted.last().map(|s| s.0).unwrap_or(0.0)
+}
+
+// Every `interval` ticks, pushes a `StatsSample` of the whole system.
+pub struct ComputeStatistics {
+    pub interval: u64,
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for ComputeStatistics {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        (ReadComp<'d, Escaped>, ReadComp<'d, Group>),
+        Read<'d, EntityStorage>,
+        (Read<'d, Gravity>, Read<'d, Exclusions>),
+        Read<'d, Time>,
+        Write<'d, Statistics>,
+    );
+
+    fn run(
+        &self,
+        (
+            masses,
+            positions,
+            vels,
+            (escaped, groups),
+            ents,
+            (gravity, exclusions),
+            time,
+            mut stats,
+        ): Self::SystemData,
+    ) {
+        if time.tick % self.interval != 0 {
+            return;
+        }
+
+        let bodies: Vec<(f32, Position)> = (&masses, &positions)
+            .join()
+            .map(|(mass, pos)| (mass.mass, *pos))
+            .collect();
+        let groups: Vec<u32> = (&groups, &positions).join().map(|(g, _)| g.0).collect();
+
+        let mut kinetic = 0.0;
+        let mut max_speed: f32 = 0.0;
+        for (mass, vel) in (&masses, &vels).join() {
+            let speed_sq = vel.x * vel.x + vel.y * vel.y;
+            kinetic += 0.5 * mass.mass * speed_sq;
+            max_speed = max_speed.max(speed_sq.sqrt());
+        }
+
+        let mut potential = 0.0;
+        let mut min_pair_distance = f32::INFINITY;
+        for (i, (mass_one, pos_one)) in bodies.iter().enumerate() {
+            for (j, (mass_two, pos_two)) in bodies.iter().enumerate().skip(i + 1) {
+                let d = distance(pos_one, pos_two);
+                min_pair_distance = min_pair_distance.min(d);
+                if d > 0.0 && !exclusions.excludes(groups[i], groups[j]) {
+                    potential -= gravity.g * mass_one * mass_two * gravity.potential(d);
+                }
+            }
+        }
+
+        let (x, y) = center_of_mass(&bodies);
+        let center = Position { x, y };
+        let mut radii: Vec<(f32, f32)> = bodies
+            .iter()
+            .map(|(mass, pos)| (distance(pos, &center), *mass))
+            .collect();
+        radii.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total_mass: f32 = bodies.iter().map(|b| b.0).sum();
+        let half_mass_radius = lagrangian_radius(&radii, total_mass, 0.5);
+
+        // Surface density inside the radius holding the innermost slice of mass.
+        let core_radius = lagrangian_radius(&radii, total_mass, CORE_MASS_FRACTION);
+        let core_density = if core_radius > 0.0 {
+            CORE_MASS_FRACTION * total_mass / (std::f32::consts::PI * core_radius * core_radius)
+        } else {
+            0.0
+        };
+
+        let virial_ratio = if potential != 0.0 {
+            2.0 * kinetic / potential.abs()
+        } else {
+            0.0
+        };
+
+        let sample = StatsSample {
+            tick: time.tick,
+            time: time.total,
+            kinetic,
+            potential,
+            max_speed,
+            min_pair_distance,
+            bodies: bodies.len() as u32,
+            escaped: (&escaped, ents.deref()).join().count() as u32,
+            virial_ratio,
+            half_mass_radius,
+            core_density,
+        };
+        stats.push(sample);
+    }
+}
+
+// Every tick, one row per group in the `groups` statistics table: the body count
+// and mass, the centre of mass and its velocity, and the kinetic energy about
+// that centre and the potential energy between members, whose sum is negative
+// while the group is bound.
+pub struct GroupStatistics;
+impl<'d, 'w: 'd> System<'d, 'w, World> for GroupStatistics {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        ReadComp<'d, Group>,
+        Read<'d, Gravity>,
+        Read<'d, Exclusions>,
+        Read<'d, Time>,
+        Write<'d, StatsOutput>,
+    );
+
+    fn run(
+        &self,
+        (masses, positions, vels, groups, gravity, exclusions, time, mut output): Self::SystemData,
+    ) {
+        let table = output
+            .table(
+                "groups",
+                "tick,time,group,bodies,mass,x,y,vx,vy,kinetic,potential,total",
+            )
+            .expect("failed to create group statistics output");
+        let writer = match table {
+            Some(writer) => writer,
+            None => return,
+        };
+
+        let mut members: std::collections::BTreeMap<u32, Vec<(f32, Position, Velocity)>> =
+            std::collections::BTreeMap::new();
+        for (mass, pos, vel, group) in (&masses, &positions, &vels, &groups).join() {
+            members
+                .entry(group.0)
+                .or_default()
+                .push((mass.mass, *pos, *vel));
+        }
+
+        for (group, bodies) in members {
+            let mass: f32 = bodies.iter().map(|b| b.0).sum();
+            let weight = if mass > 0.0 { 1.0 / mass } else { 0.0 };
+            let (mut x, mut y, mut vx, mut vy) = (0.0, 0.0, 0.0, 0.0);
+            for (m, pos, vel) in &bodies {
+                x += m * pos.x * weight;
+                y += m * pos.y * weight;
+                vx += m * vel.x * weight;
+                vy += m * vel.y * weight;
+            }
+
+            let mut kinetic = 0.0;
+            let mut potential = 0.0;
+            let internal = !exclusions.excludes(group, group);
+            for (i, (mass_one, pos_one, vel_one)) in bodies.iter().enumerate() {
+                let (dvx, dvy) = (vel_one.x - vx, vel_one.y - vy);
+                kinetic += 0.5 * mass_one * (dvx * dvx + dvy * dvy);
+                for (mass_two, pos_two, _) in &bodies[i + 1..] {
+                    let d = distance(pos_one, pos_two);
+                    if d > 0.0 && internal {
+                        potential -= gravity.g * mass_one * mass_two * gravity.potential(d);
+                    }
+                }
+            }
+
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{}",
+                time.tick,
+                time.total,
+                group,
+                bodies.len(),
+                mass,
+                x,
+                y,
+                vx,
+                vy,
+                kinetic,
+                potential,
+                kinetic + potential
+            )
+            .expect("failed to write group statistics output");
+        }
+    }
+}
+
+// Appends the sample `ComputeStatistics` took this tick, if it took one.
+pub struct WriteStatistics;
+impl<'d, 'w: 'd> System<'d, 'w, World> for WriteStatistics {
+    type SystemData = (Read<'d, Statistics>, Read<'d, Time>, Write<'d, StatsOutput>);
+
+    fn run(&self, (stats, time, mut output): Self::SystemData) {
+        if let Some(sample) = stats.latest().filter(|s| s.tick == time.tick) {
+            output
+                .append(sample)
+                .expect("failed to write statistics output");
+        }
+    }
+}
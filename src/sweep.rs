@@ -0,0 +1,141 @@
+// Parameter sweeps for convergence studies: each row of a table of option
+// values is run as a separate process of the simulation binary, and the run
+// summaries are collected into one CSV table.
+use crate::simulation::RunSummary;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// A header of long option names (`count,dt,far-field-interval`, dashes
+// optional) and one row of values per run. An empty cell or `false` leaves the
+// option out and `true` passes it as a bare flag. Blank lines and lines
+// starting with `#` are skipped.
+pub struct Sweep {
+    pub options: Vec<String>,
+    pub runs: Vec<Vec<String>>,
+}
+
+impl Sweep {
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let options: Vec<String> = match lines.next() {
+            Some(header) => header
+                .split(',')
+                .map(|option| option.trim().trim_start_matches("--").to_string())
+                .collect(),
+            None => return Err(format!("{}: no options", path)),
+        };
+
+        let mut runs = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let values: Vec<String> = line.split(',').map(|v| v.trim().to_string()).collect();
+            if values.len() != options.len() {
+                return Err(format!(
+                    "{}: run {} has {} values for {} options",
+                    path,
+                    i + 1,
+                    values.len(),
+                    options.len()
+                ));
+            }
+            runs.push(values);
+        }
+        Ok(Sweep { options, runs })
+    }
+
+    // The command-line arguments for run `index`.
+    pub fn args(&self, index: usize) -> Vec<String> {
+        let mut args = Vec::new();
+        for (option, value) in self.options.iter().zip(&self.runs[index]) {
+            match value.as_str() {
+                "" | "false" => {}
+                "true" => args.push(format!("--{}", option)),
+                _ => {
+                    args.push(format!("--{}", option));
+                    args.push(value.clone());
+                }
+            }
+        }
+        args
+    }
+}
+
+// Runs `program` with `common` followed by the arguments of each run of
+// `sweep`, at most `jobs` at a time, and writes the values and summary of every
+// run to the CSV file `output` in the order of the sweep. A failed run keeps its
+// row, with empty results and the reason in the status column.
+pub fn run(
+    program: &Path,
+    sweep: &Sweep,
+    common: &[String],
+    jobs: usize,
+    output: &str,
+) -> io::Result<()> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<RunSummary, String>>>> =
+        Mutex::new(sweep.runs.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= sweep.runs.len() {
+                    break;
+                }
+                let args = sweep.args(index);
+                eprintln!("run {}/{}: {}", index + 1, sweep.runs.len(), args.join(" "));
+                let result = run_one(program, common, &args);
+                if let Err(reason) = &result {
+                    eprintln!("run {} failed: {}", index + 1, reason);
+                }
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    let mut out = BufWriter::new(File::create(output)?);
+    for option in &sweep.options {
+        write!(out, "{},", option)?;
+    }
+    writeln!(out, "ticks,time,wall_seconds,bodies,energy_drift,status")?;
+    let results = results.into_inner().unwrap();
+    for (values, result) in sweep.runs.iter().zip(results) {
+        for value in values {
+            write!(out, "{},", value)?;
+        }
+        match result.unwrap() {
+            Ok(summary) => writeln!(
+                out,
+                "{},{},{},{},{},ok",
+                summary.ticks,
+                summary.time,
+                summary.wall_seconds,
+                summary.bodies,
+                summary.energy_drift
+            )?,
+            Err(reason) => writeln!(out, ",,,,,{}", reason)?,
+        }
+    }
+    out.flush()
+}
+
+fn run_one(program: &Path, common: &[String], args: &[String]) -> Result<RunSummary, String> {
+    let output = Command::new(program)
+        .args(common)
+        .args(args)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(match output.status.code() {
+            Some(code) => format!("exit status {}", code),
+            None => "killed by a signal".to_string(),
+        });
+    }
+    String::from_utf8_lossy(&output.stdout).parse()
+}
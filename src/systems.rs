@@ -0,0 +1,1489 @@
+use crate::cells::{morton, CellList, NeighborGrid};
+use crate::components::*;
+use crate::events::{Event, EventLog};
+use crate::history::BodyHistory;
+use crate::resources::*;
+use rand::prelude::*;
+use std::ops::Deref;
+use SmolECS::rayon::{current_num_threads, prelude::*};
+use SmolECS::{component::*, entity::*, system::*, world::*};
+
+pub struct UpdateTime;
+impl<'d, 'w: 'd> System<'d, 'w, World> for UpdateTime {
+    type SystemData = Write<'d, Time>;
+
+    fn run(&self, mut time: Self::SystemData) {
+        let current = std::time::Instant::now();
+        match time.step {
+            Some(step) => {
+                time.delta = step;
+                time.total += step;
+            }
+            None => {
+                time.delta = current.duration_since(time.last).as_secs_f64();
+                time.total += time.delta;
+            }
+        }
+        time.last = current;
+        time.tick += 1;
+    }
+}
+
+// The storage of every per-body component, and the `BodyHistory`, for
+// removing a body whole.
+pub type BodyStorages<'d> = (
+    (
+        WriteComp<'d, Body>,
+        WriteComp<'d, Mass>,
+        WriteComp<'d, Species>,
+        WriteComp<'d, Group>,
+        WriteComp<'d, BodyId>,
+    ),
+    (
+        WriteComp<'d, Acceleration>,
+        WriteComp<'d, Jerk>,
+        WriteComp<'d, Velocity>,
+        WriteComp<'d, Position>,
+        WriteComp<'d, PreviousPosition>,
+    ),
+    (
+        WriteComp<'d, Escaped>,
+        WriteComp<'d, Density>,
+        WriteComp<'d, ClumpId>,
+        WriteComp<'d, Primary>,
+        WriteComp<'d, Orbiter>,
+        WriteComp<'d, Spin>,
+    ),
+    Write<'d, BodyHistory>,
+);
+
+// Deletes `ent` with all of its components and recorded history.
+pub fn despawn(storages: &mut BodyStorages, ents: &mut EntityStorage, ent: &Entity) {
+    let (
+        (bodies, masses, species, groups, ids),
+        (accels, jerks, vels, positions, previous),
+        (escaped, densities, clumps, primaries, orbiters, spins),
+        history,
+    ) = storages;
+    bodies.remove(ent);
+    masses.remove(ent);
+    species.remove(ent);
+    groups.remove(ent);
+    ids.remove(ent);
+    accels.remove(ent);
+    jerks.remove(ent);
+    vels.remove(ent);
+    positions.remove(ent);
+    previous.remove(ent);
+    escaped.remove(ent);
+    densities.remove(ent);
+    clumps.remove(ent);
+    primaries.remove(ent);
+    orbiters.remove(ent);
+    spins.remove(ent);
+    history.forget(ent);
+    ents.delete_entity(ent);
+}
+
+pub fn overlapping(pos_one: &Position, pos_two: &Position, epsilon: f32) -> bool {
+    ((pos_one.x - pos_two.x).abs() <= epsilon) && ((pos_one.y - pos_two.y).abs() <= epsilon)
+}
+
+pub fn distance(pos_one: &Position, pos_two: &Position) -> f32 {
+    let delta_x = pos_one.x - pos_two.x;
+    let delta_y = pos_one.y - pos_two.y;
+
+    let d = delta_x.powf(2.0) + delta_y.powf(2.0);
+    d.sqrt()
+}
+
+// Direct summation over every pair, each pair visited once with the
+// acceleration of both bodies taken from the same separation. Tracers only have
+// their pull from the other bodies summed, not each other's. Rows of pairs
+// are dealt out to the threads of the pool in turn, so every thread does about
+// as much work, and each adds into a buffer of its own; the buffers are then
+// added up in order, so the result doesn't depend on the scheduling. With
+// `compensated` the contributions to each body are added with Kahan summation,
+// for large N where the many small f32 terms otherwise lose precision. Pairs
+// of groups in the `Exclusions` are skipped.
+pub struct ApplyGravity {
+    pub compensated: bool,
+}
+
+impl ApplyGravity {
+    // The sums of every `blocks`-th row of pairs starting at `block`.
+    // `sources` are the indices of the bodies with mass, in order, and
+    // `excluded` says whether a pair of them is skipped.
+    fn block(
+        &self,
+        bodies: &[(Mass, Position, Species)],
+        sources: &[usize],
+        (block, blocks): (usize, usize),
+        excluded: &(dyn Fn(usize, usize) -> bool + Sync),
+        matrix: &InteractionMatrix,
+        gravity: &Gravity,
+    ) -> Vec<ForceSum> {
+        let mut sums = vec![ForceSum::new(self.compensated); bodies.len()];
+        for i in (block..bodies.len()).step_by(blocks) {
+            let one = &bodies[i];
+            if one.0.mass == 0.0 {
+                for &j in sources.iter().filter(|&&j| !excluded(i, j)) {
+                    let two = &bodies[j];
+                    sums[i].add(pull(
+                        &one.1, &one.2, &two.0, &two.1, &two.2, matrix, gravity,
+                    ));
+                }
+                continue;
+            }
+            for &j in &sources[sources.partition_point(|&j| j <= i)..] {
+                if excluded(i, j) {
+                    continue;
+                }
+                let (on_one, on_two) = pull_pair(one, &bodies[j], matrix, gravity);
+                sums[i].add(on_one);
+                sums[j].add(on_two);
+            }
+        }
+        sums
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for ApplyGravity {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Species>,
+        ReadComp<'d, Group>,
+        WriteComp<'d, Acceleration>,
+        Read<'d, InteractionMatrix>,
+        Read<'d, Gravity>,
+        Read<'d, Exclusions>,
+    );
+
+    fn run(
+        &self,
+        (masses, positions, species, groups, mut accels, matrix, gravity, exclusions): Self::SystemData,
+    ) {
+        let (bodies, groups): (Vec<(Mass, Position, Species)>, Vec<u32>) =
+            (&masses, &positions, &species, &groups)
+                .join()
+                .map(|(m, p, s, g)| ((*m, *p, *s), g.0))
+                .unzip();
+        let sources: Vec<usize> = (0..bodies.len())
+            .filter(|&i| bodies[i].0.mass != 0.0)
+            .collect();
+        let (matrix, gravity) = (matrix.deref(), gravity.deref());
+        let excluded = |i: usize, j: usize| exclusions.excludes(groups[i], groups[j]);
+
+        let blocks = current_num_threads().clamp(1, bodies.len().max(1));
+        let sums = if blocks == 1 {
+            self.block(&bodies, &sources, (0, 1), &excluded, matrix, gravity)
+        } else {
+            let partial: Vec<Vec<ForceSum>> = (0..blocks)
+                .into_par_iter()
+                .map(|block| {
+                    self.block(
+                        &bodies,
+                        &sources,
+                        (block, blocks),
+                        &excluded,
+                        matrix,
+                        gravity,
+                    )
+                })
+                .collect();
+            let mut partial = partial.into_iter();
+            let mut sums = partial.next().unwrap();
+            for other in partial {
+                for (sum, other) in sums.iter_mut().zip(&other) {
+                    sum.merge(other);
+                }
+            }
+            sums
+        };
+
+        for ((accel, _), sum) in (&mut accels, &positions).join().zip(&sums) {
+            let (x, y) = sum.value();
+            accel.x = x;
+            accel.y = y;
+        }
+    }
+}
+
+// Running total of acceleration contributions, optionally with Kahan
+// compensation: the low-order bits each addition rounds away are kept and fed
+// into the next one.
+#[derive(Copy, Clone)]
+pub struct ForceSum {
+    compensated: bool,
+    sum: (f32, f32),
+    error: (f32, f32),
+}
+
+impl ForceSum {
+    pub fn new(compensated: bool) -> Self {
+        ForceSum::starting_at((0.0, 0.0), compensated)
+    }
+
+    pub fn starting_at(sum: (f32, f32), compensated: bool) -> Self {
+        ForceSum {
+            compensated,
+            sum,
+            error: (0.0, 0.0),
+        }
+    }
+
+    pub fn add(&mut self, (x, y): (f32, f32)) {
+        if !self.compensated {
+            self.sum.0 += x;
+            self.sum.1 += y;
+            return;
+        }
+        let (x, y) = (x - self.error.0, y - self.error.1);
+        let total = (self.sum.0 + x, self.sum.1 + y);
+        self.error = ((total.0 - self.sum.0) - x, (total.1 - self.sum.1) - y);
+        self.sum = total;
+    }
+
+    // Adds in another running total, along with what it has compensated for.
+    pub fn merge(&mut self, other: &ForceSum) {
+        self.add(other.sum);
+        if self.compensated {
+            self.add((-other.error.0, -other.error.1));
+        }
+    }
+
+    pub fn value(&self) -> (f32, f32) {
+        self.sum
+    }
+}
+
+// Acceleration of body one due to body two; zero when they overlap.
+pub fn pull(
+    pos_one: &Position,
+    species_one: &Species,
+    mass_two: &Mass,
+    pos_two: &Position,
+    species_two: &Species,
+    matrix: &InteractionMatrix,
+    gravity: &Gravity,
+) -> (f32, f32) {
+    if overlapping(pos_one, pos_two, gravity.epsilon) {
+        return (0.0, 0.0);
+    }
+
+    let dist_x = pos_two.x - pos_one.x;
+    let dist_y = pos_two.y - pos_one.y;
+    let r2 = dist_x * dist_x + dist_y * dist_y;
+
+    // a = G * m2 / r^p along the unit vector towards the other body, softened
+    let coefficient = matrix.get(species_one.id, species_two.id);
+    let magnitude = coefficient * gravity.g * mass_two.mass * gravity.factor(r2);
+    (magnitude * dist_x, magnitude * dist_y)
+}
+
+// The accelerations of two bodies towards each other, `pull` both ways from a
+// single separation; opposite and, with a symmetric interaction matrix, in
+// proportion to the other body's mass.
+pub fn pull_pair(
+    (mass_one, pos_one, species_one): &(Mass, Position, Species),
+    (mass_two, pos_two, species_two): &(Mass, Position, Species),
+    matrix: &InteractionMatrix,
+    gravity: &Gravity,
+) -> ((f32, f32), (f32, f32)) {
+    if overlapping(pos_one, pos_two, gravity.epsilon) {
+        return ((0.0, 0.0), (0.0, 0.0));
+    }
+
+    let dist_x = pos_two.x - pos_one.x;
+    let dist_y = pos_two.y - pos_one.y;
+    let r2 = dist_x * dist_x + dist_y * dist_y;
+    let scale = gravity.g * gravity.factor(r2);
+    let on_one = matrix.get(species_one.id, species_two.id) * mass_two.mass * scale;
+    let on_two = matrix.get(species_two.id, species_one.id) * mass_one.mass * scale;
+    (
+        (on_one * dist_x, on_one * dist_y),
+        (-on_two * dist_x, -on_two * dist_y),
+    )
+}
+
+// Adds the 1PN correction to the acceleration each body feels from every other,
+// in the test-particle limit (e.g. Kluzniak & Lee 2002):
+//
+//   a += G m2 / (c^2 r^3) * ((4 G m2 / r - v^2) r + 4 (r . v) v)
+//
+// with r and v the position and velocity relative to the other body. This
+// reproduces the periapsis advance of 6 pi G M / (c^2 a (1 - e^2)) per orbit.
+// Interaction matrix coefficients are not applied, and with the Hermite
+// integrator the jerk of the correction is neglected. Excluded pairs get no
+// correction either.
+pub struct PostNewtonian;
+impl<'d, 'w: 'd> System<'d, 'w, World> for PostNewtonian {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        ReadComp<'d, Group>,
+        WriteComp<'d, Acceleration>,
+        Read<'d, Gravity>,
+        Read<'d, Relativity>,
+        Read<'d, Exclusions>,
+    );
+
+    fn run(
+        &self,
+        (masses, positions, vels, groups, mut accels, gravity, relativity, exclusions): Self::SystemData,
+    ) {
+        let bodies: Vec<(f32, Position, Velocity, u32)> = (&masses, &positions, &vels, &groups)
+            .join()
+            .map(|(m, p, v, g)| (m.mass, *p, *v, g.0))
+            .collect();
+        let c2 = relativity.c * relativity.c;
+
+        for (i, (accel, _)) in (&mut accels, &positions).join().enumerate() {
+            let (_, pos_one, vel_one, group_one) = bodies[i];
+            for (j, (mass_two, pos_two, vel_two, group_two)) in bodies.iter().enumerate() {
+                if i == j
+                    || *mass_two == 0.0
+                    || overlapping(&pos_one, pos_two, gravity.epsilon)
+                    || exclusions.excludes(group_one, *group_two)
+                {
+                    continue;
+                }
+                let (rx, ry) = (pos_one.x - pos_two.x, pos_one.y - pos_two.y);
+                let (vx, vy) = (vel_one.x - vel_two.x, vel_one.y - vel_two.y);
+                let r = distance(&pos_one, pos_two);
+                let gm = gravity.g * mass_two;
+                let radial = 4.0 * gm / r - (vx * vx + vy * vy);
+                let rv = 4.0 * (rx * vx + ry * vy);
+                let scale = gm / (c2 * r * r * r);
+
+                accel.x += scale * (radial * rx + rv * vx);
+                accel.y += scale * (radial * ry + rv * vy);
+            }
+        }
+    }
+}
+
+// Adds the pull of the background `Halo`. For the Hermite integrator its
+// contribution to the jerk is neglected.
+pub struct HaloForce;
+impl<'d, 'w: 'd> System<'d, 'w, World> for HaloForce {
+    type SystemData = (
+        ReadComp<'d, Position>,
+        WriteComp<'d, Acceleration>,
+        Read<'d, Halo>,
+        Read<'d, Gravity>,
+    );
+
+    fn run(&self, (positions, mut accels, halo, gravity): Self::SystemData) {
+        for (pos, accel) in (&positions, &mut accels).join() {
+            let (dx, dy) = (halo.centre.0 - pos.x, halo.centre.1 - pos.y);
+            let r = (dx * dx + dy * dy).sqrt();
+            if r <= gravity.epsilon {
+                continue;
+            }
+            let magnitude = gravity.g * halo.enclosed_mass(r) / (r * r * r);
+            accel.x += magnitude * dx;
+            accel.y += magnitude * dy;
+        }
+    }
+}
+
+// Chandrasekhar dynamical friction on bodies of at least `mass_threshold`
+// moving through the lighter background. The background surface density,
+// mean velocity and velocity dispersion are measured from the light bodies
+// within `radius` of each tracer, and the drag is that of a thin sheet of
+// field bodies rather than of a volume of them:
+//
+//   a = -4 G Sigma arctan(Lambda) F(X) v / |v|
+//   F(X) = sqrt(pi)/2 X e^(-X^2/2) [I0(X^2/2) + I1(X^2/2)]
+//
+// with v relative to the local background, X = v / (sqrt(2) sigma) and
+// ln(Lambda) the Coulomb logarithm. This is the derivation of Binney &
+// Tremaine (2008, eq. 8.7) with the encounters summed over a line of impact
+// parameters instead of a plane: each stream of field bodies slows the tracer
+// by 4 G Sigma arctan(Lambda) whatever its speed, and F averages the direction
+// of that pull over the two-dimensional Maxwellian, so unlike in 3-D the
+// faster field bodies contribute too.
+#[derive(Copy, Clone)]
+pub struct DynamicalFriction {
+    pub mass_threshold: f32,
+    pub radius: f32,
+    pub coulomb_log: f32,
+}
+
+// e^-y [I0(y) + I1(y)] for y >= 0, from Abramowitz & Stegun 9.8.1-9.8.4,
+// accurate to 1e-7.
+fn scaled_bessel_sum(y: f32) -> f32 {
+    if y < 3.75 {
+        let t = (y / 3.75) * (y / 3.75);
+        let i0 = 1.0
+            + t * (3.515_623
+                + t * (3.089_942_4
+                    + t * (1.206_749_2 + t * (0.265_973_2 + t * (0.036_076_8 + t * 0.004_581_3)))));
+        let i1 = y
+            * (0.5
+                + t * (0.878_905_9
+                    + t * (0.514_988_7
+                        + t * (0.150_849_34
+                            + t * (0.026_587_33 + t * (0.003_015_32 + t * 0.000_324_11))))));
+        (-y).exp() * (i0 + i1)
+    } else {
+        let t = 3.75 / y;
+        let i0 = 0.398_942_3
+            + t * (0.013_285_92
+                + t * (0.002_253_19
+                    + t * (-0.001_575_65
+                        + t * (0.009_162_81
+                            + t * (-0.020_577_06
+                                + t * (0.026_355_37 + t * (-0.016_476_33 + t * 0.003_923_77)))))));
+        let i1 = 0.398_942_3
+            + t * (-0.039_880_24
+                + t * (-0.003_620_18
+                    + t * (0.001_638_01
+                        + t * (-0.010_315_55
+                            + t * (0.022_829_67
+                                + t * (-0.028_953_12 + t * (0.017_876_54 - t * 0.004_200_59)))))));
+        (i0 + i1) / y.sqrt()
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for DynamicalFriction {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        WriteComp<'d, Acceleration>,
+        Read<'d, Gravity>,
+    );
+
+    fn run(&self, (masses, positions, vels, mut accels, gravity): Self::SystemData) {
+        let background: Vec<(f32, Position, Velocity)> = (&masses, &positions, &vels)
+            .join()
+            .filter(|(m, _, _)| m.mass < self.mass_threshold)
+            .map(|(m, p, v)| (m.mass, *p, *v))
+            .collect();
+        if background.is_empty() {
+            return;
+        }
+        let points: Vec<(f32, f32)> = background.iter().map(|b| (b.1.x, b.1.y)).collect();
+        let cells = CellList::build(&points, self.radius);
+        let area = std::f32::consts::PI * self.radius * self.radius;
+        let coulomb = self.coulomb_log.exp().atan();
+
+        for (mass, pos, vel, accel) in (&masses, &positions, &vels, &mut accels).join() {
+            if mass.mass < self.mass_threshold {
+                continue;
+            }
+
+            let (mut total, mut mvx, mut mvy, mut mv2) = (0.0, 0.0, 0.0, 0.0);
+            for j in cells.nearby((pos.x, pos.y)) {
+                let (m, p, v) = &background[j];
+                if distance(pos, p) < self.radius {
+                    total += m;
+                    mvx += m * v.x;
+                    mvy += m * v.y;
+                    mv2 += m * (v.x * v.x + v.y * v.y);
+                }
+            }
+            if total <= 0.0 {
+                continue;
+            }
+            let (mean_x, mean_y) = (mvx / total, mvy / total);
+            // one-dimensional dispersion from the two in-plane components
+            let sigma2 = ((mv2 / total - mean_x * mean_x - mean_y * mean_y) / 2.0).max(0.0);
+
+            let (vx, vy) = (vel.x - mean_x, vel.y - mean_y);
+            let v = (vx * vx + vy * vy).sqrt();
+            if v <= 0.0 {
+                continue;
+            }
+            let x = if sigma2 > 0.0 {
+                v / (2.0 * sigma2).sqrt()
+            } else {
+                f32::INFINITY
+            };
+            // F(X) = 1 - 1/(4 X^2) + ..., so a cold background pulls in full
+            let fraction = if x < 1.0e3 {
+                std::f32::consts::PI.sqrt() / 2.0 * x * scaled_bessel_sum(x * x / 2.0)
+            } else {
+                1.0
+            };
+
+            let sigma = total / area;
+            let drag = 4.0 * gravity.g * sigma * coulomb * fraction / v;
+            accel.x -= drag * vx;
+            accel.y -= drag * vy;
+        }
+    }
+}
+
+// Rebuilds the `NeighborGrid` from the current positions.
+pub struct BuildNeighborGrid;
+impl<'d, 'w: 'd> System<'d, 'w, World> for BuildNeighborGrid {
+    type SystemData = (
+        ReadComp<'d, Position>,
+        Read<'d, EntityStorage>,
+        Write<'d, NeighborGrid>,
+    );
+
+    fn run(&self, (positions, ents, mut grid): Self::SystemData) {
+        grid.rebuild(
+            (&positions, ents.deref())
+                .join()
+                .map(|(p, _)| (p.x, p.y))
+                .collect(),
+        );
+    }
+}
+
+// Turns every spinning body through its rate over the tick, keeping the angle
+// within [-pi, pi).
+pub struct RotateBodies;
+impl<'d, 'w: 'd> System<'d, 'w, World> for RotateBodies {
+    type SystemData = (Read<'d, EntityStorage>, Read<'d, Time>, WriteComp<'d, Spin>);
+
+    fn run(&self, (ents, time, mut spins): Self::SystemData) {
+        use std::f32::consts::{PI, TAU};
+        let dt = time.delta as f32;
+        for (spin, _) in (&mut spins, ents.deref()).join() {
+            spin.angle = (spin.angle + spin.rate * dt + PI).rem_euclid(TAU) - PI;
+        }
+    }
+}
+
+// Mass evolution of the bodies at least `mass_threshold` massive (and not
+// tracers): each tick they lose a fraction of their mass at `wind_rate` per
+// unit time, to a wind that leaves isotropically and so carries no momentum
+// (a negative rate makes them grow instead), and absorb every lighter body
+// within `accretion_radius`, which is removed with its mass and momentum
+// added to the nearest one. Forces and energies use `Mass`, so they follow
+// the change from the next evaluation on.
+#[derive(Copy, Clone)]
+pub struct MassEvolution {
+    pub mass_threshold: f32,
+    pub wind_rate: f32,
+    pub accretion_radius: f32,
+}
+
+impl MassEvolution {
+    fn accretes(&self, mass: f32) -> bool {
+        mass > 0.0 && mass >= self.mass_threshold
+    }
+
+    // Applies the wind loss and merges each light body within reach into the
+    // nearest heavy one, returning the bodies merged away with the `BodyId`s
+    // of their host and of themselves.
+    fn capture(
+        &self,
+        ents: &EntityStorage,
+        ((_, masses, _, _, ids), (_, _, vels, positions, _), ..): &mut BodyStorages,
+        time: &Time,
+    ) -> Vec<(Entity, BodyId, BodyId)> {
+        let kept = (-self.wind_rate as f64 * time.delta).exp() as f32;
+        for (mass, _) in (&mut *masses, ents).join() {
+            if self.accretes(mass.mass) {
+                mass.mass *= kept;
+            }
+        }
+        if self.accretion_radius <= 0.0 {
+            return Vec::new();
+        }
+
+        let heavy: Vec<(Entity, Position)> = (&*masses, &*positions, ents)
+            .join()
+            .filter(|(m, _, _)| self.accretes(m.mass))
+            .map(|(_, p, ent)| (*ent, *p))
+            .collect();
+        if heavy.is_empty() {
+            return Vec::new();
+        }
+        let points: Vec<(f32, f32)> = heavy.iter().map(|(_, p)| (p.x, p.y)).collect();
+        let cells = CellList::build(&points, self.accretion_radius);
+
+        // each light body within reach goes to the nearest heavy one
+        let mut captured: Vec<(Entity, Entity, f32, Velocity)> = Vec::new();
+        for (mass, pos, vel, ent) in (&*masses, &*positions, &*vels, ents).join() {
+            if self.accretes(mass.mass) {
+                continue;
+            }
+            let nearest = cells
+                .nearby((pos.x, pos.y))
+                .map(|j| (j, distance(pos, &heavy[j].1)))
+                .filter(|(_, d)| *d < self.accretion_radius)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((j, _)) = nearest {
+                captured.push((*ent, heavy[j].0, mass.mass, *vel));
+            }
+        }
+
+        let mut merged = Vec::new();
+        for (light, host, mass, velocity) in captured {
+            let (host_id, light_id) = match (ids.get(&host), ids.get(&light)) {
+                (Some(host_id), Some(light_id)) => (*host_id, *light_id),
+                _ => continue,
+            };
+            let own = match masses.get_mut(&host) {
+                Some(m) => {
+                    let own = m.mass;
+                    m.mass += mass;
+                    own
+                }
+                None => continue,
+            };
+            if let Some(vel) = vels.get_mut(&host) {
+                let total = own + mass;
+                vel.x = (own * vel.x + mass * velocity.x) / total;
+                vel.y = (own * vel.y + mass * velocity.y) / total;
+            }
+            merged.push((light, host_id, light_id));
+        }
+        merged
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for MassEvolution {
+    type SystemData = (
+        Write<'d, EntityStorage>,
+        BodyStorages<'d>,
+        Read<'d, Time>,
+        Write<'d, EventLog>,
+    );
+
+    fn run(&self, (mut ents, mut storages, time, mut log): Self::SystemData) {
+        let captured = self.capture(&ents, &mut storages, &time);
+        for (light, host, absorbed) in captured {
+            log.record(
+                &time,
+                Event::Merge {
+                    host: host.0,
+                    absorbed: absorbed.0,
+                },
+            );
+            despawn(&mut storages, &mut ents, &light);
+        }
+    }
+}
+
+// Sets each body's `Density` from the mass of its `neighbors` nearest
+// neighbours in the `NeighborGrid`, so from positions at the start of the tick.
+pub struct EstimateDensity {
+    pub neighbors: usize,
+}
+impl<'d, 'w: 'd> System<'d, 'w, World> for EstimateDensity {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        Read<'d, EntityStorage>,
+        Read<'d, NeighborGrid>,
+        WriteComp<'d, Density>,
+    );
+
+    fn run(&self, (masses, positions, ents, grid, mut densities): Self::SystemData) {
+        let masses: Vec<f32> = (&masses, ents.deref())
+            .join()
+            .map(|(m, _)| m.mass)
+            .collect();
+        let estimates: Vec<f32> = (0..grid.len())
+            .into_par_iter()
+            .map(|i| grid.density(i, &masses, self.neighbors))
+            .collect();
+        // the grid holds the positions in join order
+        for ((_, ent), rho) in (&positions, ents.deref()).join().zip(estimates) {
+            densities.set(ent, Density { rho });
+        }
+    }
+}
+
+// Multiple-timestepping variant of `ApplyGravity`: pairs closer than `radius`
+// are summed every tick from the `NeighborGrid`, while the far field is recomputed
+// only every `interval` ticks and reused in between. Pairs that cross `radius`
+// between far-field updates are double counted or missed until the next one,
+// which is the accuracy traded for speed. The cache follows join order and is
+// refreshed whenever the number of bodies changes. `compensated` is as for
+// `ApplyGravity`, for both the near and far sums.
+pub struct SplitGravity {
+    pub interval: u64,
+    pub radius: f32,
+    pub compensated: bool,
+    far: std::sync::Mutex<Vec<(f32, f32)>>,
+}
+
+impl SplitGravity {
+    pub fn new(interval: u64, radius: f32) -> Self {
+        SplitGravity {
+            interval,
+            radius,
+            compensated: false,
+            far: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for SplitGravity {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        (ReadComp<'d, Species>, ReadComp<'d, Group>),
+        WriteComp<'d, Acceleration>,
+        Read<'d, InteractionMatrix>,
+        (Read<'d, Gravity>, Read<'d, Exclusions>),
+        Read<'d, Time>,
+        Read<'d, NeighborGrid>,
+    );
+
+    fn run(
+        &self,
+        (
+            masses,
+            positions,
+            (species, groups),
+            mut accels,
+            matrix,
+            (gravity, exclusions),
+            time,
+            grid,
+        ): Self::SystemData,
+    ) {
+        let (bodies, groups): (Vec<(Mass, Position, Species)>, Vec<u32>) =
+            (&masses, &positions, &species, &groups)
+                .join()
+                .map(|(m, p, s, g)| ((*m, *p, *s), g.0))
+                .unzip();
+        let near = |one: &Position, two: &Position| distance(one, two) < self.radius;
+        let excluded = |i: usize, j: usize| exclusions.excludes(groups[i], groups[j]);
+
+        // tracers exert no force
+        let sources: Vec<usize> = (0..bodies.len())
+            .filter(|&j| bodies[j].0.mass != 0.0)
+            .collect();
+
+        let mut far = self.far.lock().unwrap();
+        if far.len() != bodies.len() || time.tick % self.interval == 0 {
+            far.clear();
+            for (i, (_, pos_one, species_one)) in bodies.iter().enumerate() {
+                let mut sum = ForceSum::new(self.compensated);
+                for &j in &sources {
+                    let (mass_two, pos_two, species_two) = &bodies[j];
+                    if i == j || near(pos_one, pos_two) || excluded(i, j) {
+                        continue;
+                    }
+                    sum.add(pull(
+                        pos_one,
+                        species_one,
+                        mass_two,
+                        pos_two,
+                        species_two,
+                        &matrix,
+                        &gravity,
+                    ));
+                }
+                far.push(sum.value());
+            }
+        }
+
+        for (i, (accel, _)) in (&mut accels, &positions).join().enumerate() {
+            let (_, pos_one, species_one) = &bodies[i];
+            let mut sum = ForceSum::starting_at(far[i], self.compensated);
+            for j in grid.neighbors_within((pos_one.x, pos_one.y), self.radius) {
+                let (mass_two, pos_two, species_two) = &bodies[j];
+                if i == j || mass_two.mass == 0.0 || excluded(i, j) {
+                    continue;
+                }
+                sum.add(pull(
+                    pos_one,
+                    species_one,
+                    mass_two,
+                    pos_two,
+                    species_two,
+                    &matrix,
+                    &gravity,
+                ));
+            }
+            let (x, y) = sum.value();
+            accel.x = x;
+            accel.y = y;
+        }
+    }
+}
+
+pub struct ApplyAccelerations;
+impl<'d, 'w: 'd> System<'d, 'w, World> for ApplyAccelerations {
+    type SystemData = (
+        ReadComp<'d, Acceleration>,
+        Read<'d, Time>,
+        WriteComp<'d, Velocity>,
+    );
+
+    fn run(&self, (accels, time, mut vels): Self::SystemData) {
+        for (accel, vel) in (&accels, &mut vels).join() {
+            vel.x += accel.x * time.delta as f32;
+            vel.y += accel.y * time.delta as f32;
+        }
+    }
+}
+
+// Half of `ApplyAccelerations`, for the kicks either side of the leapfrog
+// drift.
+pub struct HalfKick;
+impl<'d, 'w: 'd> System<'d, 'w, World> for HalfKick {
+    type SystemData = (
+        ReadComp<'d, Acceleration>,
+        Read<'d, Time>,
+        WriteComp<'d, Velocity>,
+    );
+
+    fn run(&self, (accels, time, mut vels): Self::SystemData) {
+        let half = 0.5 * time.delta as f32;
+        for (accel, vel) in (&accels, &mut vels).join() {
+            vel.x += accel.x * half;
+            vel.y += accel.y * half;
+        }
+    }
+}
+
+// Keeps each body's position from before the tick, attaching `PreviousPosition`
+// to bodies that don't have one yet.
+pub struct StorePreviousPositions;
+impl<'d, 'w: 'd> System<'d, 'w, World> for StorePreviousPositions {
+    type SystemData = (
+        ReadComp<'d, Position>,
+        WriteComp<'d, PreviousPosition>,
+        Read<'d, EntityStorage>,
+    );
+
+    fn run(&self, (positions, mut previous, ents): Self::SystemData) {
+        for (pos, ent) in (&positions, ents.deref()).join() {
+            previous.set(ent, PreviousPosition { x: pos.x, y: pos.y });
+        }
+    }
+}
+
+pub struct ApplyVelocities;
+impl<'d, 'w: 'd> System<'d, 'w, World> for ApplyVelocities {
+    type SystemData = (
+        ReadComp<'d, Velocity>,
+        Read<'d, Time>,
+        WriteComp<'d, Position>,
+    );
+
+    fn run(&self, (vels, time, mut positions): Self::SystemData) {
+        for (vel, position) in (&vels, &mut positions).join() {
+            position.x += vel.x * time.delta as f32;
+            position.y += vel.y * time.delta as f32;
+        }
+    }
+}
+
+// Fourth-order Hermite predictor-corrector (Makino & Aarseth 1992), run as
+// predict -> HermiteForces -> correct in place of the Euler systems. The
+// acceleration and jerk from the previous tick are the start-of-step values.
+//
+// Predicts positions and velocities from the Taylor series in acceleration and
+// jerk, keeping the start-of-step state for the corrector. Bodies spawned since
+// the last tick get a zero `Jerk` here.
+pub struct HermitePredict;
+impl<'d, 'w: 'd> System<'d, 'w, World> for HermitePredict {
+    type SystemData = (
+        WriteComp<'d, Position>,
+        WriteComp<'d, Velocity>,
+        ReadComp<'d, Acceleration>,
+        WriteComp<'d, Jerk>,
+        Write<'d, HermiteStart>,
+        Read<'d, Time>,
+        Read<'d, EntityStorage>,
+    );
+
+    fn run(
+        &self,
+        (mut positions, mut vels, accels, mut jerks, mut start, time, ents): Self::SystemData,
+    ) {
+        let with_jerk: std::collections::HashSet<Entity> =
+            (&jerks, ents.deref()).join().map(|(_, ent)| *ent).collect();
+        let missing: Vec<Entity> = (&accels, ents.deref())
+            .join()
+            .map(|(_, ent)| *ent)
+            .filter(|ent| !with_jerk.contains(ent))
+            .collect();
+        for ent in missing {
+            jerks.set(&ent, Jerk { x: 0.0, y: 0.0 });
+        }
+
+        let dt = time.delta as f32;
+        start.state.clear();
+        for (pos, vel, accel, jerk) in (&mut positions, &mut vels, &accels, &jerks).join() {
+            start.state.push((*pos, *vel, *accel, *jerk));
+            pos.x += vel.x * dt + accel.x * dt * dt / 2.0 + jerk.x * dt * dt * dt / 6.0;
+            pos.y += vel.y * dt + accel.y * dt * dt / 2.0 + jerk.y * dt * dt * dt / 6.0;
+            vel.x += accel.x * dt + jerk.x * dt * dt / 2.0;
+            vel.y += accel.y * dt + jerk.y * dt * dt / 2.0;
+        }
+    }
+}
+
+// Acceleration and jerk at the current positions and velocities.
+pub struct HermiteForces;
+impl<'d, 'w: 'd> System<'d, 'w, World> for HermiteForces {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        (ReadComp<'d, Species>, ReadComp<'d, Group>),
+        WriteComp<'d, Acceleration>,
+        WriteComp<'d, Jerk>,
+        Read<'d, InteractionMatrix>,
+        (Read<'d, Gravity>, Read<'d, Exclusions>),
+    );
+
+    fn run(
+        &self,
+        (
+            masses,
+            positions,
+            vels,
+            (species, groups),
+            mut accels,
+            mut jerks,
+            matrix,
+            (gravity, exclusions),
+        ): Self::SystemData,
+    ) {
+        let bodies: Vec<(f32, Position, Velocity, usize, u32)> =
+            (&masses, &positions, &vels, &species, &groups)
+                .join()
+                .map(|(m, p, v, s, g)| (m.mass, *p, *v, s.id, g.0))
+                .collect();
+        // tracers exert no force
+        let sources: Vec<usize> = (0..bodies.len()).filter(|&j| bodies[j].0 != 0.0).collect();
+
+        for (i, (accel, jerk)) in (&mut accels, &mut jerks).join().enumerate() {
+            let (_, pos_one, vel_one, species_one, group_one) = bodies[i];
+            *accel = Acceleration { x: 0.0, y: 0.0 };
+            *jerk = Jerk { x: 0.0, y: 0.0 };
+            for &j in &sources {
+                let (mass_two, pos_two, vel_two, species_two, group_two) = &bodies[j];
+                if i == j
+                    || overlapping(&pos_one, pos_two, gravity.epsilon)
+                    || exclusions.excludes(group_one, *group_two)
+                {
+                    continue;
+                }
+                let (rx, ry) = (pos_two.x - pos_one.x, pos_two.y - pos_one.y);
+                let (vx, vy) = (vel_two.x - vel_one.x, vel_two.y - vel_one.y);
+                let (f, slope) = gravity.factor_and_slope(rx * rx + ry * ry);
+                let k = matrix.get(species_one, *species_two) * gravity.g * mass_two;
+                let rv = slope * (rx * vx + ry * vy);
+
+                accel.x += k * f * rx;
+                accel.y += k * f * ry;
+                jerk.x += k * (f * vx + rv * rx);
+                jerk.y += k * (f * vy + rv * ry);
+            }
+        }
+    }
+}
+
+// Start-of-step state in join order, kept by `HermitePredict` for
+// `HermiteCorrect`.
+#[derive(Default)]
+pub struct HermiteStart {
+    pub state: Vec<(Position, Velocity, Acceleration, Jerk)>,
+}
+
+// Corrects the predicted state using the start and end-of-step acceleration and
+// jerk.
+pub struct HermiteCorrect;
+impl<'d, 'w: 'd> System<'d, 'w, World> for HermiteCorrect {
+    type SystemData = (
+        WriteComp<'d, Position>,
+        WriteComp<'d, Velocity>,
+        ReadComp<'d, Acceleration>,
+        ReadComp<'d, Jerk>,
+        Read<'d, HermiteStart>,
+        Read<'d, Time>,
+    );
+
+    fn run(&self, (mut positions, mut vels, accels, jerks, start, time): Self::SystemData) {
+        let dt = time.delta as f32;
+        for ((pos, vel, a1, j1), (x0, v0, a0, j0)) in (&mut positions, &mut vels, &accels, &jerks)
+            .join()
+            .zip(start.state.iter())
+        {
+            vel.x = v0.x + (a0.x + a1.x) * dt / 2.0 + (j0.x - j1.x) * dt * dt / 12.0;
+            vel.y = v0.y + (a0.y + a1.y) * dt / 2.0 + (j0.y - j1.y) * dt * dt / 12.0;
+            pos.x = x0.x + (v0.x + vel.x) * dt / 2.0 + (a0.x - a1.x) * dt * dt / 12.0;
+            pos.y = x0.y + (v0.y + vel.y) * dt / 2.0 + (a0.y - a1.y) * dt * dt / 12.0;
+        }
+    }
+}
+
+// Drift step that resolves close encounters: pairs closer than `radius` at the
+// start of the tick have their mutual kick from `ApplyAccelerations` taken back
+// and are instead integrated over the tick in M kick-drift substeps of their
+// own interaction, with M growing as (radius / r)^1.5 up to `max_substeps`.
+// Bodies linked by close pairs are substepped together; everything else
+// drifts once as in `ApplyVelocities`.
+#[derive(Copy, Clone)]
+pub struct SubstepDrift {
+    pub radius: f32,
+    pub max_substeps: u32,
+}
+
+pub(crate) fn group_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for SubstepDrift {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Species>,
+        ReadComp<'d, Group>,
+        WriteComp<'d, Position>,
+        WriteComp<'d, Velocity>,
+        Read<'d, InteractionMatrix>,
+        (Read<'d, Gravity>, Read<'d, Exclusions>),
+        Read<'d, Time>,
+    );
+
+    fn run(
+        &self,
+        (
+            masses,
+            species,
+            groups,
+            mut positions,
+            mut vels,
+            matrix,
+            (gravity, exclusions),
+            time,
+        ): Self::SystemData,
+    ) {
+        let dt = time.delta as f32;
+        let mut bodies: Vec<(Mass, Species, Position, Velocity)> =
+            (&masses, &species, &positions, &vels)
+                .join()
+                .map(|(m, s, p, v)| (*m, *s, *p, *v))
+                .collect();
+        let groups: Vec<u32> = (&groups, &positions).join().map(|(g, _)| g.0).collect();
+
+        let points: Vec<(f32, f32)> = bodies.iter().map(|b| (b.2.x, b.2.y)).collect();
+        let cells = CellList::build(&points, self.radius);
+        let mut pairs = Vec::new();
+        let mut parents: Vec<usize> = (0..bodies.len()).collect();
+        let mut substeps = vec![1u32; bodies.len()];
+        for (i, point) in points.iter().enumerate() {
+            for j in cells.nearby(*point).filter(|&j| j > i) {
+                let r = distance(&bodies[i].2, &bodies[j].2);
+                if r >= self.radius || exclusions.excludes(groups[i], groups[j]) {
+                    continue;
+                }
+                pairs.push((i, j));
+                let wanted = ((self.radius / r.max(f32::MIN_POSITIVE)).powf(1.5).ceil() as u32)
+                    .min(self.max_substeps)
+                    .max(1);
+                let (a, b) = (group_root(&mut parents, i), group_root(&mut parents, j));
+                parents[a] = b;
+                substeps[b] = substeps[b].max(substeps[a]).max(wanted);
+            }
+        }
+
+        let mutual = |bodies: &[(Mass, Species, Position, Velocity)], i: usize, j: usize| {
+            let (one, two) = (&bodies[i], &bodies[j]);
+            (
+                pull(&one.2, &one.1, &two.0, &two.2, &two.1, &matrix, &gravity),
+                pull(&two.2, &two.1, &one.0, &one.2, &one.1, &matrix, &gravity),
+            )
+        };
+        let kick = |bodies: &mut [(Mass, Species, Position, Velocity)],
+                    pairs: &[(usize, usize)],
+                    h: f32| {
+            for &(i, j) in pairs {
+                let (on_i, on_j) = mutual(bodies, i, j);
+                bodies[i].3.x += on_i.0 * h;
+                bodies[i].3.y += on_i.1 * h;
+                bodies[j].3.x += on_j.0 * h;
+                bodies[j].3.y += on_j.1 * h;
+            }
+        };
+
+        // undo the single full-tick kick the close pairs already got
+        kick(&mut bodies, &pairs, -dt);
+
+        let mut groups: std::collections::HashMap<usize, Vec<usize>> =
+            std::collections::HashMap::new();
+        for i in 0..bodies.len() {
+            let root = group_root(&mut parents, i);
+            groups.entry(root).or_default().push(i);
+        }
+        for (root, members) in groups {
+            let steps = substeps[root];
+            let h = dt / steps as f32;
+            let group_pairs: Vec<(usize, usize)> = pairs
+                .iter()
+                .cloned()
+                .filter(|&(i, _)| group_root(&mut parents, i) == root)
+                .collect();
+            for _ in 0..steps {
+                kick(&mut bodies, &group_pairs, h);
+                for &i in &members {
+                    let vel = bodies[i].3;
+                    bodies[i].2.x += vel.x * h;
+                    bodies[i].2.y += vel.y * h;
+                }
+            }
+        }
+
+        for ((pos, vel), body) in (&mut positions, &mut vels).join().zip(bodies) {
+            *pos = body.2;
+            *vel = body.3;
+        }
+    }
+}
+
+// Splits a body into fragments when the tidal pull of a neighbour across the
+// body exceeds `threshold` times its own surface gravity. Fragments share the
+// parent's mass equally and get a random velocity kick with zero mean, so
+// mass and momentum are conserved, and they keep its species and group. Kicks
+// are drawn from the `RandomState`.
+pub struct TidalDisruption {
+    pub threshold: f32,
+    pub fragments: usize,
+    pub dispersion: f32,
+    pub radius_scale: f32,
+    pub min_mass: f32,
+}
+
+impl TidalDisruption {
+    fn radius(&self, mass: f32) -> f32 {
+        self.radius_scale * mass.cbrt()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Fragment {
+    mass: f32,
+    position: Position,
+    velocity: Velocity,
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for TidalDisruption {
+    type SystemData = (
+        Write<'d, EntityStorage>,
+        (
+            WriteComp<'d, Body>,
+            WriteComp<'d, Group>,
+            WriteComp<'d, Spin>,
+            WriteComp<'d, BodyId>,
+            WriteComp<'d, Jerk>,
+        ),
+        WriteComp<'d, Mass>,
+        WriteComp<'d, Species>,
+        WriteComp<'d, Acceleration>,
+        WriteComp<'d, Velocity>,
+        WriteComp<'d, Position>,
+        (
+            Read<'d, Gravity>,
+            Write<'d, RandomState>,
+            Write<'d, BodyIds>,
+        ),
+    );
+
+    fn run(
+        &self,
+        (
+            mut ents,
+            (mut bodies, mut groups, mut spins, mut ids, mut jerks),
+            mut masses,
+            mut species,
+            mut accels,
+            mut vels,
+            mut positions,
+            (gravity, mut random, mut issued),
+        ): Self::SystemData,
+    ) {
+        let state: Vec<(f32, Position, Velocity)> = (&masses, &positions, &vels)
+            .join()
+            .map(|(mass, pos, vel)| (mass.mass, *pos, *vel))
+            .collect();
+
+        let rng = &mut random.rng;
+        let mut splits: Vec<Option<Vec<Fragment>>> = vec![None; state.len()];
+        for (i, (mass, pos, vel)) in state.iter().enumerate() {
+            let fragment_mass = mass / self.fragments as f32;
+            if fragment_mass < self.min_mass {
+                continue;
+            }
+
+            let radius = self.radius(*mass);
+            let self_gravity = gravity.g * mass / radius.powf(2.0);
+            let disrupted = state.iter().enumerate().any(|(j, (mass_two, pos_two, _))| {
+                let d = distance(pos, pos_two);
+                j != i
+                    && d > radius
+                    && 2.0 * gravity.g * mass_two * radius / d.powf(3.0)
+                        > self.threshold * self_gravity
+            });
+            if !disrupted {
+                continue;
+            }
+
+            // gen_range needs a non-empty range, so no dispersion means no kick
+            let kicks: Vec<(f32, f32)> = (0..self.fragments)
+                .map(|_| {
+                    if self.dispersion > 0.0 {
+                        (
+                            rng.gen_range(-self.dispersion, self.dispersion),
+                            rng.gen_range(-self.dispersion, self.dispersion),
+                        )
+                    } else {
+                        (0.0, 0.0)
+                    }
+                })
+                .collect();
+            let mean_x = kicks.iter().map(|k| k.0).sum::<f32>() / self.fragments as f32;
+            let mean_y = kicks.iter().map(|k| k.1).sum::<f32>() / self.fragments as f32;
+
+            let fragments = kicks
+                .iter()
+                .enumerate()
+                .map(|(k, kick)| {
+                    let angle = 2.0 * std::f32::consts::PI * k as f32 / self.fragments as f32;
+                    Fragment {
+                        mass: fragment_mass,
+                        position: Position {
+                            x: pos.x + radius * angle.cos(),
+                            y: pos.y + radius * angle.sin(),
+                        },
+                        velocity: Velocity {
+                            x: vel.x + kick.0 - mean_x,
+                            y: vel.y + kick.1 - mean_y,
+                        },
+                    }
+                })
+                .collect();
+            splits[i] = Some(fragments);
+        }
+
+        // The parent becomes the first fragment, the rest are spawned below
+        // with its group and spin.
+        let mut spawned = Vec::new();
+        for ((mass, pos, vel, kind, group, ent), split) in (
+            &mut masses,
+            &mut positions,
+            &mut vels,
+            &species,
+            &groups,
+            ents.deref(),
+        )
+            .join()
+            .zip(splits)
+        {
+            if let Some(fragments) = split {
+                mass.mass = fragments[0].mass;
+                *pos = fragments[0].position;
+                *vel = fragments[0].velocity;
+                let spin = spins.get(ent).copied();
+                spawned.extend(fragments[1..].iter().map(|f| (*f, *kind, *group, spin)));
+            }
+        }
+
+        for (fragment, kind, group, spin) in spawned {
+            let entity = ents
+                .create_entity()
+                .add(&mut bodies, Body {})
+                .add(&mut groups, group)
+                .add(&mut ids, issued.issue())
+                .add(
+                    &mut masses,
+                    Mass {
+                        mass: fragment.mass,
+                    },
+                )
+                .add(&mut species, kind)
+                .add(&mut accels, Acceleration { x: 0.0, y: 0.0 })
+                .add(&mut jerks, Jerk { x: 0.0, y: 0.0 })
+                .add(&mut vels, fragment.velocity)
+                .add(&mut positions, fragment.position);
+            if let Some(spin) = spin {
+                entity.add(&mut spins, spin);
+            }
+        }
+    }
+}
+
+// Every `interval` ticks, reorders body state along a Z-order curve over the
+// bounding box so that bodies near each other in space are near each other in
+// storage. Every component and the recorded history are moved between
+// entities together, so a body keeps its `BodyId`, tags and group; only state
+// kept in join order outside the components, such as the far-field cache of
+// `SplitGravity`, goes stale.
+pub struct SortBodies {
+    pub interval: u64,
+}
+
+// Gives the entity at `slots[i]` the `T`, or lack of one, of the entity at
+// `slots[order[i]]`.
+fn permute<T: Copy>(storage: &mut WriteComp<T>, slots: &[Entity], order: &[usize]) {
+    let moved: Vec<Option<T>> = order
+        .iter()
+        .map(|&from| storage.get(&slots[from]).copied())
+        .collect();
+    for (ent, value) in slots.iter().zip(moved) {
+        match value {
+            Some(value) => storage.set(ent, value),
+            None => {
+                storage.remove(ent);
+            }
+        }
+    }
+}
+
+impl<'d, 'w: 'd> System<'d, 'w, World> for SortBodies {
+    type SystemData = (Read<'d, EntityStorage>, BodyStorages<'d>, Read<'d, Time>);
+
+    fn run(&self, (ents, mut storages, time): Self::SystemData) {
+        if time.tick % self.interval != 0 {
+            return;
+        }
+
+        let (_, (_, _, _, positions, _), ..) = &storages;
+        let (slots, points): (Vec<Entity>, Vec<Position>) = (positions, ents.deref())
+            .join()
+            .map(|(pos, ent)| (*ent, *pos))
+            .unzip();
+        if slots.len() < 2 {
+            return;
+        }
+
+        let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+        let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for pos in &points {
+            min_x = min_x.min(pos.x);
+            min_y = min_y.min(pos.y);
+            max_x = max_x.max(pos.x);
+            max_y = max_y.max(pos.y);
+        }
+        let scale_x = u16::MAX as f32 / (max_x - min_x).max(f32::MIN_POSITIVE);
+        let scale_y = u16::MAX as f32 / (max_y - min_y).max(f32::MIN_POSITIVE);
+        let mut order: Vec<usize> = (0..slots.len()).collect();
+        order.sort_by_key(|&i| {
+            morton(
+                ((points[i].x - min_x) * scale_x) as u16,
+                ((points[i].y - min_y) * scale_y) as u16,
+            )
+        });
+
+        let (
+            (bodies, masses, species, groups, ids),
+            (accels, jerks, vels, positions, previous),
+            (escaped, densities, clumps, primaries, orbiters, spins),
+            history,
+        ) = &mut storages;
+        permute(bodies, &slots, &order);
+        permute(masses, &slots, &order);
+        permute(species, &slots, &order);
+        permute(groups, &slots, &order);
+        permute(ids, &slots, &order);
+        permute(accels, &slots, &order);
+        permute(jerks, &slots, &order);
+        permute(vels, &slots, &order);
+        permute(positions, &slots, &order);
+        permute(previous, &slots, &order);
+        permute(escaped, &slots, &order);
+        permute(densities, &slots, &order);
+        permute(clumps, &slots, &order);
+        permute(primaries, &slots, &order);
+        permute(orbiters, &slots, &order);
+        permute(spins, &slots, &order);
+        history.permute(&slots, &order);
+    }
+}
+
+// Tags bodies that are moving away from the centre of mass of everything else
+// faster than that mass's escape velocity.
+pub struct DetectEscapes;
+impl<'d, 'w: 'd> System<'d, 'w, World> for DetectEscapes {
+    type SystemData = (
+        ReadComp<'d, Mass>,
+        ReadComp<'d, Position>,
+        ReadComp<'d, Velocity>,
+        (ReadComp<'d, BodyId>, WriteComp<'d, Escaped>),
+        Read<'d, Gravity>,
+        Read<'d, EntityStorage>,
+        Read<'d, Time>,
+        Write<'d, EventLog>,
+    );
+
+    fn run(
+        &self,
+        (masses, positions, vels, (ids, mut escaped), gravity, ents, time, mut log): Self::SystemData,
+    ) {
+        let (mut total, mut mx, mut my, mut mvx, mut mvy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (mass, pos, vel) in (&masses, &positions, &vels).join() {
+            total += mass.mass;
+            mx += mass.mass * pos.x;
+            my += mass.mass * pos.y;
+            mvx += mass.mass * vel.x;
+            mvy += mass.mass * vel.y;
+        }
+
+        let already: std::collections::HashSet<Entity> = (&escaped, ents.deref())
+            .join()
+            .map(|(_, ent)| *ent)
+            .collect();
+        let mut escapees = Vec::new();
+        for (mass, pos, vel, id, ent) in (&masses, &positions, &vels, &ids, ents.deref()).join() {
+            // centre of mass and velocity of the rest of the system
+            let rest = total - mass.mass;
+            if rest <= 0.0 {
+                continue;
+            }
+            let (cx, cy) = (
+                (mx - mass.mass * pos.x) / rest,
+                (my - mass.mass * pos.y) / rest,
+            );
+            let (cvx, cvy) = (
+                (mvx - mass.mass * vel.x) / rest,
+                (mvy - mass.mass * vel.y) / rest,
+            );
+
+            let (rx, ry) = (pos.x - cx, pos.y - cy);
+            let (vx, vy) = (vel.x - cvx, vel.y - cvy);
+            let r = (rx * rx + ry * ry).sqrt();
+            let receding = rx * vx + ry * vy > 0.0;
+            if receding && r > 0.0 && vx * vx + vy * vy > 2.0 * gravity.g * rest / r {
+                if !already.contains(ent) {
+                    log.record(&time, Event::Escape { body: id.0 });
+                }
+                escapees.push(*ent);
+            }
+        }
+
+        for ent in escapees {
+            escaped.set(&ent, Escaped {});
+        }
+    }
+}
+
+// Deletes escaped bodies so they stop contributing to forces and statistics.
+pub struct RemoveEscaped;
+impl<'d, 'w: 'd> System<'d, 'w, World> for RemoveEscaped {
+    type SystemData = (Write<'d, EntityStorage>, BodyStorages<'d>);
+
+    fn run(&self, (mut ents, mut storages): Self::SystemData) {
+        let (_, _, (escaped, ..), _) = &storages;
+        let removed: Vec<Entity> = (escaped, ents.deref())
+            .join()
+            .map(|(_, ent)| *ent)
+            .collect();
+
+        for ent in removed {
+            despawn(&mut storages, &mut ents, &ent);
+        }
+    }
+}
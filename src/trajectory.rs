@@ -0,0 +1,242 @@
+// Sequences of snapshots in every format `convert` reads and writes, picked by
+// extension:
+//
+//   .csv, .csv.gz, .csv.zst, .parquet  one frame per file, as `snapshot::write`
+//   .chk                               a checkpoint, read only
+//   .json, .jsonl                      a JSON object per frame and line, with
+//                                      tick, time and an array of bodies
+//   .bin                               magic "SNBT", version u32, then per
+//                                      frame tick u64, time f64 and the bodies
+//                                      as in a checkpoint
+//   .h5, .hdf5                         with the `hdf5` feature, see `h5`
+use crate::api::parse_body;
+use crate::checkpoint::{self, Checkpoint};
+use crate::snapshot::{self, Snapshot};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"SNBT";
+const VERSION: u32 = 2;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Format {
+    Csv,
+    Checkpoint,
+    Json,
+    Binary,
+    Hdf5,
+}
+
+impl Format {
+    pub fn of<P: AsRef<Path>>(path: P) -> Format {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("chk") => Format::Checkpoint,
+            Some("json") | Some("jsonl") => Format::Json,
+            Some("bin") => Format::Binary,
+            Some("h5") | Some("hdf5") => Format::Hdf5,
+            // CSV, compressed or not, and Parquet
+            _ => Format::Csv,
+        }
+    }
+
+    // Whether a file holds a single frame.
+    fn single(self) -> bool {
+        matches!(self, Format::Csv | Format::Checkpoint)
+    }
+}
+
+fn invalid(path: &Path, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), message),
+    )
+}
+
+// Every frame in `path`, in order.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<Snapshot>> {
+    let path = path.as_ref();
+    match Format::of(path) {
+        Format::Csv => Ok(vec![snapshot::read(path)?]),
+        Format::Checkpoint => {
+            let checkpoint = Checkpoint::read(path)?;
+            Ok(vec![Snapshot {
+                tick: checkpoint.tick,
+                time: checkpoint.time,
+                bodies: checkpoint.bodies,
+            }])
+        }
+        Format::Json => {
+            let mut frames = Vec::new();
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    frames.push(parse_frame(&line).map_err(|e| invalid(path, e))?);
+                }
+            }
+            Ok(frames)
+        }
+        Format::Binary => read_binary(path),
+        #[cfg(feature = "hdf5")]
+        Format::Hdf5 => crate::h5::read(path),
+        #[cfg(not(feature = "hdf5"))]
+        Format::Hdf5 => Err(io::Error::other(format!(
+            "{} needs a build with the \"hdf5\" feature",
+            path.display()
+        ))),
+    }
+}
+
+// Writes `frames` to `path` and returns the files written. Formats of one frame
+// per file get a file per frame named `<stem>_<tick>` as in a `SnapshotSeries`,
+// unless there is only one.
+pub fn write<P: AsRef<Path>>(path: P, frames: &[Snapshot]) -> io::Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let format = Format::of(path);
+    if format.single() {
+        if format == Format::Checkpoint {
+            return Err(io::Error::other("checkpoints can only be written by a run"));
+        }
+        if let [frame] = frames {
+            snapshot::write(path, frame.tick, frame.time, &frame.bodies)?;
+            return Ok(vec![path.to_path_buf()]);
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let (stem, extension) = name.split_once('.').unwrap_or((&name, "csv"));
+        let mut written = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let file = path.with_file_name(format!("{}_{:06}.{}", stem, frame.tick, extension));
+            snapshot::write(&file, frame.tick, frame.time, &frame.bodies)?;
+            written.push(file);
+        }
+        return Ok(written);
+    }
+
+    match format {
+        Format::Json => {
+            let mut out = BufWriter::new(File::create(path)?);
+            for frame in frames {
+                writeln!(out, "{}", frame_json(frame))?;
+            }
+            out.flush()?;
+        }
+        Format::Binary => {
+            let mut out = BufWriter::new(File::create(path)?);
+            out.write_all(MAGIC)?;
+            out.write_all(&VERSION.to_le_bytes())?;
+            for frame in frames {
+                out.write_all(&frame.tick.to_le_bytes())?;
+                out.write_all(&frame.time.to_le_bytes())?;
+                checkpoint::write_bodies(&mut out, &frame.bodies)?;
+            }
+            out.flush()?;
+        }
+        #[cfg(feature = "hdf5")]
+        Format::Hdf5 => crate::h5::write(path, frames)?,
+        #[cfg(not(feature = "hdf5"))]
+        Format::Hdf5 => {
+            return Err(io::Error::other(format!(
+                "{} needs a build with the \"hdf5\" feature",
+                path.display()
+            )))
+        }
+        Format::Csv | Format::Checkpoint => unreachable!(),
+    }
+    Ok(vec![path.to_path_buf()])
+}
+
+// Keeps every `frames`-th frame, and of those every `bodies`-th body, starting
+// with the first of each.
+pub fn downsample(frames: Vec<Snapshot>, every_frame: usize, every_body: usize) -> Vec<Snapshot> {
+    frames
+        .into_iter()
+        .step_by(every_frame.max(1))
+        .map(|frame| Snapshot {
+            bodies: frame
+                .bodies
+                .into_iter()
+                .step_by(every_body.max(1))
+                .collect(),
+            ..frame
+        })
+        .collect()
+}
+
+fn read_binary(path: &Path) -> io::Result<Vec<Snapshot>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid(path, "not a binary trajectory".to_string()));
+    }
+    let version = checkpoint::read_u32(&mut reader)?;
+    if version != 1 && version != VERSION {
+        return Err(invalid(
+            path,
+            format!("unsupported trajectory version {}", version),
+        ));
+    }
+
+    let mut frames = Vec::new();
+    while !reader.fill_buf()?.is_empty() {
+        frames.push(Snapshot {
+            tick: checkpoint::read_u64(&mut reader)?,
+            time: checkpoint::read_f64(&mut reader)?,
+            bodies: checkpoint::read_bodies(&mut reader, version >= 2)?,
+        });
+    }
+    Ok(frames)
+}
+
+fn frame_json(frame: &Snapshot) -> String {
+    let bodies: Vec<String> = frame
+        .bodies
+        .iter()
+        .map(|b| {
+            format!(
+                "{{\"mass\":{},\"species\":{},\"group\":{},\"x\":{},\"y\":{},\"vx\":{},\"vy\":{}}}",
+                b.mass, b.species, b.group, b.position.x, b.position.y, b.velocity.x, b.velocity.y
+            )
+        })
+        .collect();
+    format!(
+        "{{\"tick\":{},\"time\":{},\"bodies\":[{}]}}",
+        frame.tick,
+        frame.time,
+        bodies.join(",")
+    )
+}
+
+// The inverse of `frame_json`, for frames on a single line.
+fn parse_frame(line: &str) -> Result<Snapshot, String> {
+    let (header, rest) = line
+        .trim()
+        .strip_prefix('{')
+        .and_then(|t| t.split_once("\"bodies\":["))
+        .ok_or("expected a frame object with a bodies array")?;
+    let mut frame = Snapshot {
+        tick: 0,
+        time: 0.0,
+        bodies: Vec::new(),
+    };
+    for field in header.split(',').filter(|f| !f.trim().is_empty()) {
+        let (key, value) = field.split_once(':').ok_or("expected key:value")?;
+        let value = value.trim();
+        match key.trim().trim_matches('"') {
+            "tick" => frame.tick = value.parse().map_err(|_| "bad tick")?,
+            "time" => frame.time = value.parse().map_err(|_| "bad time")?,
+            key => return Err(format!("unknown field '{}'", key)),
+        }
+    }
+    let list = rest
+        .trim_end()
+        .strip_suffix("]}")
+        .ok_or("unterminated bodies array")?;
+    for object in list.split('}') {
+        let object = object.trim().trim_start_matches(',').trim();
+        if !object.is_empty() {
+            frame.bodies.push(parse_body(&format!("{}}}", object))?);
+        }
+    }
+    Ok(frame)
+}
@@ -0,0 +1,717 @@
+// Long runs of scenarios with known analytic behaviour. Each uses a timestep
+// that divides the orbital period evenly, so after a whole number of periods
+// every body should be back where it started, or have precessed by a known
+// amount.
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use smolnbody::analysis::FriendsOfFriends;
+use smolnbody::checkpoint::Checkpoint;
+use smolnbody::components::{Acceleration, Mass, Position, Spin, Velocity};
+use smolnbody::diff::StateDiff;
+use smolnbody::orbits;
+use smolnbody::resources::{Gravity, Softening, Time};
+use smolnbody::scenarios::{self, BodyState, Scenario, FIGURE_EIGHT_PERIOD};
+use smolnbody::systems::{DynamicalFriction, MassEvolution, TidalDisruption};
+use smolnbody::{Integrator, Simulation, SimulationBuilder, Stage};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use SmolECS::{component::*, system::*, world::*};
+
+fn simulation(scenario: Scenario, integrator: Integrator) -> Simulation {
+    SimulationBuilder::new(scenario.bodies)
+        .gravity(Gravity {
+            g: scenario.gravity,
+            epsilon: 0.0,
+            softening: Softening::None,
+            exponent: 2.0,
+        })
+        .timestep(Some(scenario.dt))
+        .integrator(integrator)
+        .threads(1)
+        .build()
+}
+
+// Runs for `periods` orbits, checking the relative energy error every
+// `check_every` ticks, and returns the largest position and velocity
+// deviation from the initial state at the end.
+fn run_periods(
+    scenario: Scenario,
+    integrator: Integrator,
+    ticks_per_period: u64,
+    periods: u64,
+    max_energy_error: f32,
+) -> (f32, f32) {
+    let mut sim = simulation(scenario, integrator);
+    let initial = sim.bodies();
+    let initial_energy = sim.energy();
+
+    for period in 0..periods {
+        for _ in 0..ticks_per_period / 10 {
+            sim.run(10);
+            let error = ((sim.energy() - initial_energy) / initial_energy).abs();
+            assert!(
+                error < max_energy_error,
+                "relative energy error {} in period {} exceeds {}",
+                error,
+                period,
+                max_energy_error
+            );
+        }
+    }
+
+    let mut position_error: f32 = 0.0;
+    let mut velocity_error: f32 = 0.0;
+    for (start, end) in initial.iter().zip(sim.bodies().iter()) {
+        position_error = position_error
+            .max((end.position.x - start.position.x).abs())
+            .max((end.position.y - start.position.y).abs());
+        velocity_error = velocity_error
+            .max((end.velocity.x - start.velocity.x).abs())
+            .max((end.velocity.y - start.velocity.y).abs());
+    }
+    (position_error, velocity_error)
+}
+
+#[test]
+fn two_body_circular_orbit() {
+    let scenario = scenarios::two_body();
+    let ticks_per_period = (2.0 * std::f64::consts::PI / scenario.dt).round() as u64;
+    let (position_error, velocity_error) =
+        run_periods(scenario, Integrator::Euler, ticks_per_period, 20, 1.0e-4);
+
+    assert!(position_error < 5.0e-3, "position error {}", position_error);
+    assert!(velocity_error < 5.0e-3, "velocity error {}", velocity_error);
+}
+
+#[test]
+fn figure_eight_choreography() {
+    let scenario = scenarios::figure_eight();
+    let ticks_per_period = (FIGURE_EIGHT_PERIOD / scenario.dt).round() as u64;
+    let (position_error, velocity_error) =
+        run_periods(scenario, Integrator::Euler, ticks_per_period, 10, 2.0e-3);
+
+    assert!(position_error < 1.0e-2, "position error {}", position_error);
+    assert!(velocity_error < 1.0e-2, "velocity error {}", velocity_error);
+}
+
+// Hermite at ten times the preset timestep should still be far more accurate
+// than Euler at the preset one.
+#[test]
+fn figure_eight_hermite() {
+    let mut scenario = scenarios::figure_eight();
+    scenario.dt *= 10.0;
+    let ticks_per_period = (FIGURE_EIGHT_PERIOD / scenario.dt).round() as u64;
+    let (position_error, velocity_error) =
+        run_periods(scenario, Integrator::Hermite4, ticks_per_period, 10, 5.0e-5);
+
+    assert!(position_error < 1.0e-3, "position error {}", position_error);
+    assert!(velocity_error < 1.0e-3, "velocity error {}", velocity_error);
+}
+
+// Leapfrog run forward for a period and back again should retrace its steps,
+// up to f32 rounding.
+#[test]
+fn figure_eight_leapfrog_reverses() {
+    let scenario = scenarios::figure_eight();
+    let ticks_per_period = (FIGURE_EIGHT_PERIOD / scenario.dt).round() as u64;
+    let mut sim = simulation(scenario, Integrator::Leapfrog);
+    let report = sim.reverse_test(ticks_per_period);
+
+    let (body, error) = report.max().unwrap();
+    assert!(error < 1.0e-4, "body {} is {} from its start", body, error);
+}
+
+// Tracers added to the figure eight follow it without disturbing it: the three
+// bodies move exactly as they do on their own.
+#[test]
+fn tracers_exert_no_force() {
+    let scenario = scenarios::figure_eight();
+    let mut bodies = scenario.bodies.clone();
+    bodies.extend(scenarios::tracers(
+        50,
+        &scenario.bodies,
+        &mut ChaCha8Rng::seed_from_u64(7),
+    ));
+    let mut alone = simulation(scenarios::figure_eight(), Integrator::Euler);
+    let mut traced = simulation(
+        Scenario {
+            bodies,
+            ..scenarios::figure_eight()
+        },
+        Integrator::Euler,
+    );
+    alone.run(1000);
+    traced.run(1000);
+
+    for (one, two) in alone.bodies().iter().zip(&traced.bodies()[..3]) {
+        assert_eq!(one.position.x, two.position.x);
+        assert_eq!(one.position.y, two.position.y);
+    }
+}
+
+// The figure eight with softened gravity and every body torn into fragments
+// over the first few ticks, each split drawing random kicks.
+fn fragmenting(builder: SimulationBuilder) -> Simulation {
+    builder
+        .softening(Softening::Plummer(0.05))
+        .tidal_disruption(TidalDisruption {
+            threshold: 1.0e-5,
+            fragments: 3,
+            dispersion: 0.1,
+            radius_scale: 0.05,
+            min_mass: 0.1,
+        })
+        .threads(1)
+        .build()
+}
+
+// A run stopped at a checkpoint and resumed from it ends bit-identical to one
+// that ran straight through, random fragment kicks included.
+#[test]
+fn resumed_run_matches_uninterrupted() {
+    let start = || {
+        let scenario = scenarios::figure_eight();
+        SimulationBuilder::new(scenario.bodies)
+            .gravity(Gravity {
+                g: scenario.gravity,
+                epsilon: 0.0,
+                softening: Softening::None,
+                exponent: 2.0,
+            })
+            .timestep(Some(scenario.dt))
+            .seed(11)
+    };
+    let mut straight = fragmenting(start());
+    straight.run(200);
+
+    let mut first = fragmenting(start());
+    first.run(1);
+    let path = std::env::temp_dir().join(format!("smolnbody_resume_{}.chk", std::process::id()));
+    first.checkpoint().write(&path).unwrap();
+    let checkpoint = Checkpoint::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut resumed = fragmenting(SimulationBuilder::from_checkpoint(checkpoint));
+    resumed.run(199);
+
+    let (one, two) = (straight.bodies(), resumed.bodies());
+    assert!(one.len() > 3, "no body was disrupted");
+    assert_eq!(one.len(), two.len());
+    assert_eq!(straight.tick(), resumed.tick());
+    for (a, b) in one.iter().zip(&two) {
+        assert_eq!(a.mass, b.mass);
+        assert_eq!(a.group, b.group);
+        assert_eq!(
+            (a.position.x, a.position.y, a.velocity.x, a.velocity.y),
+            (b.position.x, b.position.y, b.velocity.x, b.velocity.y)
+        );
+    }
+}
+
+// The history holds the last few ticks of each body, ending at its current
+// state.
+#[test]
+fn body_history_ring_buffer() {
+    let mut sim = SimulationBuilder::new(scenarios::figure_eight().bodies)
+        .timestep(Some(0.001))
+        .history(5)
+        .threads(1)
+        .build();
+    assert_eq!(sim.body_history(0).len(), 1);
+    sim.run(10);
+
+    let history = sim.body_history(1);
+    let ticks: Vec<u64> = history.iter().map(|sample| sample.tick).collect();
+    assert_eq!(ticks, vec![6, 7, 8, 9, 10]);
+    let last = history[4].state;
+    let now = sim.bodies()[1];
+    assert_eq!(
+        (
+            last.position.x,
+            last.position.y,
+            last.velocity.x,
+            last.velocity.y
+        ),
+        (
+            now.position.x,
+            now.position.y,
+            now.velocity.x,
+            now.velocity.y
+        )
+    );
+    assert!(sim.body_history(3).is_empty());
+}
+
+// Two bodies at rest in an excluded group feel nothing from each other and
+// stay where they are, while a pair left alone falls together.
+#[test]
+fn excluded_groups_exert_no_force() {
+    let pair = |group: u32| {
+        let bodies = (0..2)
+            .map(|i| BodyState {
+                mass: 1.0,
+                species: 0,
+                group,
+                position: Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                velocity: Velocity { x: 0.0, y: 0.0 },
+            })
+            .collect();
+        SimulationBuilder::new(bodies)
+            .gravity(Gravity {
+                g: 1.0,
+                epsilon: 0.0,
+                softening: Softening::None,
+                exponent: 2.0,
+            })
+            .timestep(Some(0.001))
+            .exclude(1, 1)
+            .threads(1)
+            .build()
+    };
+    let (mut sim, mut free) = (pair(1), pair(2));
+    assert_eq!(sim.energy(), 0.0);
+    sim.run(100);
+    free.run(100);
+
+    for (body, x) in sim.bodies().iter().zip([0.0, 1.0]) {
+        assert_eq!((body.position.x, body.velocity.x), (x, 0.0));
+    }
+    assert!(free.bodies()[0].velocity.x > 0.0);
+}
+
+// A 9x9 square lattice of unit masses at rest, with its corner at (`x0`, 0).
+fn lattice(spacing: f32, x0: f32) -> impl Iterator<Item = BodyState> {
+    (0..81).map(move |i| BodyState {
+        mass: 1.0,
+        species: 0,
+        group: 0,
+        position: Position {
+            x: x0 + spacing * (i % 9) as f32,
+            y: spacing * (i / 9) as f32,
+        },
+        velocity: Velocity { x: 0.0, y: 0.0 },
+    })
+}
+
+// The nearest eight neighbours of a body inside a square lattice of spacing `a`
+// are four at `a` and four at `a`√2, so its estimated density is 8m / 2πa².
+#[test]
+fn lattice_densities() {
+    let bodies: Vec<BodyState> = lattice(1.0, 0.0).chain(lattice(0.5, 100.0)).collect();
+    let mut sim = SimulationBuilder::new(bodies)
+        .timestep(Some(0.001))
+        .density_estimate(8)
+        .threads(1)
+        .build();
+    sim.step();
+
+    // the centre of each lattice
+    let densities = sim.densities();
+    for (rho, spacing) in [(densities[40], 1.0f32), (densities[81 + 40], 0.5)] {
+        let expected = 8.0 / (2.0 * std::f32::consts::PI * spacing * spacing);
+        assert!(
+            ((rho - expected) / expected).abs() < 1e-4,
+            "density {} for spacing {}, expected {}",
+            rho,
+            spacing,
+            expected
+        );
+    }
+}
+
+// A heavy body crossing the centre of a lattice at rest is slowed by a thin
+// cold sheet, 4 G Sigma arctan(Lambda), on top of the lattice's gravity. The
+// 20 lattice bodies within 2.5 of the centre set Sigma.
+#[test]
+fn dynamical_friction_of_a_cold_sheet() {
+    let run = |friction: bool| {
+        let mut bodies: Vec<BodyState> = lattice(1.0, 0.0).collect();
+        bodies[40].mass = 10.0;
+        bodies[40].velocity = Velocity { x: 1.0, y: 0.0 };
+        let mut builder = SimulationBuilder::new(bodies)
+            .gravity(Gravity {
+                g: 1.0,
+                epsilon: 0.0,
+                softening: Softening::None,
+                exponent: 2.0,
+            })
+            .timestep(Some(0.001))
+            .threads(1);
+        if friction {
+            builder = builder.dynamical_friction(DynamicalFriction {
+                mass_threshold: 5.0,
+                radius: 2.5,
+                coulomb_log: 3.0,
+            });
+        }
+        let mut sim = builder.build();
+        sim.step();
+        sim.bodies()[40].velocity
+    };
+    let (with, without) = (run(true), run(false));
+
+    let sigma = 20.0 / (std::f32::consts::PI * 2.5 * 2.5);
+    let expected = 4.0 * sigma * 3.0f32.exp().atan();
+    let drag = (without.x - with.x) / 0.001;
+    assert!(
+        ((drag - expected) / expected).abs() < 1e-3,
+        "drag {}, expected {}",
+        drag,
+        expected
+    );
+    assert!((with.y - without.y).abs() < 1e-9);
+}
+
+// With a linking length between the two spacings only the denser lattice is
+// linked, into a single clump.
+#[test]
+fn friends_of_friends_clumps() {
+    let bodies: Vec<BodyState> = lattice(1.0, 0.0).chain(lattice(0.5, 100.0)).collect();
+    let mut sim = SimulationBuilder::new(bodies)
+        .timestep(Some(0.001))
+        .clump_finder(FriendsOfFriends {
+            interval: 1,
+            linking_length: 0.75,
+            min_members: 10,
+        })
+        .threads(1)
+        .build();
+    sim.step();
+
+    let catalog = sim.clumps();
+    assert_eq!(catalog.clumps.len(), 1);
+    let clump = &catalog.clumps[0];
+    assert_eq!(clump.members, 81);
+    assert!((clump.mass - 81.0).abs() < 1e-3);
+    assert!((clump.center.0 - 102.0).abs() < 1e-2 && (clump.center.1 - 2.0).abs() < 1e-2);
+}
+
+// Trojans seeded near L4 and L5 librate about them on tadpole orbits rather
+// than drifting around onto the secondary.
+#[test]
+fn trojans_librate_about_l4_and_l5() {
+    let scenario = scenarios::restricted_three_body(scenarios::RESTRICTED_MASS_RATIO, 4);
+    let ticks_per_period = (2.0 * std::f64::consts::PI / scenario.dt).round() as u64;
+    let mut sim = simulation(scenario, Integrator::Euler);
+
+    for _ in 0..30 * ticks_per_period / 100 {
+        sim.run(100);
+        let state = sim.bodies();
+        let (sx, sy) = (
+            state[1].position.x - state[0].position.x,
+            state[1].position.y - state[0].position.y,
+        );
+        for (i, trojan) in state[2..].iter().enumerate() {
+            let (px, py) = (trojan.position.x, trojan.position.y);
+            let angle = (sx * py - sy * px).atan2(sx * px + sy * py).to_degrees();
+            // even particles start at L4, leading the secondary
+            let leading = if i % 2 == 0 { angle } else { -angle };
+            assert!(
+                leading > 10.0 && leading < 170.0,
+                "trojan {} is {} degrees from the secondary",
+                i,
+                angle
+            );
+        }
+    }
+}
+
+// A test body on an e = 0.5 orbit about a unit mass, with the speed of light
+// turned down so that general relativity advances periapsis by about 0.01 rad
+// per orbit.
+#[test]
+fn relativistic_periapsis_precession() {
+    let (a, e, c) = (1.0_f32, 0.5_f32, 50.0_f32);
+    let ticks_per_period = 2000;
+    let orbits = 20;
+    let periapsis = a * (1.0 - e);
+    let speed = ((1.0 + e) / periapsis).sqrt();
+    let bodies = vec![
+        BodyState {
+            mass: 1.0,
+            species: 0,
+            group: 0,
+            position: Position { x: 0.0, y: 0.0 },
+            velocity: Velocity { x: 0.0, y: 0.0 },
+        },
+        BodyState {
+            mass: 1.0e-6,
+            species: 0,
+            group: 0,
+            position: Position {
+                x: periapsis,
+                y: 0.0,
+            },
+            velocity: Velocity { x: 0.0, y: speed },
+        },
+    ];
+    let mut sim = SimulationBuilder::new(bodies)
+        .gravity(Gravity {
+            g: 1.0,
+            epsilon: 0.0,
+            softening: Softening::None,
+            exponent: 2.0,
+        })
+        .timestep(Some(2.0 * std::f64::consts::PI / ticks_per_period as f64))
+        .relativistic(c)
+        .threads(1)
+        .build();
+    sim.run(ticks_per_period * orbits);
+
+    let state = sim.bodies();
+    let (sun, planet) = (&state[0], &state[1]);
+    let elements = orbits::from_state(
+        [
+            (planet.position.x - sun.position.x) as f64,
+            (planet.position.y - sun.position.y) as f64,
+        ],
+        [
+            (planet.velocity.x - sun.velocity.x) as f64,
+            (planet.velocity.y - sun.velocity.y) as f64,
+        ],
+        1.0 + 1.0e-6,
+    )
+    .expect("orbit became unbound");
+
+    let expected = orbits as f64 * 6.0 * std::f64::consts::PI
+        / ((c * c) as f64 * a as f64 * (1.0 - (e * e) as f64));
+    let advance = elements.argument_of_periapsis;
+    assert!(
+        (advance - expected).abs() < 0.05 * expected,
+        "periapsis advanced {} rad, expected {}",
+        advance,
+        expected
+    );
+}
+
+// A uniform field added as a user force system on top of gravity.
+struct UniformField;
+impl<'d, 'w: 'd> System<'d, 'w, World> for UniformField {
+    type SystemData = (ReadComp<'d, Mass>, WriteComp<'d, Acceleration>);
+
+    fn run(&self, (masses, mut accels): Self::SystemData) {
+        for (_, accel) in (&masses, &mut accels).join() {
+            accel.y -= 1.0;
+        }
+    }
+}
+
+// Semi-implicit Euler is exact for the velocity under a constant acceleration.
+#[test]
+fn user_force_system() {
+    let bodies = vec![BodyState {
+        mass: 1.0,
+        species: 0,
+        group: 0,
+        position: Position { x: 0.0, y: 0.0 },
+        velocity: Velocity { x: 0.0, y: 0.0 },
+    }];
+    let mut sim = SimulationBuilder::new(bodies)
+        .timestep(Some(1.0e-3))
+        .add_system_at(Stage::Force, UniformField {}, "uniform_field", vec![])
+        .threads(1)
+        .build();
+    sim.run(1000);
+
+    let velocity = sim.bodies()[0].velocity;
+    assert!(velocity.x.abs() < 1.0e-6, "velocity x {}", velocity.x);
+    assert!(
+        (velocity.y + 1.0).abs() < 1.0e-4,
+        "velocity y {}",
+        velocity.y
+    );
+}
+
+// Counts the ticks it runs in.
+struct Tally {
+    ticks: Arc<AtomicU64>,
+}
+impl<'d, 'w: 'd> System<'d, 'w, World> for Tally {
+    type SystemData = Read<'d, Time>;
+
+    fn run(&self, _: Self::SystemData) {
+        self.ticks.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Checks that the tallies it waits for have run once more than it has.
+struct AfterTallies {
+    before: Vec<Arc<AtomicU64>>,
+    ticks: AtomicU64,
+    ordered: Arc<AtomicBool>,
+}
+impl<'d, 'w: 'd> System<'d, 'w, World> for AfterTallies {
+    type SystemData = Read<'d, Time>;
+
+    fn run(&self, _: Self::SystemData) {
+        let ticks = self.ticks.fetch_add(1, Ordering::SeqCst) + 1;
+        if self
+            .before
+            .iter()
+            .any(|t| t.load(Ordering::SeqCst) != ticks)
+        {
+            self.ordered.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+// Two sets running side by side after integration and a third waiting for
+// both of them.
+#[test]
+fn system_sets_wait_for_earlier_sets() {
+    let analysis = Arc::new(AtomicU64::new(0));
+    let output = Arc::new(AtomicU64::new(0));
+    let ordered = Arc::new(AtomicBool::new(true));
+    let report = AfterTallies {
+        before: vec![analysis.clone(), output.clone()],
+        ticks: AtomicU64::new(0),
+        ordered: ordered.clone(),
+    };
+    let mut sim = SimulationBuilder::new(scenarios::figure_eight().bodies)
+        .timestep(Some(1.0e-3))
+        .system_set("analysis", Stage::PostIntegrate, vec![])
+        .system_set("output", Stage::PostIntegrate, vec![])
+        .system_set("report", Stage::PostStep, vec!["analysis", "output"])
+        .add_system_to("report", report, "check_order")
+        .add_system_to(
+            "analysis",
+            Tally {
+                ticks: analysis.clone(),
+            },
+            "count_analysis",
+        )
+        .add_system_to(
+            "output",
+            Tally {
+                ticks: output.clone(),
+            },
+            "count_output",
+        )
+        .threads(4)
+        .build();
+    sim.run(100);
+
+    assert_eq!(analysis.load(Ordering::SeqCst), 100);
+    assert_eq!(output.load(Ordering::SeqCst), 100);
+    assert!(
+        ordered.load(Ordering::SeqCst),
+        "a set ran before one it waits for"
+    );
+}
+
+// A torque-free body turns at a constant rate, and keeps turning after a
+// checkpoint round trip.
+#[test]
+fn spin_survives_checkpoint() {
+    let bodies = vec![BodyState {
+        mass: 1.0,
+        species: 0,
+        group: 0,
+        position: Position { x: 0.0, y: 0.0 },
+        velocity: Velocity { x: 0.0, y: 0.0 },
+    }];
+    let mut sim = SimulationBuilder::new(bodies)
+        .timestep(Some(1.0e-3))
+        .spin(
+            0,
+            Spin {
+                angle: 0.0,
+                rate: 1.0,
+            },
+        )
+        .threads(1)
+        .build();
+    sim.run(1000);
+    let spin = sim.spins()[0].expect("body lost its spin");
+    assert!((spin.angle - 1.0).abs() < 1.0e-3, "angle {}", spin.angle);
+
+    let path = std::env::temp_dir().join(format!("smolnbody_spin_{}.chk", std::process::id()));
+    sim.checkpoint().write(&path).unwrap();
+    let checkpoint = Checkpoint::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut resumed = SimulationBuilder::from_checkpoint(checkpoint)
+        .threads(1)
+        .build();
+    resumed.run(3000);
+    // four radians, wrapped into [-pi, pi)
+    let spin = resumed.spins()[0].expect("resumed body lost its spin");
+    let expected = 4.0 - 2.0 * std::f32::consts::PI;
+    assert!(
+        (spin.angle - expected).abs() < 1.0e-2,
+        "angle {}",
+        spin.angle
+    );
+    assert_eq!(spin.rate, 1.0);
+}
+
+// A wind takes mass away exponentially, and accretion hands the accreted
+// body's mass and momentum to the body that absorbs it.
+#[test]
+fn mass_evolution_conserves_accreted_momentum() {
+    let body = |mass, x, vy| BodyState {
+        mass,
+        species: 0,
+        group: 0,
+        position: Position { x, y: 0.0 },
+        velocity: Velocity { x: 0.0, y: vy },
+    };
+
+    let mut windy = SimulationBuilder::new(vec![body(1.0, 0.0, 0.0)])
+        .timestep(Some(1.0e-3))
+        .mass_evolution(MassEvolution {
+            mass_threshold: 0.0,
+            wind_rate: 0.5,
+            accretion_radius: 0.0,
+        })
+        .threads(1)
+        .build();
+    windy.run(1000);
+    let mass = windy.bodies()[0].mass;
+    assert!((mass - (-0.5f32).exp()).abs() < 1.0e-4, "mass {}", mass);
+
+    let mut accreting = SimulationBuilder::new(vec![body(1.0, 0.0, 0.0), body(0.01, 0.05, 1.0)])
+        .timestep(Some(1.0e-3))
+        .mass_evolution(MassEvolution {
+            mass_threshold: 0.1,
+            wind_rate: 0.0,
+            accretion_radius: 0.1,
+        })
+        .threads(1)
+        .build();
+    accreting.run(1);
+    let bodies = accreting.bodies();
+    assert_eq!(bodies.len(), 1);
+    assert!(
+        (bodies[0].mass - 1.01).abs() < 1.0e-6,
+        "mass {}",
+        bodies[0].mass
+    );
+    let momentum = bodies[0].mass * bodies[0].velocity.y;
+    assert!((momentum - 0.01).abs() < 1.0e-6, "momentum {}", momentum);
+}
+
+// Two runs of the same bodies on several threads end in the same state.
+#[test]
+fn repeated_runs_do_not_diverge() {
+    let run = || {
+        let scenario = scenarios::figure_eight();
+        let mut sim = SimulationBuilder::new(scenario.bodies)
+            .gravity(Gravity {
+                g: scenario.gravity,
+                epsilon: 0.0,
+                softening: Softening::None,
+                exponent: 2.0,
+            })
+            .timestep(Some(scenario.dt))
+            .integrator(Integrator::Leapfrog)
+            .threads(4)
+            .build();
+        sim.run(1000);
+        sim.bodies()
+    };
+    let (first, second) = (run(), run());
+    let diff = StateDiff::between(&first, &second);
+    assert!(diff.within(0.0, 0.0, 0.0), "{}", diff);
+}